@@ -0,0 +1,306 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! RFC8617 Authenticated Received Chain header set generation.
+
+use std::io;
+
+use crate::encoders::encode::check_header_line;
+
+use super::{
+    canonicalize::{field_name, relaxed_field, split_fields, split_message},
+    signature::{body_hash, DkimSigner},
+};
+
+/// Result of validating the existing ARC chain, written in the `cv=` tag
+/// of the ARC-Seal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainValidation {
+    None,
+    Pass,
+    Fail,
+}
+
+impl ChainValidation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ChainValidation::None => "none",
+            ChainValidation::Pass => "pass",
+            ChainValidation::Fail => "fail",
+        }
+    }
+}
+
+const ARC_HEADERS: [&str; 3] = [
+    "ARC-Authentication-Results",
+    "ARC-Message-Signature",
+    "ARC-Seal",
+];
+
+impl<'x> DkimSigner<'x> {
+    /// Seal an assembled message, returning the ARC-Seal,
+    /// ARC-Message-Signature and ARC-Authentication-Results headers to
+    /// prepend to it. The instance number follows the highest one found
+    /// in the message, and the seal covers all prior ARC sets.
+    ///
+    /// `authentication_results` is the value of the new
+    /// ARC-Authentication-Results header without the instance tag, such as
+    /// `mx.example.org; spf=pass smtp.mailfrom=example.com`, which must not
+    /// contain line breaks. The chain validation result is ignored for the
+    /// first instance, which is always sealed with `cv=none`.
+    pub fn arc_seal(
+        &self,
+        message: &[u8],
+        authentication_results: &str,
+        chain_validation: ChainValidation,
+    ) -> io::Result<String> {
+        check_header_line(authentication_results)?;
+
+        // Collect prior ARC sets, sorted by instance.
+        let mut arc_fields = split_fields(split_message(message).0)
+            .into_iter()
+            .filter_map(|field| {
+                let name = field_name(field);
+                let order = ARC_HEADERS
+                    .iter()
+                    .position(|arc_name| name.eq_ignore_ascii_case(arc_name.as_bytes()))?;
+                Some((instance(field)?, order, field))
+            })
+            .collect::<Vec<_>>();
+        arc_fields.sort_by_key(|(instance, order, _)| (*instance, *order));
+        let instance = arc_fields.last().map_or(1, |(instance, _, _)| instance + 1);
+        let chain_validation = if instance == 1 {
+            ChainValidation::None
+        } else {
+            chain_validation
+        };
+
+        let results = format!(
+            "ARC-Authentication-Results: i={}; {}\r\n",
+            instance, authentication_results
+        );
+
+        // The message signature never covers ARC headers.
+        let mut signer = self.clone();
+        signer.headers.retain(|name| {
+            !ARC_HEADERS
+                .iter()
+                .any(|arc_name| name.eq_ignore_ascii_case(arc_name))
+        });
+        let signature = signer.sign_with_body_hash(
+            &format!("ARC-Message-Signature: i={};", instance),
+            message,
+            &body_hash(message, self.body_canonicalization),
+        )?;
+
+        let mut seal = format!(
+            "ARC-Seal: i={}; a={}; cv={}; d={}; s={};",
+            instance,
+            self.key.algorithm(),
+            chain_validation.as_str(),
+            self.domain,
+            self.selector
+        );
+        if let Some(timestamp) = self.timestamp {
+            seal.push_str(&format!(" t={};", timestamp));
+        }
+        seal.push_str("\r\n\tb=");
+
+        let mut data = Vec::new();
+        for (_, _, field) in &arc_fields {
+            relaxed_field(field, &mut data);
+        }
+        relaxed_field(results.as_bytes(), &mut data);
+        relaxed_field(signature.as_bytes(), &mut data);
+        relaxed_field(seal.as_bytes(), &mut data);
+        // The new ARC-Seal is hashed without its trailing CRLF.
+        data.truncate(data.len() - 2);
+        self.append_signature(&mut seal, &data)?;
+
+        Ok(seal + &signature + &results)
+    }
+}
+
+/// Returns the `i=` tag of an ARC header.
+fn instance(field: &[u8]) -> Option<u32> {
+    let value = &field[field.iter().position(|&ch| ch == b':')? + 1..];
+    std::str::from_utf8(value).ok()?.split(';').find_map(|tag| {
+        let (name, value) = tag.split_once('=')?;
+        if name.trim() == "i" {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        dkim::{
+            canonicalize::{field_name, relaxed_field, split_fields, split_message},
+            sha256::{sha256, Sha256},
+            signature::{body_hash, Canonicalization, DkimSigner, SigningKey},
+        },
+        encoders::base64::base64_encode,
+        MessageBuilder,
+    };
+
+    use super::ChainValidation;
+
+    struct TestKey(&'static [u8]);
+
+    impl SigningKey for TestKey {
+        fn algorithm(&self) -> &str {
+            "rsa-sha256"
+        }
+
+        fn sign(&self, digest: &[u8; 32]) -> io::Result<Vec<u8>> {
+            let mut hasher = Sha256::new();
+            hasher.update(self.0);
+            hasher.update(digest);
+            Ok(hasher.finalize().to_vec())
+        }
+    }
+
+    fn tag(field: &[u8], name: &str) -> String {
+        String::from_utf8_lossy(field)
+            .split_once(':')
+            .unwrap()
+            .1
+            .chars()
+            .filter(|ch| !ch.is_whitespace())
+            .collect::<String>()
+            .split(';')
+            .find_map(|tag| Some(tag.strip_prefix(name)?.strip_prefix('=')?.to_string()))
+            .unwrap_or_default()
+    }
+
+    fn signature(key: &TestKey, mut data: Vec<u8>, field: &[u8]) -> String {
+        let b_pos = field.windows(3).rposition(|w| w == b"\tb=").unwrap() + 3;
+        relaxed_field(&field[..b_pos], &mut data);
+        data.truncate(data.len() - 2);
+        let mut output = Vec::new();
+        base64_encode(&key.sign(&sha256(&data)).unwrap(), &mut output, true).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn arc_seal_chain() {
+        let key = TestKey(b"forwarder secret");
+        let signer = DkimSigner::new(TestKey(b"forwarder secret"), "example.org", "arc")
+            .headers(["From", "Subject", "ARC-Seal"])
+            .canonicalization(Canonicalization::Relaxed, Canonicalization::Simple);
+
+        let mut builder = MessageBuilder::new();
+        builder.from("john@example.com");
+        builder.subject("Forwarded");
+        builder.date(1651103113);
+        builder.text_body("Hello world");
+        let mut message = Vec::new();
+        builder.write_to(&mut message).unwrap();
+
+        let first = signer
+            .arc_seal(
+                &message,
+                "mx.example.org; spf=pass smtp.mailfrom=example.com",
+                ChainValidation::Pass,
+            )
+            .unwrap();
+        let mut sealed = first.as_bytes().to_vec();
+        sealed.extend_from_slice(&message);
+
+        let fields = split_fields(split_message(&sealed).0);
+        let names = fields[..3]
+            .iter()
+            .map(|field| String::from_utf8_lossy(field_name(field)).into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "ARC-Seal",
+                "ARC-Message-Signature",
+                "ARC-Authentication-Results"
+            ]
+        );
+        let (seal, ams, aar) = (fields[0], fields[1], fields[2]);
+        assert!(fields[..3].iter().all(|field| tag(field, "i") == "1"));
+        assert_eq!(tag(seal, "cv"), "none");
+        assert_eq!(tag(seal, "d"), "example.org");
+        assert_eq!(tag(seal, "h"), "");
+        assert_eq!(
+            aar,
+            b"ARC-Authentication-Results: i=1; mx.example.org; spf=pass smtp.mailfrom=example.com\r\n"
+        );
+
+        // The message signature covers the headers and body like DKIM,
+        // ignoring ARC headers.
+        assert_eq!(tag(ams, "h"), "From:Subject");
+        assert_eq!(tag(ams, "c"), "relaxed/simple");
+        assert_eq!(
+            tag(ams, "bh"),
+            body_hash(&message, Canonicalization::Simple)
+        );
+        let message_fields = split_fields(split_message(&message).0);
+        let mut data = Vec::new();
+        for name in [&b"From"[..], b"Subject"].iter() {
+            let field = message_fields
+                .iter()
+                .find(|field| field_name(field) == *name)
+                .unwrap();
+            relaxed_field(field, &mut data);
+        }
+        assert_eq!(tag(ams, "b"), signature(&key, data, ams));
+
+        // The seal covers the ARC set.
+        let mut data = Vec::new();
+        relaxed_field(aar, &mut data);
+        relaxed_field(ams, &mut data);
+        assert_eq!(tag(seal, "b"), signature(&key, data, seal));
+        assert_ne!(
+            tag(seal, "b"),
+            signature(&TestKey(b"other"), Vec::new(), seal)
+        );
+
+        // A second hop chains to the first set.
+        let second = signer
+            .arc_seal(&sealed, "mx2.example.net; arc=pass", ChainValidation::Pass)
+            .unwrap();
+        let fields = split_fields(second.as_bytes());
+        assert_eq!(fields.len(), 3);
+        assert!(fields.iter().all(|field| tag(field, "i") == "2"));
+        assert_eq!(tag(fields[0], "cv"), "pass");
+
+        let mut data = Vec::new();
+        for field in [aar, ams, seal, fields[2], fields[1]].iter() {
+            relaxed_field(field, &mut data);
+        }
+        assert_eq!(tag(fields[0], "b"), signature(&key, data, fields[0]));
+
+        // Results that would inject other headers are rejected.
+        for (results, expected) in [
+            (
+                "mx.example.org; spf=pass\r\nX-Injected: yes",
+                crate::Error::LineBreakInHeader,
+            ),
+            ("mx.example.org; spf=pass\0", crate::Error::NulInHeader),
+        ]
+        .iter()
+        {
+            let err = signer
+                .arc_seal(&message, results, ChainValidation::None)
+                .unwrap_err();
+            assert_eq!(crate::Error::from_io(&err), Some(expected));
+        }
+    }
+}
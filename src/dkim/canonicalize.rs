@@ -0,0 +1,222 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! RFC6376 header and body canonicalization algorithms.
+//!
+//! These functions take an assembled message, such as the output of
+//! `MessageBuilder::write_to`, and return the canonical form of its
+//! header or body section.
+
+/// Split a message into its header section (including the CRLF ending
+/// the last header) and its body.
+pub(crate) fn split_message(message: &[u8]) -> (&[u8], &[u8]) {
+    if message.starts_with(b"\r\n") {
+        return (&[], &message[2..]);
+    }
+    match message.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => (&message[..pos + 2], &message[pos + 4..]),
+        None => (message, &[]),
+    }
+}
+
+/// Strip any trailing empty lines from a body.
+fn trim_empty_lines(mut body: &[u8]) -> &[u8] {
+    while body.ends_with(b"\r\n\r\n") {
+        body = &body[..body.len() - 2];
+    }
+    if body == b"\r\n" {
+        &[]
+    } else {
+        body
+    }
+}
+
+/// Canonicalize the header section of a message using the "simple"
+/// algorithm, which leaves headers unchanged.
+pub fn simple_headers(message: &[u8]) -> Vec<u8> {
+    split_message(message).0.to_vec()
+}
+
+/// Canonicalize the header section of a message using the "relaxed"
+/// algorithm: header names are lowercased, values are unfolded, runs of
+/// whitespace are collapsed and whitespace around the colon is removed.
+pub fn relaxed_headers(message: &[u8]) -> Vec<u8> {
+    let headers = split_message(message).0;
+    let mut output = Vec::with_capacity(headers.len());
+    for field in split_fields(headers) {
+        relaxed_field(field, &mut output);
+    }
+    output
+}
+
+/// Split a header section into its fields, each including its
+/// continuation lines and trailing CRLF.
+pub(crate) fn split_fields(headers: &[u8]) -> Vec<&[u8]> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+
+    while start < headers.len() {
+        // Find the end of the header field, including continuation lines.
+        let mut end = start;
+        loop {
+            match headers[end..].windows(2).position(|w| w == b"\r\n") {
+                Some(pos) => {
+                    end += pos + 2;
+                    if !matches!(headers.get(end), Some(b' ' | b'\t')) {
+                        break;
+                    }
+                }
+                None => {
+                    end = headers.len();
+                    break;
+                }
+            }
+        }
+        fields.push(&headers[start..end]);
+        start = end;
+    }
+
+    fields
+}
+
+/// Return the name of a header field, without surrounding whitespace.
+pub(crate) fn field_name(field: &[u8]) -> &[u8] {
+    let name = match field.iter().position(|&ch| ch == b':') {
+        Some(pos) => &field[..pos],
+        None => field,
+    };
+    let start = name
+        .iter()
+        .position(|ch| !ch.is_ascii_whitespace())
+        .unwrap_or(name.len());
+    let end = name
+        .iter()
+        .rposition(|ch| !ch.is_ascii_whitespace())
+        .map_or(start, |pos| pos + 1);
+    &name[start..end]
+}
+
+/// Canonicalize a single header field using the "relaxed" algorithm,
+/// appending it to `output` followed by a CRLF.
+pub(crate) fn relaxed_field(field: &[u8], output: &mut Vec<u8>) {
+    let (name, value) = match field.iter().position(|&ch| ch == b':') {
+        Some(pos) => (&field[..pos], &field[pos + 1..]),
+        None => (field, &[][..]),
+    };
+    output.extend(
+        name.iter()
+            .filter(|ch| !ch.is_ascii_whitespace())
+            .map(|ch| ch.to_ascii_lowercase()),
+    );
+    output.push(b':');
+
+    let mut pending_space = false;
+    let mut is_empty = true;
+    for &ch in value {
+        match ch {
+            b'\r' | b'\n' => (),
+            b' ' | b'\t' => pending_space = true,
+            _ => {
+                if pending_space && !is_empty {
+                    output.push(b' ');
+                }
+                pending_space = false;
+                is_empty = false;
+                output.push(ch);
+            }
+        }
+    }
+    output.extend_from_slice(b"\r\n");
+}
+
+/// Canonicalize the body of a message using the "simple" algorithm,
+/// which only removes trailing empty lines. An empty body becomes a
+/// single CRLF.
+pub fn simple_body(message: &[u8]) -> Vec<u8> {
+    let body = trim_empty_lines(split_message(message).1);
+    let mut output = body.to_vec();
+    if !output.ends_with(b"\r\n") {
+        output.extend_from_slice(b"\r\n");
+    }
+    output
+}
+
+/// Canonicalize the body of a message using the "relaxed" algorithm:
+/// runs of whitespace are collapsed, trailing whitespace is removed from
+/// every line and trailing empty lines are removed.
+pub fn relaxed_body(message: &[u8]) -> Vec<u8> {
+    let body = split_message(message).1;
+    let mut output = Vec::with_capacity(body.len());
+    let mut pending_space = false;
+
+    for (pos, &ch) in body.iter().enumerate() {
+        match ch {
+            b' ' | b'\t' => pending_space = true,
+            // Only the CR of a line break is dropped, bare CRs are
+            // part of the line contents.
+            b'\r' if body.get(pos + 1) == Some(&b'\n') => (),
+            b'\n' => {
+                pending_space = false;
+                output.extend_from_slice(b"\r\n");
+            }
+            _ => {
+                if pending_space {
+                    output.push(b' ');
+                    pending_space = false;
+                }
+                output.push(ch);
+            }
+        }
+    }
+
+    let len = trim_empty_lines(&output).len();
+    output.truncate(len);
+    if !output.is_empty() && !output.ends_with(b"\r\n") {
+        output.extend_from_slice(b"\r\n");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{relaxed_body, relaxed_headers, simple_body, simple_headers};
+
+    // RFC6376 section 3.4.5
+    const MESSAGE: &[u8] = b"A: X\r\nB : Y\t\r\n\tZ  \r\n\r\n C \r\nD \t E\r\n\r\n\r\n";
+
+    #[test]
+    fn canonicalize_rfc6376_example() {
+        assert_eq!(relaxed_headers(MESSAGE), b"a:X\r\nb:Y Z\r\n");
+        assert_eq!(relaxed_body(MESSAGE), b" C\r\nD E\r\n");
+        assert_eq!(simple_headers(MESSAGE), b"A: X\r\nB : Y\t\r\n\tZ  \r\n");
+        assert_eq!(simple_body(MESSAGE), b" C \r\nD \t E\r\n");
+        assert_eq!(
+            relaxed_headers(b"Subject:  Re:  Hello \r\n\r\n"),
+            b"subject:Re: Hello\r\n"
+        );
+    }
+
+    #[test]
+    fn canonicalize_empty_body() {
+        let message = b"Subject: Hello\r\n\r\n";
+        assert_eq!(simple_body(message), b"\r\n");
+        assert_eq!(relaxed_body(message), b"");
+        assert_eq!(simple_body(b"Subject: Hello\r\n\r\n\r\n\r\n"), b"\r\n");
+        assert_eq!(relaxed_body(b"Subject: Hello\r\n\r\n \r\n\t\r\n"), b"");
+    }
+
+    #[test]
+    fn canonicalize_bare_cr() {
+        let message = b"Subject: Hello\r\n\r\nA\rB \r C\r\n";
+        assert_eq!(relaxed_body(message), b"A\rB \r C\r\n");
+        assert_eq!(simple_body(message), b"A\rB \r C\r\n");
+    }
+}
@@ -9,75 +9,125 @@
  * except according to those terms.
  */
 
+#[cfg(feature = "std")]
 use std::io::{self, Write};
 
 const CHARPAD: u8 = b'=';
+#[cfg(feature = "std")]
+const LINE_LEN: usize = 76;
 
+/// Base64 encodes a block of up to three bytes into four characters,
+/// padding the output when the block is shorter than three bytes.
+pub fn base64_encode_block(input: &[u8]) -> [u8; 4] {
+    match *input {
+        [t1, t2, t3, ..] => [
+            E0[t1 as usize],
+            E1[(((t1 & 0x03) << 4) | ((t2 >> 4) & 0x0F)) as usize],
+            E1[(((t2 & 0x0F) << 2) | ((t3 >> 6) & 0x03)) as usize],
+            E2[t3 as usize],
+        ],
+        [t1, t2] => [
+            E0[t1 as usize],
+            E1[(((t1 & 0x03) << 4) | ((t2 >> 4) & 0x0F)) as usize],
+            E2[((t2 & 0x0F) << 2) as usize],
+            CHARPAD,
+        ],
+        [t1] => [
+            E0[t1 as usize],
+            E1[((t1 & 0x03) << 4) as usize],
+            CHARPAD,
+            CHARPAD,
+        ],
+        [] => [CHARPAD; 4],
+    }
+}
+
+/// Base64 encodes the input, writing it in line-sized chunks directly to
+/// the output so that memory usage does not depend on the input size.
+/// Lines are terminated with CRLF every 76 characters unless `is_inline`
+/// is set.
+#[cfg(feature = "std")]
 pub fn base64_encode(input: &[u8], mut output: impl Write, is_inline: bool) -> io::Result<usize> {
-    let mut i = 0;
-    let mut t1;
-    let mut t2;
-    let mut t3;
+    let mut buf = [0u8; LINE_LEN + 2];
+    let mut buf_len = 0;
     let mut bytes_written = 0;
 
-    if input.len() > 2 {
-        while i < input.len() - 2 {
-            t1 = input[i];
-            t2 = input[i + 1];
-            t3 = input[i + 2];
-
-            output.write_all(&[
-                E0[t1 as usize],
-                E1[(((t1 & 0x03) << 4) | ((t2 >> 4) & 0x0F)) as usize],
-                E1[(((t2 & 0x0F) << 2) | ((t3 >> 6) & 0x03)) as usize],
-                E2[t3 as usize],
-            ])?;
-
-            bytes_written += 4;
+    for chunk in input.chunks(3) {
+        buf[buf_len..buf_len + 4].copy_from_slice(&base64_encode_block(chunk));
+        buf_len += 4;
+        bytes_written += 4;
 
-            if !is_inline && bytes_written % 19 == 0 {
-                output.write_all(b"\r\n")?;
+        if buf_len == LINE_LEN {
+            if !is_inline {
+                buf[LINE_LEN..].copy_from_slice(b"\r\n");
+                buf_len += 2;
             }
-
-            i += 3;
+            output.write_all(&buf[..buf_len])?;
+            buf_len = 0;
         }
     }
 
-    let remaining = input.len() - i;
-    if remaining > 0 {
-        t1 = input[i];
-        if remaining == 1 {
-            output.write_all(&[
-                E0[t1 as usize],
-                E1[((t1 & 0x03) << 4) as usize],
-                CHARPAD,
-                CHARPAD,
-            ])?;
-        } else {
-            t2 = input[i + 1];
-            output.write_all(&[
-                E0[t1 as usize],
-                E1[(((t1 & 0x03) << 4) | ((t2 >> 4) & 0x0F)) as usize],
-                E2[((t2 & 0x0F) << 2) as usize],
-                CHARPAD,
-            ])?;
+    if buf_len > 0 {
+        if !is_inline {
+            buf[buf_len..buf_len + 2].copy_from_slice(b"\r\n");
+            buf_len += 2;
         }
+        output.write_all(&buf[..buf_len])?;
+    }
 
-        bytes_written += 4;
+    Ok(bytes_written)
+}
+
+/// Base64 encodes everything read from an async reader, writing CRLF
+/// terminated lines as the input arrives so that it is never fully buffered.
+/// The output is identical to `base64_encode` with `is_inline` unset.
+#[cfg(all(feature = "std", feature = "tokio"))]
+pub async fn base64_encode_async(
+    mut input: impl tokio::io::AsyncRead + Unpin,
+    mut output: impl tokio::io::AsyncWrite + Unpin,
+) -> io::Result<usize> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-        if !is_inline && bytes_written % 19 == 0 {
-            output.write_all(b"\r\n")?;
+    // Read whole lines at a time, so that every chunk but the last one is
+    // encoded without padding.
+    let mut buf = vec![0u8; LINE_LEN / 4 * 3 * 64];
+    let mut buf_len = 0;
+    let mut encoded = Vec::with_capacity((LINE_LEN + 2) * 64);
+    let mut bytes_written = 0;
+
+    loop {
+        let bytes_read = input.read(&mut buf[buf_len..]).await?;
+        buf_len += bytes_read;
+        if buf_len == buf.len() || (bytes_read == 0 && buf_len > 0) {
+            encoded.clear();
+            bytes_written += base64_encode(&buf[..buf_len], &mut encoded, false)?;
+            output.write_all(&encoded).await?;
+            buf_len = 0;
+        }
+        if bytes_read == 0 {
+            return Ok(bytes_written);
         }
     }
+}
 
-    if !is_inline && bytes_written % 19 != 0 {
-        output.write_all(b"\r\n")?;
+/// Base64 encodes the input using the URL and filename safe alphabet
+/// (RFC4648 section 5), without padding or line breaks.
+#[cfg(feature = "std")]
+pub fn base64url_encode(input: &[u8]) -> String {
+    let mut output = String::with_capacity((input.len() * 4).div_ceil(3));
+    for chunk in input.chunks(3) {
+        for &ch in &base64_encode_block(chunk)[..chunk.len() + 1] {
+            output.push(match ch {
+                b'+' => '-',
+                b'/' => '_',
+                ch => ch as char,
+            });
+        }
     }
-
-    Ok(bytes_written)
+    output
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
 
     #[test]
@@ -114,6 +164,65 @@ mod tests {
             assert_eq!(std::str::from_utf8(&output).unwrap(), expected_result);
         }
     }
+
+    #[test]
+    fn encode_base64url() {
+        for (input, expected_result) in [
+            ("", ""),
+            ("A", "QQ"),
+            ("Ye", "WWU"),
+            ("Test", "VGVzdA"),
+            ("\u{3ff}\u{fbff}?>", "z7_vr78_Pg"),
+        ] {
+            assert_eq!(super::base64url_encode(input.as_bytes()), expected_result);
+        }
+    }
+
+    #[test]
+    fn encode_base64_chunked() {
+        struct LineWriter {
+            output: Vec<u8>,
+            max_write: usize,
+        }
+
+        impl std::io::Write for LineWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.max_write = std::cmp::max(self.max_write, buf.len());
+                self.output.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        for len in [1024usize * 1024, 1024 * 1024 + 1, 1024 * 1024 + 2] {
+            let input = (0..len).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+            let mut writer = LineWriter {
+                output: Vec::new(),
+                max_write: 0,
+            };
+            let bytes_written = super::base64_encode(&input, &mut writer, false).unwrap();
+
+            assert_eq!(writer.max_write, 78);
+            assert_eq!(bytes_written, len.div_ceil(3) * 4);
+
+            let output = std::str::from_utf8(&writer.output).unwrap();
+            assert!(output.ends_with("\r\n"));
+            let lines = output.split_terminator("\r\n").collect::<Vec<_>>();
+            assert_eq!(lines.len(), bytes_written.div_ceil(76));
+            assert!(lines[..lines.len() - 1].iter().all(|line| line.len() == 76));
+            assert_eq!(
+                lines.last().unwrap().matches('=').count(),
+                (3 - len % 3) % 3
+            );
+
+            let mut single_line = Vec::new();
+            super::base64_encode(&input, &mut single_line, true).unwrap();
+            assert_eq!(single_line, lines.concat().into_bytes());
+        }
+    }
 }
 
 /*
@@ -128,6 +237,7 @@ mod tests {
  *
  */
 
+#[allow(clippy::byte_char_slices)]
 pub static E0: &[u8] = &[
     b'A', b'A', b'A', b'A', b'B', b'B', b'B', b'B', b'C', b'C', b'C', b'C', b'D', b'D', b'D', b'D',
     b'E', b'E', b'E', b'E', b'F', b'F', b'F', b'F', b'G', b'G', b'G', b'G', b'H', b'H', b'H', b'H',
@@ -147,6 +257,7 @@ pub static E0: &[u8] = &[
     b'8', b'8', b'8', b'8', b'9', b'9', b'9', b'9', b'+', b'+', b'+', b'+', b'/', b'/', b'/', b'/',
 ];
 
+#[allow(clippy::byte_char_slices)]
 pub static E1: &[u8] = &[
     b'A', b'B', b'C', b'D', b'E', b'F', b'G', b'H', b'I', b'J', b'K', b'L', b'M', b'N', b'O', b'P',
     b'Q', b'R', b'S', b'T', b'U', b'V', b'W', b'X', b'Y', b'Z', b'a', b'b', b'c', b'd', b'e', b'f',
@@ -166,6 +277,7 @@ pub static E1: &[u8] = &[
     b'w', b'x', b'y', b'z', b'0', b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'+', b'/',
 ];
 
+#[allow(clippy::byte_char_slices)]
 pub static E2: &[u8] = &[
     b'A', b'B', b'C', b'D', b'E', b'F', b'G', b'H', b'I', b'J', b'K', b'L', b'M', b'N', b'O', b'P',
     b'Q', b'R', b'S', b'T', b'U', b'V', b'W', b'X', b'Y', b'Z', b'a', b'b', b'c', b'd', b'e', b'f',
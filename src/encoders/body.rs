@@ -0,0 +1,64 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+use super::{base64::base64_encode, quoted_printable::quoted_printable_encode};
+
+/// Content-Transfer-Encoding used to write the body of a MIME part instead
+/// of the automatically detected one, see `MimePart::encoder`.
+pub trait BodyEncoder: Send + Sync {
+    /// Value written in the Content-Transfer-Encoding header, which must be
+    /// a valid token.
+    fn name(&self) -> &str;
+
+    /// Encode the contents, which must produce CRLF terminated lines of at
+    /// most 998 octets.
+    fn encode(&self, input: &[u8], output: &mut dyn Write) -> io::Result<()>;
+}
+
+impl fmt::Debug for dyn BodyEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BodyEncoder").field(&self.name()).finish()
+    }
+}
+
+/// Base64 body encoder.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Base64Encoder;
+
+impl BodyEncoder for Base64Encoder {
+    fn name(&self) -> &str {
+        "base64"
+    }
+
+    fn encode(&self, input: &[u8], output: &mut dyn Write) -> io::Result<()> {
+        base64_encode(input, output, false).map(|_| ())
+    }
+}
+
+/// Quoted-printable body encoder. Line breaks are written as CRLF rather
+/// than encoded, so it is meant for text contents.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuotedPrintableEncoder;
+
+impl BodyEncoder for QuotedPrintableEncoder {
+    fn name(&self) -> &str {
+        "quoted-printable"
+    }
+
+    fn encode(&self, input: &[u8], output: &mut dyn Write) -> io::Result<()> {
+        quoted_printable_encode(input, output, false, true).map(|_| ())
+    }
+}
@@ -9,10 +9,13 @@
  * except according to those terms.
  */
 
+#[cfg(feature = "std")]
 use std::io::{self, Write};
 
-use super::{base64::base64_encode, quoted_printable::quoted_printable_encode};
+#[cfg(feature = "std")]
+use super::base64::base64_encode;
 
+#[derive(Debug)]
 pub enum EncodingType {
     Base64,
     QuotedPrintable(bool),
@@ -20,17 +23,27 @@ pub enum EncodingType {
 }
 
 pub fn get_encoding_type(input: &[u8], is_inline: bool, is_body: bool) -> EncodingType {
-    let base64_len = (input.len() * 4 / 3 + 3) & !3;
-    let mut qp_len = if !is_inline { input.len() / 76 } else { 0 };
+    let mut base64_len = (input.len() * 4 / 3 + 3) & !3;
+    if !is_inline {
+        // Base64 lines are terminated with CRLF every 76 characters.
+        base64_len += base64_len.div_ceil(76) * 2;
+    }
+    let mut qp_len = 0;
+    // Encoded length at the start of the current line, used to count the
+    // soft line breaks quoted-printable needs once bare LFs become CRLF.
+    let mut qp_line_start = 0;
     let mut is_ascii = true;
     let mut needs_encoding = false;
     let mut line_len = 0;
     let mut prev_ch = 0;
 
-    for (pos, &ch) in input.iter().enumerate() {
+    for &ch in input {
         line_len += 1;
 
-        if ch >= 127 {
+        if ch == 0 && !is_inline {
+            // NUL bytes are not allowed in 7bit or quoted-printable bodies.
+            return EncodingType::Base64;
+        } else if ch >= 127 {
             qp_len += 3;
             if !needs_encoding {
                 needs_encoding = true;
@@ -44,7 +57,8 @@ pub fn get_encoding_type(input: &[u8], is_inline: bool, is_body: bool) -> Encodi
         {
             qp_len += 3;
         } else if ch == b'\n' {
-            if !needs_encoding && line_len > 997 {
+            // Lines cannot exceed 998 octets, excluding the CRLF.
+            if !needs_encoding && line_len - 1 - (prev_ch == b'\r') as usize > 998 {
                 needs_encoding = true;
             }
             if is_body {
@@ -52,6 +66,8 @@ pub fn get_encoding_type(input: &[u8], is_inline: bool, is_body: bool) -> Encodi
                     qp_len += 1;
                 }
                 qp_len += 1;
+                qp_len += (qp_len - qp_line_start) / 76 * 3;
+                qp_line_start = qp_len;
             } else {
                 if !needs_encoding && prev_ch != b'\r' {
                     needs_encoding = true;
@@ -66,6 +82,13 @@ pub fn get_encoding_type(input: &[u8], is_inline: bool, is_body: bool) -> Encodi
         prev_ch = ch;
     }
 
+    if line_len > 998 {
+        needs_encoding = true;
+    }
+    if !is_inline {
+        qp_len += (qp_len - qp_line_start) / 76 * 3;
+    }
+
     if !needs_encoding {
         EncodingType::None
     } else if qp_len < base64_len {
@@ -75,27 +98,89 @@ pub fn get_encoding_type(input: &[u8], is_inline: bool, is_body: bool) -> Encodi
     }
 }
 
-pub fn rfc2047_encode(input: &str, mut output: impl Write) -> io::Result<usize> {
+/// Encodes a MIME parameter value using the RFC2231 extended notation
+/// (`utf-8''%XX...`), returning the number of bytes written.
+#[cfg(feature = "std")]
+pub fn rfc2231_encode(input: &str, mut output: impl Write) -> io::Result<usize> {
+    let mut bytes_written = 7;
+    output.write_all(b"utf-8''")?;
+    for &ch in input.as_bytes() {
+        if ch.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&ch) {
+            output.write_all(&[ch])?;
+            bytes_written += 1;
+        } else {
+            output.write_all(format!("%{:02X}", ch).as_bytes())?;
+            bytes_written += 3;
+        }
+    }
+    Ok(bytes_written)
+}
+
+/// Returns an error if a header value contains a NUL byte, which are
+/// not allowed anywhere in a message header.
+#[cfg(feature = "std")]
+pub fn check_header_value(value: &str) -> io::Result<()> {
+    if !value.as_bytes().contains(&0) {
+        Ok(())
+    } else {
+        Err(crate::Error::NulInHeader.into())
+    }
+}
+
+/// Returns an error if a value that is written without encoding or folding,
+/// such as a MIME type or a report field, contains a NUL, CR or LF character.
+#[cfg(feature = "std")]
+pub fn check_header_line(value: &str) -> io::Result<()> {
+    check_header_value(value)?;
+    if !value.contains(['\r', '\n']) {
+        Ok(())
+    } else {
+        Err(crate::Error::LineBreakInHeader.into())
+    }
+}
+
+#[cfg(feature = "std")]
+pub fn rfc2047_encode(input: &str, output: impl Write) -> io::Result<usize> {
+    rfc2047_encode_with(input, output, false)
+}
+
+/// Encodes a phrase as `rfc2047_encode` does, writing the charset label of
+/// encoded-words in uppercase when `uppercase_charset` is set.
+#[cfg(feature = "std")]
+pub(crate) fn rfc2047_encode_with(
+    input: &str,
+    mut output: impl Write,
+    uppercase_charset: bool,
+) -> io::Result<usize> {
+    check_header_value(input)?;
     Ok(match get_encoding_type(input.as_bytes(), true, false) {
         EncodingType::Base64 => {
-            output.write_all(b"\"=?utf-8?B?")?;
-            let bytes_written = base64_encode(input.as_bytes(), &mut output, true)? + 14;
-            output.write_all(b"?=\"")?;
+            // Encoded-words are never quoted, as they are not decoded
+            // inside quoted-strings (RFC2047 section 5).
+            output.write_all(if uppercase_charset {
+                b"=?UTF-8?B?"
+            } else {
+                b"=?utf-8?B?"
+            })?;
+            let bytes_written = base64_encode(input.as_bytes(), &mut output, true)? + 12;
+            output.write_all(b"?=")?;
             bytes_written
         }
         EncodingType::QuotedPrintable(is_ascii) => {
-            if !is_ascii {
-                output.write_all(b"\"=?utf-8?Q?")?;
-            } else {
-                output.write_all(b"\"=?us-ascii?Q?")?;
-            }
+            output.write_all(match (is_ascii, uppercase_charset) {
+                (false, false) => b"=?utf-8?Q?",
+                (false, true) => b"=?UTF-8?Q?",
+                (true, false) => b"=?us-ascii?Q?",
+                (true, true) => b"=?US-ASCII?Q?",
+            })?;
             let bytes_written =
-                quoted_printable_encode(input.as_bytes(), &mut output, true, false)?
-                    + if is_ascii { 19 } else { 14 };
-            output.write_all(b"?=\"")?;
+                phrase_q_encode(input.as_bytes(), &mut output)? + if is_ascii { 17 } else { 12 };
+            output.write_all(b"?=")?;
             bytes_written
         }
         EncodingType::None => {
+            // Always write a quoted-string, so that specials such as
+            // `,`, `;`, `:`, `.` or `@` cannot break address parsing.
             let mut bytes_written = 2;
             output.write_all(b"\"")?;
             for &ch in input.as_bytes() {
@@ -113,3 +198,85 @@ pub fn rfc2047_encode(input: &str, mut output: impl Write) -> io::Result<usize>
         }
     })
 }
+
+/// Writes the text of a `Q` encoded-word that appears in a phrase, where
+/// only letters, digits and `!*+-/` may be left unencoded (RFC2047 section 5).
+#[cfg(feature = "std")]
+fn phrase_q_encode(input: &[u8], mut output: impl Write) -> io::Result<usize> {
+    let mut bytes_written = 0;
+    for &ch in input {
+        if ch.is_ascii_alphanumeric() || b"!*+-/".contains(&ch) {
+            output.write_all(&[ch])?;
+            bytes_written += 1;
+        } else if ch == b' ' {
+            output.write_all(b"_")?;
+            bytes_written += 1;
+        } else {
+            output.write_all(format!("={:02X}", ch).as_bytes())?;
+            bytes_written += 3;
+        }
+    }
+    Ok(bytes_written)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{get_encoding_type, rfc2047_encode, EncodingType};
+    use crate::encoders::{base64::base64_encode, quoted_printable::quoted_printable_encode_with};
+
+    #[test]
+    fn encoding_type_with_crlf_expansion() {
+        // Bare LFs are written as CRLF, which makes quoted-printable longer
+        // than the input but still shorter than base64 with its line breaks.
+        let mut body = "ab\n".repeat(1000);
+        body.push('é');
+        let mut qp = Vec::new();
+        quoted_printable_encode_with(body.as_bytes(), &mut qp, false, true, false).unwrap();
+        let mut base64 = Vec::new();
+        base64_encode(body.as_bytes(), &mut base64, false).unwrap();
+        assert!(qp.len() < base64.len());
+        assert!(matches!(
+            get_encoding_type(body.as_bytes(), false, true),
+            EncodingType::QuotedPrintable(false)
+        ));
+
+        // Outside of bodies the LFs are encoded, so base64 is shorter.
+        assert!(matches!(
+            get_encoding_type(body.as_bytes(), false, false),
+            EncodingType::Base64
+        ));
+
+        // Lines are measured without the CR added to bare LFs.
+        let line = format!("{}\n", "a".repeat(998));
+        assert!(matches!(
+            get_encoding_type(line.as_bytes(), false, true),
+            EncodingType::None
+        ));
+        let line = format!("{}\n", "a".repeat(999));
+        assert!(!matches!(
+            get_encoding_type(line.as_bytes(), false, true),
+            EncodingType::None
+        ));
+    }
+
+    #[test]
+    fn encode_phrase() {
+        for (input, expected) in [
+            ("John Doe", "\"John Doe\""),
+            ("Doe, \"JD\" John", "\"Doe, \\\"JD\\\" John\""),
+            ("Jöhn Doe, Jr.", "=?utf-8?Q?J=C3=B6hn_Doe=2C_Jr=2E?="),
+            (
+                "Jöhn <evil@example.com>",
+                "=?utf-8?Q?J=C3=B6hn_=3Cevil=40example=2Ecom=3E?=",
+            ),
+            ("Jöhn Dœ", "=?utf-8?B?SsO2aG4gRMWT?="),
+        ]
+        .iter()
+        {
+            let mut output = Vec::new();
+            let bytes_written = rfc2047_encode(input, &mut output).unwrap();
+            assert_eq!(std::str::from_utf8(&output).unwrap(), *expected);
+            assert_eq!(bytes_written, expected.len(), "{:?}", input);
+        }
+    }
+}
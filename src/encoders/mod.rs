@@ -10,5 +10,10 @@
  */
 
 pub mod base64;
+#[cfg(feature = "std")]
+pub mod body;
 pub mod encode;
+#[cfg(feature = "std")]
 pub mod quoted_printable;
+#[cfg(feature = "std")]
+pub mod utf7;
@@ -12,10 +12,22 @@
 use std::io::{self, Write};
 
 pub fn quoted_printable_encode(
+    input: &[u8],
+    output: impl Write,
+    is_inline: bool,
+    is_body: bool,
+) -> io::Result<usize> {
+    quoted_printable_encode_with(input, output, is_inline, is_body, false)
+}
+
+/// Same as `quoted_printable_encode`, optionally encoding the first character
+/// of lines starting with `From ` so they are not mangled by mbox storage.
+pub fn quoted_printable_encode_with(
     input: &[u8],
     mut output: impl Write,
     is_inline: bool,
     is_body: bool,
+    escape_from: bool,
 ) -> io::Result<usize> {
     let mut bytes_written = 0;
     if !is_inline {
@@ -44,8 +56,17 @@ pub fn quoted_printable_encode(
                     output.write_all(b"=\r\n")?;
                     bytes_written = 0;
                 }
-                output.write_all(&[ch])?;
-                bytes_written += 1;
+                if escape_from
+                    && ch == b'F'
+                    && (bytes_written == 0 || pos == 0 || input[pos - 1] == b'\n')
+                    && input[pos..].starts_with(b"From ")
+                {
+                    output.write_all(b"=46")?;
+                    bytes_written += 3;
+                } else {
+                    output.write_all(&[ch])?;
+                    bytes_written += 1;
+                }
             }
 
             if is_body {
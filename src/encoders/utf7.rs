@@ -0,0 +1,160 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! RFC2152 UTF-7 and RFC3501 modified UTF-7 encoding, used by some
+//! legacy systems that cannot handle 8-bit text.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes a string as UTF-7. When `modified` is set, the IMAP modified UTF-7
+/// variant is used, which shifts with `&` and uses `,` instead of `/`.
+pub fn utf7_encode(input: &str, modified: bool) -> String {
+    let (shift, last) = if modified { (b'&', b',') } else { (b'+', b'/') };
+    let mut output = String::with_capacity(input.len());
+    let mut utf16 = Vec::new();
+
+    for ch in input.chars() {
+        if is_direct(ch, modified) {
+            if !utf16.is_empty() {
+                encode_utf16(&utf16, shift, last, &mut output);
+                utf16.clear();
+            }
+            output.push(ch);
+            if ch == shift as char {
+                output.push('-');
+            }
+        } else {
+            let mut buf = [0u16; 2];
+            utf16.extend_from_slice(ch.encode_utf16(&mut buf));
+        }
+    }
+    if !utf16.is_empty() {
+        encode_utf16(&utf16, shift, last, &mut output);
+    }
+
+    output
+}
+
+/// Decodes a UTF-7 or modified UTF-7 string, returning `None` if the input
+/// is not valid.
+pub fn utf7_decode(input: &str, modified: bool) -> Option<String> {
+    let (shift, last) = if modified { (b'&', b',') } else { (b'+', b'/') };
+    let mut output = String::with_capacity(input.len());
+    let mut bytes = input.bytes().peekable();
+
+    while let Some(ch) = bytes.next() {
+        if ch != shift {
+            if !ch.is_ascii() {
+                return None;
+            }
+            output.push(ch as char);
+            continue;
+        } else if bytes.peek() == Some(&b'-') {
+            bytes.next();
+            output.push(shift as char);
+            continue;
+        }
+
+        let mut utf16 = Vec::new();
+        let mut bits = 0u32;
+        let mut bit_count = 0;
+        while let Some(&ch) = bytes.peek() {
+            let value = match ch {
+                b'A'..=b'Z' => ch - b'A',
+                b'a'..=b'z' => ch - b'a' + 26,
+                b'0'..=b'9' => ch - b'0' + 52,
+                b'+' if !modified => 62,
+                _ if ch == last => 63,
+                _ => break,
+            };
+            bytes.next();
+            bits = (bits << 6) | value as u32;
+            bit_count += 6;
+            if bit_count >= 16 {
+                bit_count -= 16;
+                utf16.push((bits >> bit_count) as u16);
+                bits &= (1 << bit_count) - 1;
+            }
+        }
+        if bytes.peek() == Some(&b'-') {
+            bytes.next();
+        } else if modified {
+            return None;
+        }
+        output.push_str(&String::from_utf16(&utf16).ok()?);
+    }
+
+    Some(output)
+}
+
+fn is_direct(ch: char, modified: bool) -> bool {
+    if modified {
+        (' '..='~').contains(&ch)
+    } else {
+        ch.is_ascii_alphanumeric() || " \t\r\n'(),-./:?!\"#$%&*;<=>@[]^_`{|}+".contains(ch)
+    }
+}
+
+fn encode_utf16(utf16: &[u16], shift: u8, last: u8, output: &mut String) {
+    let encode = |value: u32| match ALPHABET[value as usize] {
+        b'/' => last as char,
+        ch => ch as char,
+    };
+
+    output.push(shift as char);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for &unit in utf16 {
+        bits = (bits << 16) | unit as u32;
+        bit_count += 16;
+        while bit_count >= 6 {
+            bit_count -= 6;
+            output.push(encode((bits >> bit_count) & 0x3f));
+        }
+        bits &= (1 << bit_count) - 1;
+    }
+    if bit_count > 0 {
+        output.push(encode((bits << (6 - bit_count)) & 0x3f));
+    }
+    output.push('-');
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn encode_utf7() {
+        for (input, expected_result, modified) in [
+            ("Hello, world!", "Hello, world!", false),
+            ("1 + 1 = 2", "1 +- 1 = 2", false),
+            ("Hi Mom -☺-!", "Hi Mom -+Jjo--!", false),
+            ("日本語", "+ZeVnLIqe-", false),
+            ("A≢Α.", "A+ImIDkQ-.", false),
+            ("Entwürfe", "Entw&APw-rfe", true),
+            ("Tom & Jerry", "Tom &- Jerry", true),
+            (
+                "~peter/mail/台北/日本語",
+                "~peter/mail/&U,BTFw-/&ZeVnLIqe-",
+                true,
+            ),
+            ("😀 emoji", "&2D3eAA- emoji", true),
+        ] {
+            let result = super::utf7_encode(input, modified);
+            assert_eq!(result, expected_result, "{}", input);
+            assert_eq!(
+                super::utf7_decode(&result, modified).as_deref(),
+                Some(input),
+                "{}",
+                input
+            );
+        }
+    }
+}
@@ -0,0 +1,108 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{fmt, io};
+
+/// Errors caused by a message that cannot be serialized.
+///
+/// These are returned wrapped in an `io::Error` of kind `InvalidInput`,
+/// use [`Error::from_io`] to recover them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// A multipart Content-Type header is neither a `ContentType` nor a `Raw` value.
+    UnsupportedContentType,
+    /// A multipart MIME part has no body parts.
+    EmptyMultipart,
+    /// A raw multipart Content-Type header has a malformed boundary parameter.
+    InvalidBoundary,
+    /// Raw headers are not CRLF terminated or contain empty lines.
+    InvalidRawHeaders,
+    /// A List-ID header is not a valid `list-label.domain` identifier.
+    InvalidListId(String),
+    /// A header line is longer than 998 octets and cannot be folded.
+    HeaderTooLong,
+    /// A header value contains a NUL byte.
+    NulInHeader,
+    /// A header value that is written as is contains a CR or LF character.
+    LineBreakInHeader,
+    /// An address list or group contains another list or group.
+    NestedAddress,
+    /// A message body was set more than once while in strict mode.
+    BodyOverwritten,
+    /// A raw MIME body was set together with other bodies or attachments.
+    ConflictingBody,
+    /// A message with async attachments was written synchronously, signed,
+    /// or its async parts could not be found in the output.
+    AsyncAttachment,
+    /// A remote inline image URL returned a response that is not an image.
+    NotAnImage(String),
+    /// A remote inline image is larger than `MAX_IMAGE_SIZE`.
+    ImageTooLarge,
+    /// The name of a custom body encoder is not a valid
+    /// Content-Transfer-Encoding token.
+    InvalidEncoderName(String),
+}
+
+impl Error {
+    /// Returns the `Error` wrapped by an `io::Error`, if any.
+    pub fn from_io(err: &io::Error) -> Option<&Error> {
+        err.get_ref().and_then(|err| err.downcast_ref())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnsupportedContentType => f.write_str("Unsupported Content-Type header value."),
+            Error::EmptyMultipart => f.write_str("Multipart MIME part has no body parts."),
+            Error::InvalidBoundary => {
+                f.write_str("Invalid boundary parameter in Content-Type header.")
+            }
+            Error::InvalidRawHeaders => {
+                f.write_str("Raw headers must be CRLF terminated and cannot contain empty lines.")
+            }
+            Error::InvalidListId(id) => write!(f, "Invalid List-ID {:?}.", id),
+            Error::HeaderTooLong => {
+                f.write_str("Header line exceeds 998 octets and cannot be folded.")
+            }
+            Error::NulInHeader => f.write_str("Header values cannot contain NUL bytes."),
+            Error::LineBreakInHeader => {
+                f.write_str("Header values written as is cannot contain line breaks.")
+            }
+            Error::NestedAddress => {
+                f.write_str("Address lists and groups cannot contain other lists or groups.")
+            }
+            Error::BodyOverwritten => f.write_str("Message body was set more than once."),
+            Error::AsyncAttachment => f.write_str(
+                "Async attachments can only be written using write_to_async, without DKIM signing.",
+            ),
+            Error::NotAnImage(content_type) => {
+                write!(f, "Expected an image, got content type {:?}.", content_type)
+            }
+            Error::ImageTooLarge => f.write_str("Remote image exceeds the maximum size."),
+            Error::InvalidEncoderName(name) => {
+                write!(f, "Invalid Content-Transfer-Encoding {:?}.", name)
+            }
+            Error::ConflictingBody => {
+                f.write_str("A raw MIME body cannot be combined with other bodies or attachments.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
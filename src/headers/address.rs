@@ -11,23 +11,29 @@
 
 use std::borrow::Cow;
 
-use crate::encoders::encode::rfc2047_encode;
+use crate::{
+    encoders::encode::{check_header_value, rfc2047_encode_with},
+    Error,
+};
 
-use super::Header;
+use super::{Header, HeaderOptions};
 
 /// RFC5322 e-mail address
+#[derive(Debug, Clone)]
 pub struct EmailAddress<'x> {
     pub name: Option<Cow<'x, str>>,
     pub email: Cow<'x, str>,
 }
 
 /// RFC5322 grouped e-mail addresses
+#[derive(Debug, Clone)]
 pub struct GroupedAddresses<'x> {
     pub name: Option<Cow<'x, str>>,
     pub addresses: Vec<Address<'x>>,
 }
 
 /// RFC5322 address
+#[derive(Debug, Clone)]
 pub enum Address<'x> {
     Address(EmailAddress<'x>),
     Group(GroupedAddresses<'x>),
@@ -59,6 +65,45 @@ impl<'x> Address<'x> {
         Address::List(items)
     }
 
+    /// Convert the address into an owned, `'static` version
+    pub fn into_owned(self) -> Address<'static> {
+        match self {
+            Address::Address(address) => Address::Address(address.into_owned()),
+            Address::Group(group) => Address::Group(group.into_owned()),
+            Address::List(list) => {
+                Address::List(list.into_iter().map(|a| a.into_owned()).collect())
+            }
+        }
+    }
+
+    /// Returns the first e-mail address found, looking inside groups and lists.
+    pub fn first_email(&self) -> Option<&str> {
+        match self {
+            Address::Address(address) => Some(address.email.as_ref()),
+            Address::Group(group) => group.addresses.iter().find_map(|a| a.first_email()),
+            Address::List(list) => list.iter().find_map(|a| a.first_email()),
+        }
+    }
+
+    /// Returns the first e-mail address found, including its display name.
+    pub fn first_address(&self) -> Option<&EmailAddress<'x>> {
+        match self {
+            Address::Address(address) => Some(address),
+            Address::Group(group) => group.addresses.iter().find_map(|a| a.first_address()),
+            Address::List(list) => list.iter().find_map(|a| a.first_address()),
+        }
+    }
+
+    /// Returns the number of mailboxes, counting those inside groups and lists.
+    pub fn mailbox_count(&self) -> usize {
+        match self {
+            Address::Address(_) => 1,
+            Address::Group(group) => group.addresses.iter().map(|a| a.mailbox_count()).sum(),
+            Address::List(list) => list.iter().map(|a| a.mailbox_count()).sum(),
+        }
+    }
+
+    /// Returns the inner `EmailAddress`, panicking if this is a group or a list.
     pub fn unwrap_address(&self) -> &EmailAddress<'x> {
         match self {
             Address::Address(address) => address,
@@ -67,6 +112,26 @@ impl<'x> Address<'x> {
     }
 }
 
+impl<'x> EmailAddress<'x> {
+    /// Convert the address into an owned, `'static` version
+    pub fn into_owned(self) -> EmailAddress<'static> {
+        EmailAddress {
+            name: self.name.map(|name| name.into_owned().into()),
+            email: self.email.into_owned().into(),
+        }
+    }
+}
+
+impl<'x> GroupedAddresses<'x> {
+    /// Convert the group into an owned, `'static` version
+    pub fn into_owned(self) -> GroupedAddresses<'static> {
+        GroupedAddresses {
+            name: self.name.map(|name| name.into_owned().into()),
+            addresses: self.addresses.into_iter().map(|a| a.into_owned()).collect(),
+        }
+    }
+}
+
 impl<'x> From<(&'x str, &'x str)> for Address<'x> {
     fn from(value: (&'x str, &'x str)) -> Self {
         Address::Address(EmailAddress {
@@ -85,6 +150,24 @@ impl<'x> From<(String, String)> for Address<'x> {
     }
 }
 
+impl<'x> From<(Option<&'x str>, &'x str)> for Address<'x> {
+    fn from(value: (Option<&'x str>, &'x str)) -> Self {
+        Address::Address(EmailAddress {
+            name: value.0.map(|name| name.into()),
+            email: value.1.into(),
+        })
+    }
+}
+
+impl<'x> From<(Option<String>, String)> for Address<'x> {
+    fn from(value: (Option<String>, String)) -> Self {
+        Address::Address(EmailAddress {
+            name: value.0.map(|name| name.into()),
+            email: value.1.into(),
+        })
+    }
+}
+
 impl<'x> From<&'x str> for Address<'x> {
     fn from(value: &'x str) -> Self {
         Address::Address(EmailAddress {
@@ -103,6 +186,27 @@ impl<'x> From<String> for Address<'x> {
     }
 }
 
+impl<'x> From<&'x String> for Address<'x> {
+    fn from(value: &'x String) -> Self {
+        Address::Address(EmailAddress {
+            name: None,
+            email: value.as_str().into(),
+        })
+    }
+}
+
+impl<'x> From<&'x [String]> for Address<'x> {
+    fn from(value: &'x [String]) -> Self {
+        Address::new_list(value.iter().map(|x| x.into()).collect())
+    }
+}
+
+impl<'x> From<&'x [&'x str]> for Address<'x> {
+    fn from(value: &'x [&'x str]) -> Self {
+        Address::new_list(value.iter().map(|&x| x.into()).collect())
+    }
+}
+
 impl<'x, T> From<Vec<T>> for Address<'x>
 where
     T: Into<Address<'x>>,
@@ -128,15 +232,24 @@ where
 impl<'x> Header for Address<'x> {
     fn write_header(
         &self,
-        mut output: impl std::io::Write,
+        output: &mut dyn std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with(output, bytes_written, HeaderOptions::default())
+    }
+
+    fn write_header_with(
+        &self,
+        mut output: &mut dyn std::io::Write,
         mut bytes_written: usize,
+        options: HeaderOptions,
     ) -> std::io::Result<usize> {
         match self {
             Address::Address(address) => {
-                address.write_header(&mut output, bytes_written)?;
+                address.write_header_with(&mut output, bytes_written, options)?;
             }
             Address::Group(group) => {
-                group.write_header(&mut output, bytes_written)?;
+                group.write_header_with(&mut output, bytes_written, options)?;
             }
             Address::List(list) => {
                 for (pos, address) in list.iter().enumerate() {
@@ -160,20 +273,22 @@ impl<'x> Header for Address<'x> {
 
                     match address {
                         Address::Address(address) => {
-                            bytes_written += address.write_header(&mut output, bytes_written)?;
+                            bytes_written +=
+                                address.write_header_with(&mut output, bytes_written, options)?;
                             if pos < list.len() - 1 {
                                 output.write_all(b", ")?;
                                 bytes_written += 1;
                             }
                         }
                         Address::Group(group) => {
-                            bytes_written += group.write_header(&mut output, bytes_written)?;
+                            bytes_written +=
+                                group.write_header_with(&mut output, bytes_written, options)?;
                             if pos < list.len() - 1 {
                                 output.write_all(b"; ")?;
                                 bytes_written += 1;
                             }
                         }
-                        Address::List(_) => unreachable!(),
+                        Address::List(_) => return Err(Error::NestedAddress.into()),
                     }
                 }
             }
@@ -186,11 +301,20 @@ impl<'x> Header for Address<'x> {
 impl<'x> Header for EmailAddress<'x> {
     fn write_header(
         &self,
-        mut output: impl std::io::Write,
+        output: &mut dyn std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with(output, bytes_written, HeaderOptions::default())
+    }
+
+    fn write_header_with(
+        &self,
+        mut output: &mut dyn std::io::Write,
         mut bytes_written: usize,
+        options: HeaderOptions,
     ) -> std::io::Result<usize> {
-        if let Some(name) = &self.name {
-            bytes_written += rfc2047_encode(name, &mut output)?;
+        if let Some(name) = self.name.as_ref().filter(|name| !name.trim().is_empty()) {
+            bytes_written += rfc2047_encode_with(name, &mut output, options.uppercase_charset)?;
             if bytes_written + self.email.len() + 2 >= 76 {
                 output.write_all(b"\r\n\t")?;
                 bytes_written = 1;
@@ -200,6 +324,7 @@ impl<'x> Header for EmailAddress<'x> {
             }
         }
 
+        check_header_value(&self.email)?;
         output.write_all(b"<")?;
         output.write_all(self.email.as_bytes())?;
         output.write_all(b">")?;
@@ -211,16 +336,28 @@ impl<'x> Header for EmailAddress<'x> {
 impl<'x> Header for GroupedAddresses<'x> {
     fn write_header(
         &self,
-        mut output: impl std::io::Write,
+        output: &mut dyn std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with(output, bytes_written, HeaderOptions::default())
+    }
+
+    fn write_header_with(
+        &self,
+        mut output: &mut dyn std::io::Write,
         mut bytes_written: usize,
+        options: HeaderOptions,
     ) -> std::io::Result<usize> {
         if let Some(name) = &self.name {
-            bytes_written += rfc2047_encode(name, &mut output)? + 2;
+            bytes_written += rfc2047_encode_with(name, &mut output, options.uppercase_charset)? + 2;
             output.write_all(b": ")?;
         }
 
         for (pos, address) in self.addresses.iter().enumerate() {
-            let address = address.unwrap_address();
+            let address = match address {
+                Address::Address(address) => address,
+                _ => return Err(Error::NestedAddress.into()),
+            };
 
             if bytes_written
                 + address.email.len()
@@ -232,7 +369,7 @@ impl<'x> Header for GroupedAddresses<'x> {
                 bytes_written = 1;
             }
 
-            bytes_written += address.write_header(&mut output, bytes_written)?;
+            bytes_written += address.write_header_with(&mut output, bytes_written, options)?;
             if pos < self.addresses.len() - 1 {
                 output.write_all(b", ")?;
                 bytes_written += 2;
@@ -0,0 +1,90 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::borrow::Cow;
+
+use crate::encoders::encode::check_header_value;
+
+use super::Header;
+
+/// RFC2557 Content-Location header. URLs that do not fit in the current
+/// line are moved to their own line and split into 76 column chunks, as
+/// receivers remove the folding whitespace from the URL when unfolding.
+#[derive(Debug, Clone)]
+pub struct ContentLocation<'x> {
+    pub url: Cow<'x, str>,
+}
+
+impl<'x> ContentLocation<'x> {
+    /// Create a new Content-Location header
+    pub fn new(url: impl Into<Cow<'x, str>>) -> Self {
+        Self { url: url.into() }
+    }
+
+    /// Convert the header into an owned, `'static` version
+    pub fn into_owned(self) -> ContentLocation<'static> {
+        ContentLocation {
+            url: self.url.into_owned().into(),
+        }
+    }
+}
+
+impl<'x> Header for ContentLocation<'x> {
+    fn write_header(
+        &self,
+        output: &mut dyn std::io::Write,
+        mut bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        check_header_value(&self.url)?;
+        let url = self.url.trim();
+        if bytes_written + url.len() > 76 {
+            output.write_all(b"\r\n ")?;
+            bytes_written = 1;
+        }
+        for ch in url.chars() {
+            if bytes_written + ch.len_utf8() > 76 {
+                output.write_all(b"\r\n ")?;
+                bytes_written = 1;
+            }
+            let mut buf = [0; 4];
+            output.write_all(ch.encode_utf8(&mut buf).as_bytes())?;
+            bytes_written += ch.len_utf8();
+        }
+        output.write_all(b"\r\n")?;
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headers::Header;
+
+    use super::ContentLocation;
+
+    #[test]
+    fn content_location() {
+        let mut output = Vec::new();
+        ContentLocation::new("http://example.com/image.png")
+            .write_header(&mut output, 18)
+            .unwrap();
+        assert_eq!(output, b"http://example.com/image.png\r\n");
+
+        let url = format!("https://example.com/{}.png", "a".repeat(200));
+        let mut output = Vec::new();
+        ContentLocation::new(url.as_str())
+            .write_header(&mut output, 18)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("\r\n https://example.com/"));
+        assert!(output.split("\r\n").all(|line| line.len() <= 76));
+        assert_eq!(output.replace("\r\n ", "").trim_end(), url);
+    }
+}
@@ -11,14 +11,16 @@
 
 use std::{borrow::Cow, collections::BTreeMap};
 
-use crate::encoders::encode::rfc2047_encode;
+use crate::encoders::encode::{check_header_line, rfc2231_encode};
 
 use super::Header;
 
 /// MIME Content-Type or Content-Disposition header
+#[derive(Debug, Clone)]
 pub struct ContentType<'x> {
     pub c_type: Cow<'x, str>,
     pub attributes: BTreeMap<Cow<'x, str>, Cow<'x, str>>,
+    pub quote_charset: bool,
 }
 
 impl<'x> ContentType<'x> {
@@ -27,6 +29,7 @@ impl<'x> ContentType<'x> {
         Self {
             c_type: c_type.into(),
             attributes: BTreeMap::new(),
+            quote_charset: false,
         }
     }
 
@@ -40,9 +43,38 @@ impl<'x> ContentType<'x> {
         self
     }
 
-    /// Returns true when the part is text/*
+    /// Write the `charset` parameter as a quoted-string with its original case,
+    /// for compatibility with parsers expecting `charset="utf-8"`. By default
+    /// it is written as a lowercase token, such as `charset=utf-8`.
+    pub fn quoted_charset(mut self) -> Self {
+        self.quote_charset = true;
+        self
+    }
+
+    /// Convert the header into an owned, `'static` version
+    pub fn into_owned(self) -> ContentType<'static> {
+        ContentType {
+            c_type: self.c_type.into_owned().into(),
+            attributes: self
+                .attributes
+                .into_iter()
+                .map(|(key, value)| (key.into_owned().into(), value.into_owned().into()))
+                .collect(),
+            quote_charset: self.quote_charset,
+        }
+    }
+
+    /// Returns true when the part contains text, such as text/*, JSON,
+    /// JavaScript or any XML based type (including SVG images).
     pub fn is_text(&self) -> bool {
-        self.c_type.starts_with("text/")
+        let c_type = self.c_type.trim().to_ascii_lowercase();
+        c_type.starts_with("text/")
+            || c_type.ends_with("+xml")
+            || c_type.ends_with("+json")
+            || matches!(
+                c_type.as_str(),
+                "application/json" | "application/xml" | "application/javascript"
+            )
     }
 
     /// Returns true when the part is an attachment
@@ -54,23 +86,96 @@ impl<'x> ContentType<'x> {
 impl<'x> Header for ContentType<'x> {
     fn write_header(
         &self,
-        mut output: impl std::io::Write,
+        output: &mut dyn std::io::Write,
         mut bytes_written: usize,
     ) -> std::io::Result<usize> {
+        check_header_line(&self.c_type)?;
+        for (key, value) in &self.attributes {
+            check_header_line(key)?;
+            check_header_line(value)?;
+        }
         output.write_all(self.c_type.as_bytes())?;
         bytes_written += self.c_type.len();
         if !self.attributes.is_empty() {
             output.write_all(b"; ")?;
             bytes_written += 2;
-            for (pos, (key, value)) in self.attributes.iter().enumerate() {
-                if bytes_written + key.len() + value.len() + 3 >= 76 {
+            // Attributes are written in a canonical order: the charset first,
+            // as most clients do, then the rest alphabetically with the
+            // boundary last.
+            let attributes = self
+                .attributes
+                .get_key_value("charset")
+                .into_iter()
+                .chain(
+                    self.attributes
+                        .iter()
+                        .filter(|(key, _)| *key != "charset" && *key != "boundary"),
+                )
+                .chain(self.attributes.get_key_value("boundary"));
+            for (pos, (key, value)) in attributes.enumerate() {
+                let encoded = if key != "boundary" && !value.is_ascii() {
+                    let mut encoded = Vec::with_capacity(value.len() * 3 + 7);
+                    rfc2231_encode(value, &mut encoded)?;
+                    Some(encoded)
+                } else {
+                    None
+                };
+                let value_len = encoded.as_ref().map_or(value.len(), |v| v.len());
+
+                if bytes_written + key.len() + value_len + 3 >= 76 {
                     output.write_all(b"\r\n\t")?;
                     bytes_written = 1;
                 }
 
                 output.write_all(key.as_bytes())?;
-                output.write_all(b"=")?;
-                bytes_written += rfc2047_encode(value, &mut output)? + key.len() + 1;
+                if key == "boundary" {
+                    // Boundaries are always quoted, as they may contain characters
+                    // that are not allowed in tokens.
+                    output.write_all(b"=\"")?;
+                    output.write_all(value.as_bytes())?;
+                    output.write_all(b"\"")?;
+                    bytes_written += key.len() + value.len() + 3;
+                } else if key == "charset" && !self.quote_charset && is_token(value) {
+                    output.write_all(b"=")?;
+                    output.write_all(value.to_ascii_lowercase().as_bytes())?;
+                    bytes_written += key.len() + value.len() + 1;
+                } else if let Some(encoded) = encoded {
+                    if key.len() + encoded.len() + 4 < 76 {
+                        output.write_all(b"*=")?;
+                        output.write_all(&encoded)?;
+                        bytes_written += key.len() + encoded.len() + 2;
+                    } else {
+                        // Split long values into RFC2231 continuations,
+                        // each one on its own line.
+                        for (num, segment) in split_rfc2231(&encoded, 76 - key.len() - 8)
+                            .into_iter()
+                            .enumerate()
+                        {
+                            if num > 0 {
+                                output.write_all(b";\r\n\t")?;
+                                output.write_all(key.as_bytes())?;
+                                bytes_written = 1 + key.len();
+                            }
+                            let param = format!("*{}*=", num);
+                            output.write_all(param.as_bytes())?;
+                            output.write_all(segment)?;
+                            bytes_written += param.len() + segment.len();
+                        }
+                    }
+                } else {
+                    // Other ASCII values are written as a quoted-string, as
+                    // encoded-words are not decoded inside parameters.
+                    output.write_all(b"=\"")?;
+                    for ch in value.bytes() {
+                        if ch == b'\\' || ch == b'"' {
+                            output.write_all(b"\\")?;
+                            bytes_written += 1;
+                        }
+                        output.write_all(&[ch])?;
+                    }
+                    output.write_all(b"\"")?;
+                    bytes_written += key.len() + value.len() + 3;
+                }
                 if pos < self.attributes.len() - 1 {
                     output.write_all(b"; ")?;
                     bytes_written += 2;
@@ -81,3 +186,43 @@ impl<'x> Header for ContentType<'x> {
         Ok(0)
     }
 }
+
+/// Returns true if the value is a RFC2045 token, which can be written unquoted.
+pub(crate) fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|ch| ch.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?=".contains(&ch))
+}
+
+/// Split an RFC2231 encoded value into segments of at most `max_len` octets,
+/// without splitting percent-encoded octets or multi-byte UTF-8 characters.
+fn split_rfc2231(encoded: &[u8], max_len: usize) -> Vec<&[u8]> {
+    let max_len = max_len.max(12);
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut boundary = 0;
+    let mut pos = 0;
+
+    while pos < encoded.len() {
+        let (token_len, is_continuation) = if encoded[pos] == b'%' {
+            let octet = std::str::from_utf8(&encoded[pos + 1..pos + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .unwrap_or(0);
+            (3, (0x80..0xC0).contains(&octet))
+        } else {
+            (1, false)
+        };
+        if !is_continuation {
+            boundary = pos;
+        }
+        if boundary > start && pos + token_len - start > max_len {
+            segments.push(&encoded[start..boundary]);
+            start = boundary;
+        }
+        pos += token_len;
+    }
+    segments.push(&encoded[start..]);
+    segments
+}
@@ -9,25 +9,122 @@
  * except according to those terms.
  */
 
-use std::io::{self, Write};
+use std::{
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use super::Header;
 
+static DAYS: &[&str] = &["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+static MONTHS: &[&str] = &[
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
 /// RFC5322 Date header
+#[derive(Debug, Clone)]
 pub struct Date {
     pub date: i64,
+    pub offset: i32,
 }
 
 impl Date {
-    /// Create a new Date header from a `chrono` timestamp.
+    /// Create a new Date header from a UNIX timestamp, in UTC.
     pub fn new(date: i64) -> Self {
-        Self { date }
+        Self { date, offset: 0 }
+    }
+
+    /// Create a new Date header using the current time, in UTC.
+    pub fn now() -> Self {
+        Self::new(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0),
+        )
+    }
+
+    /// Set the timezone offset of the date, in minutes east of UTC.
+    pub fn with_offset(mut self, offset: i32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Returns the date formatted as an RFC5322 date-time string.
+    pub fn to_rfc822(&self) -> String {
+        // Civil date from days since epoch, see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let date = self.date + self.offset as i64 * 60;
+        let days = date.div_euclid(86400);
+        let secs = date.rem_euclid(86400);
+
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        format!(
+            "{}, {:02} {} {:04} {:02}:{:02}:{:02} {}{:02}{:02}",
+            DAYS[(days + 4).rem_euclid(7) as usize],
+            day,
+            MONTHS[(month - 1) as usize],
+            year,
+            secs / 3600,
+            (secs / 60) % 60,
+            secs % 60,
+            if self.offset < 0 { '-' } else { '+' },
+            self.offset.abs() / 60,
+            self.offset.abs() % 60,
+        )
+    }
+}
+
+impl From<i64> for Date {
+    fn from(value: i64) -> Self {
+        Self::new(value)
     }
 }
 
 impl Header for Date {
-    fn write_header(&self, mut output: impl Write, _bytes_written: usize) -> io::Result<usize> {
+    fn write_header(&self, output: &mut dyn Write, _bytes_written: usize) -> io::Result<usize> {
+        output.write_all(self.to_rfc822().as_bytes())?;
         output.write_all(b"\r\n")?;
         Ok(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn format_rfc822_date() {
+        for (timestamp, expected_result) in [
+            (0, "Thu, 01 Jan 1970 00:00:00 +0000"),
+            (-1, "Wed, 31 Dec 1969 23:59:59 +0000"),
+            (951782400, "Tue, 29 Feb 2000 00:00:00 +0000"),
+            (1000000000, "Sun, 09 Sep 2001 01:46:40 +0000"),
+            (1234567890, "Fri, 13 Feb 2009 23:31:30 +0000"),
+            (1640995199, "Fri, 31 Dec 2021 23:59:59 +0000"),
+            (4102444800, "Fri, 01 Jan 2100 00:00:00 +0000"),
+            (-14182940, "Sun, 20 Jul 1969 20:17:40 +0000"),
+        ] {
+            assert_eq!(super::Date::new(timestamp).to_rfc822(), expected_result);
+        }
+
+        for (timestamp, offset, expected_result) in [
+            (0, 60, "Thu, 01 Jan 1970 01:00:00 +0100"),
+            (0, -210, "Wed, 31 Dec 1969 20:30:00 -0330"),
+            (1234567890, 330, "Sat, 14 Feb 2009 05:01:30 +0530"),
+        ] {
+            assert_eq!(
+                super::Date::new(timestamp).with_offset(offset).to_rfc822(),
+                expected_result
+            );
+        }
+    }
+}
@@ -0,0 +1,124 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::borrow::Cow;
+
+use crate::{encoders::encode::rfc2047_encode_with, Error};
+
+use super::{Header, HeaderOptions};
+
+/// RFC2919 List-ID header
+#[derive(Debug, Clone)]
+pub struct ListId<'x> {
+    pub description: Option<Cow<'x, str>>,
+    pub id: Cow<'x, str>,
+}
+
+impl<'x> ListId<'x> {
+    /// Create a new List-ID header
+    pub fn new(description: Option<impl Into<Cow<'x, str>>>, id: impl Into<Cow<'x, str>>) -> Self {
+        Self {
+            description: description.map(|v| v.into()),
+            id: id.into(),
+        }
+    }
+
+    /// Convert the header into an owned, `'static` version
+    pub fn into_owned(self) -> ListId<'static> {
+        ListId {
+            description: self.description.map(|v| v.into_owned().into()),
+            id: self.id.into_owned().into(),
+        }
+    }
+
+    /// Returns true when the list id is a valid dot-atom containing
+    /// at least one dot, as required by RFC2919.
+    pub fn is_valid(&self) -> bool {
+        self.id.contains('.')
+            && self.id.split('.').all(|label| {
+                !label.is_empty()
+                    && label.bytes().all(|ch| {
+                        ch.is_ascii_alphanumeric() || b"!#$%&'*+-/=?^_`{|}~".contains(&ch)
+                    })
+            })
+    }
+}
+
+impl<'x> Header for ListId<'x> {
+    fn write_header(
+        &self,
+        output: &mut dyn std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with(output, bytes_written, HeaderOptions::default())
+    }
+
+    fn write_header_with(
+        &self,
+        mut output: &mut dyn std::io::Write,
+        mut bytes_written: usize,
+        options: HeaderOptions,
+    ) -> std::io::Result<usize> {
+        if !self.is_valid() {
+            return Err(Error::InvalidListId(self.id.to_string()).into());
+        }
+
+        if let Some(description) = &self.description {
+            bytes_written +=
+                rfc2047_encode_with(description, &mut output, options.uppercase_charset)?;
+            if bytes_written + self.id.len() + 2 >= 76 {
+                output.write_all(b"\r\n\t")?;
+            } else {
+                output.write_all(b" ")?;
+            }
+        }
+
+        output.write_all(b"<")?;
+        output.write_all(self.id.as_bytes())?;
+        output.write_all(b">\r\n")?;
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headers::Header;
+
+    #[test]
+    fn write_list_id() {
+        for (description, id, expected_result) in [
+            (None, "list.example.com", Some("<list.example.com>\r\n")),
+            (
+                Some("My list"),
+                "list.example.com",
+                Some("\"My list\" <list.example.com>\r\n"),
+            ),
+            (
+                Some("Lista de ñandúes"),
+                "nandu.example.com",
+                Some("=?utf-8?B?TGlzdGEgZGUgw7FhbmTDumVz?= <nandu.example.com>\r\n"),
+            ),
+            (None, "example", None),
+            (None, "list..example.com", None),
+            (Some("My list"), "list@example.com", None),
+        ] {
+            let mut output = Vec::new();
+            let result = super::ListId::new(description, id).write_header(&mut output, 9);
+            if let Some(expected_result) = expected_result {
+                result.unwrap();
+                assert_eq!(std::str::from_utf8(&output).unwrap(), expected_result);
+            } else {
+                assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+            }
+        }
+    }
+}
@@ -9,11 +9,19 @@
  * except according to those terms.
  */
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{encoders::encode::check_header_value, mime::make_boundary};
 
 use super::Header;
 
 /// RFC5322 Message ID header
+#[derive(Debug, Clone)]
 pub struct MessageId<'x> {
     pub id: Vec<Cow<'x, str>>,
 }
@@ -22,7 +30,7 @@ impl<'x> MessageId<'x> {
     /// Create a new Message ID header
     pub fn new(id: impl Into<Cow<'x, str>>) -> Self {
         Self {
-            id: vec![id.into()],
+            id: vec![normalize_id(id.into())],
         }
     }
 
@@ -33,11 +41,107 @@ impl<'x> MessageId<'x> {
         U: Into<Cow<'x, str>>,
     {
         Self {
-            id: ids.map(|s| s.into()).collect(),
+            id: ids.map(|s| normalize_id(s.into())).collect(),
+        }
+    }
+
+    /// Keep at most `max_ids` ids by removing those in the middle, keeping
+    /// the first id and the most recent ones, as is usual for the References
+    /// header of long threads.
+    pub fn truncate_middle(&mut self, max_ids: usize) {
+        let max_ids = max_ids.max(1);
+        if self.id.len() > max_ids {
+            self.id.drain(1..self.id.len() - (max_ids - 1));
+        }
+    }
+
+    /// Convert the header into an owned, `'static` version
+    pub fn into_owned(self) -> MessageId<'static> {
+        MessageId {
+            id: self
+                .id
+                .into_iter()
+                .map(|id| id.into_owned().into())
+                .collect(),
         }
     }
 }
 
+/// Format of the automatically generated Message-ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageIdFormat {
+    /// A unique MIME boundary style id without a domain, such as
+    /// `<18df66717acdb260_c99ad76ccea3940e_0>`.
+    #[default]
+    Boundary,
+    /// A random version 4 UUID, such as
+    /// `<0f8fad5b-d9cb-469f-a165-70867728950e@example.com>`.
+    Uuid,
+    /// The UNIX time in milliseconds followed by a random number, such as
+    /// `<1634418000000.e3b0c44298fc1c14@example.com>`.
+    TimestampRandom,
+    /// A random 32 character hexadecimal token, such as
+    /// `<9b74c9897bac770ffc029102a200c5de@example.com>`.
+    RandomToken,
+}
+
+impl MessageIdFormat {
+    /// Generate a new message id in this format, using `domain` as the
+    /// right-hand side of the id for all formats except `Boundary`.
+    pub fn generate(&self, domain: &str) -> String {
+        match self {
+            MessageIdFormat::Boundary => make_boundary(),
+            MessageIdFormat::Uuid => {
+                let (high, low) = (random_u64(), random_u64());
+                format!(
+                    "{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}@{}",
+                    high >> 32,
+                    (high >> 16) & 0xffff,
+                    high & 0x0fff,
+                    (low >> 48) & 0x3fff | 0x8000,
+                    low & 0xffff_ffff_ffff,
+                    domain
+                )
+            }
+            MessageIdFormat::TimestampRandom => format!(
+                "{}.{:016x}@{}",
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis())
+                    .unwrap_or(0),
+                random_u64(),
+                domain
+            ),
+            MessageIdFormat::RandomToken => {
+                format!("{:016x}{:016x}@{}", random_u64(), random_u64(), domain)
+            }
+        }
+    }
+}
+
+/// Returns a random number, obtained by hashing a unique boundary with
+/// randomly seeded keys.
+fn random_u64() -> u64 {
+    RandomState::new().hash_one(make_boundary())
+}
+
+/// Remove surrounding whitespace and angle brackets from a message id, as they
+/// are added when the header is written.
+fn normalize_id(id: Cow<str>) -> Cow<str> {
+    fn strip(id: &str) -> &str {
+        let id = id.trim();
+        id.strip_prefix('<')
+            .and_then(|id| id.strip_suffix('>'))
+            .unwrap_or(id)
+    }
+
+    match id {
+        Cow::Borrowed(id) => Cow::Borrowed(strip(id)),
+        Cow::Owned(id) if strip(&id).len() == id.len() => Cow::Owned(id),
+        Cow::Owned(id) => Cow::Owned(strip(&id).to_string()),
+    }
+}
+
 impl<'x> From<&'x str> for MessageId<'x> {
     fn from(value: &'x str) -> Self {
         Self::new(value)
@@ -53,7 +157,7 @@ impl<'x> From<String> for MessageId<'x> {
 impl<'x> From<&[&'x str]> for MessageId<'x> {
     fn from(value: &[&'x str]) -> Self {
         MessageId {
-            id: value.iter().map(|&s| s.into()).collect(),
+            id: value.iter().map(|&s| normalize_id(s.into())).collect(),
         }
     }
 }
@@ -64,7 +168,7 @@ where
 {
     fn from(value: Vec<T>) -> Self {
         MessageId {
-            id: value.into_iter().map(|s| s.into()).collect(),
+            id: value.into_iter().map(|s| normalize_id(s.into())).collect(),
         }
     }
 }
@@ -72,12 +176,15 @@ where
 impl<'x> Header for MessageId<'x> {
     fn write_header(
         &self,
-        mut output: impl std::io::Write,
+        output: &mut dyn std::io::Write,
         mut bytes_written: usize,
     ) -> std::io::Result<usize> {
         for (pos, id) in self.id.iter().enumerate() {
+            check_header_value(id)?;
             if pos > 0 {
-                if bytes_written + id.len() + 2 >= 76 {
+                // Fold before ids that would not fit in the line, including
+                // the separating space and angle brackets.
+                if bytes_written + id.len() + 3 > 76 {
                     output.write_all(b"\r\n\t")?;
                     bytes_written = 1;
                 } else {
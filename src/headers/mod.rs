@@ -10,32 +10,93 @@
  */
 
 pub mod address;
+pub mod content_location;
 pub mod content_type;
 pub mod date;
+pub mod list_id;
 pub mod message_id;
 pub mod raw;
+pub mod received;
 pub mod text;
 pub mod url;
 
-use std::io::{self, Write};
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+use crate::Error;
 
 use self::{
-    address::Address, content_type::ContentType, date::Date, message_id::MessageId, raw::Raw,
-    text::Text, url::URL,
+    address::Address, content_location::ContentLocation, content_type::ContentType, date::Date,
+    list_id::ListId, message_id::MessageId, raw::Raw, received::Received, text::Text, url::URL,
 };
 
+/// An e-mail header value.
+///
+/// Custom header types can implement this trait and be added to a message
+/// by wrapping them in `HeaderType::Custom`.
 pub trait Header {
-    fn write_header(&self, output: impl Write, bytes_written: usize) -> io::Result<usize>;
+    /// Write the header value, excluding the header name, followed by a CRLF.
+    /// `bytes_written` is the length of the current line so far (usually the
+    /// header name plus `": "`), which implementations should use to fold
+    /// long values at 76 columns.
+    fn write_header(&self, output: &mut dyn Write, bytes_written: usize) -> io::Result<usize>;
+
+    /// Write the header value as `write_header` does, following `options`.
+    /// Headers that are not affected by any of the options do not need to
+    /// implement it.
+    fn write_header_with(
+        &self,
+        output: &mut dyn Write,
+        bytes_written: usize,
+        _options: HeaderOptions,
+    ) -> io::Result<usize> {
+        self.write_header(output, bytes_written)
+    }
+}
+
+/// Options for writing header values, see `Header::write_header_with`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HeaderOptions {
+    /// Write the charset label of encoded-words in uppercase, such as
+    /// `=?UTF-8?B?`.
+    pub uppercase_charset: bool,
 }
 
 pub enum HeaderType<'x> {
     Address(Address<'x>),
     Date(Date),
+    ListId(ListId<'x>),
     MessageId(MessageId<'x>),
     Raw(Raw<'x>),
+    Received(Received<'x>),
     Text(Text<'x>),
     URL(URL<'x>),
     ContentType(ContentType<'x>),
+    ContentLocation(ContentLocation<'x>),
+    Custom(Box<dyn Header + Send + Sync>),
+}
+
+impl<'x> fmt::Debug for HeaderType<'x> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderType::Address(value) => f.debug_tuple("Address").field(value).finish(),
+            HeaderType::Date(value) => f.debug_tuple("Date").field(value).finish(),
+            HeaderType::ListId(value) => f.debug_tuple("ListId").field(value).finish(),
+            HeaderType::Received(value) => f.debug_tuple("Received").field(value).finish(),
+            HeaderType::MessageId(value) => f.debug_tuple("MessageId").field(value).finish(),
+            HeaderType::Raw(value) => f.debug_tuple("Raw").field(value).finish(),
+            HeaderType::Text(value) => f.debug_tuple("Text").field(value).finish(),
+            HeaderType::URL(value) => f.debug_tuple("URL").field(value).finish(),
+            HeaderType::ContentLocation(value) => {
+                f.debug_tuple("ContentLocation").field(value).finish()
+            }
+            HeaderType::ContentType(value) => f.debug_tuple("ContentType").field(value).finish(),
+            HeaderType::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
 }
 
 impl<'x> From<Address<'x>> for HeaderType<'x> {
@@ -55,6 +116,11 @@ impl<'x> From<Date> for HeaderType<'x> {
         HeaderType::Date(value)
     }
 }
+impl<'x> From<ListId<'x>> for HeaderType<'x> {
+    fn from(value: ListId<'x>) -> Self {
+        HeaderType::ListId(value)
+    }
+}
 impl<'x> From<MessageId<'x>> for HeaderType<'x> {
     fn from(value: MessageId<'x>) -> Self {
         HeaderType::MessageId(value)
@@ -65,6 +131,11 @@ impl<'x> From<Raw<'x>> for HeaderType<'x> {
         HeaderType::Raw(value)
     }
 }
+impl<'x> From<Received<'x>> for HeaderType<'x> {
+    fn from(value: Received<'x>) -> Self {
+        HeaderType::Received(value)
+    }
+}
 impl<'x> From<Text<'x>> for HeaderType<'x> {
     fn from(value: Text<'x>) -> Self {
         HeaderType::Text(value)
@@ -77,22 +148,157 @@ impl<'x> From<URL<'x>> for HeaderType<'x> {
     }
 }
 
-impl<'x> Header for HeaderType<'x> {
-    fn write_header(&self, output: impl Write, bytes_written: usize) -> io::Result<usize> {
-        match self {
-            HeaderType::Address(value) => value.write_header(output, bytes_written),
-            HeaderType::Date(value) => value.write_header(output, bytes_written),
-            HeaderType::MessageId(value) => value.write_header(output, bytes_written),
-            HeaderType::Raw(value) => value.write_header(output, bytes_written),
-            HeaderType::Text(value) => value.write_header(output, bytes_written),
-            HeaderType::URL(value) => value.write_header(output, bytes_written),
-            HeaderType::ContentType(value) => value.write_header(output, bytes_written),
+impl<'x> From<ContentLocation<'x>> for HeaderType<'x> {
+    fn from(value: ContentLocation<'x>) -> Self {
+        HeaderType::ContentLocation(value)
+    }
+}
+
+impl<'x> From<Box<dyn Header + Send + Sync>> for HeaderType<'x> {
+    fn from(value: Box<dyn Header + Send + Sync>) -> Self {
+        HeaderType::Custom(value)
+    }
+}
+
+/// Maximum length of a header line in octets, excluding the CRLF (RFC5322 section 2.1.1).
+pub const MAX_LINE_LENGTH: usize = 998;
+
+/// Writer that fails if any line written through it exceeds `MAX_LINE_LENGTH`.
+struct LineLimit<'a> {
+    output: &'a mut dyn Write,
+    line_len: usize,
+}
+
+impl<'a> Write for LineLimit<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &ch in buf {
+            match ch {
+                b'\n' => self.line_len = 0,
+                b'\r' => (),
+                _ => {
+                    self.line_len += 1;
+                    if self.line_len > MAX_LINE_LENGTH {
+                        return Err(Error::HeaderTooLong.into());
+                    }
+                }
+            }
         }
+        self.output.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Write a header value, returning an error if any of its lines
+/// is longer than `MAX_LINE_LENGTH` after folding.
+pub(crate) fn write_header_limited(
+    header: &dyn Header,
+    output: &mut dyn Write,
+    bytes_written: usize,
+    options: HeaderOptions,
+) -> io::Result<usize> {
+    header.write_header_with(
+        &mut LineLimit {
+            output,
+            line_len: bytes_written,
+        },
+        bytes_written,
+        options,
+    )
+}
+
+impl<'x> Header for HeaderType<'x> {
+    fn write_header(&self, output: &mut dyn Write, bytes_written: usize) -> io::Result<usize> {
+        self.write_header_with(output, bytes_written, HeaderOptions::default())
+    }
+
+    fn write_header_with(
+        &self,
+        output: &mut dyn Write,
+        bytes_written: usize,
+        options: HeaderOptions,
+    ) -> io::Result<usize> {
+        let header: &dyn Header = match self {
+            HeaderType::Address(value) => value,
+            HeaderType::Date(value) => value,
+            HeaderType::ListId(value) => value,
+            HeaderType::Received(value) => value,
+            HeaderType::MessageId(value) => value,
+            HeaderType::Raw(value) => value,
+            HeaderType::Text(value) => value,
+            HeaderType::URL(value) => value,
+            HeaderType::ContentType(value) => value,
+            HeaderType::ContentLocation(value) => value,
+            HeaderType::Custom(value) => value.as_ref(),
+        };
+        write_header_limited(header, output, bytes_written, options)
+    }
+}
+
+/// Value of a custom header serialized by `HeaderType::try_clone`, which is
+/// written as is.
+struct Serialized(Vec<u8>);
+
+impl Header for Serialized {
+    fn write_header(&self, output: &mut dyn Write, _bytes_written: usize) -> io::Result<usize> {
+        output.write_all(&self.0)?;
+        Ok(0)
     }
 }
 
 impl<'x> HeaderType<'x> {
-    pub fn as_content_type(&self) -> Option<&ContentType> {
+    /// Returns a copy of the header. Custom headers, which cannot be cloned,
+    /// are replaced by their value as written after `bytes_written` bytes.
+    pub(crate) fn try_clone(
+        &self,
+        bytes_written: usize,
+        uppercase_charset: bool,
+    ) -> io::Result<HeaderType<'x>> {
+        Ok(match self {
+            HeaderType::Address(value) => HeaderType::Address(value.clone()),
+            HeaderType::Date(value) => HeaderType::Date(value.clone()),
+            HeaderType::ListId(value) => HeaderType::ListId(value.clone()),
+            HeaderType::Received(value) => HeaderType::Received(value.clone()),
+            HeaderType::MessageId(value) => HeaderType::MessageId(value.clone()),
+            HeaderType::Raw(value) => HeaderType::Raw(value.clone()),
+            HeaderType::Text(value) => HeaderType::Text(value.clone()),
+            HeaderType::URL(value) => HeaderType::URL(value.clone()),
+            HeaderType::ContentType(value) => HeaderType::ContentType(value.clone()),
+            HeaderType::ContentLocation(value) => HeaderType::ContentLocation(value.clone()),
+            HeaderType::Custom(value) => {
+                let mut output = Vec::new();
+                write_header_limited(
+                    value.as_ref(),
+                    &mut output,
+                    bytes_written,
+                    HeaderOptions { uppercase_charset },
+                )?;
+                HeaderType::Custom(Box::new(Serialized(output)))
+            }
+        })
+    }
+
+    /// Convert the header into an owned, `'static` version
+    pub fn into_owned(self) -> HeaderType<'static> {
+        match self {
+            HeaderType::Address(value) => HeaderType::Address(value.into_owned()),
+            HeaderType::Date(value) => HeaderType::Date(value),
+            HeaderType::ListId(value) => HeaderType::ListId(value.into_owned()),
+            HeaderType::Received(value) => HeaderType::Received(value.into_owned()),
+            HeaderType::MessageId(value) => HeaderType::MessageId(value.into_owned()),
+            HeaderType::Raw(value) => HeaderType::Raw(value.into_owned()),
+            HeaderType::Text(value) => HeaderType::Text(value.into_owned()),
+            HeaderType::URL(value) => HeaderType::URL(value.into_owned()),
+            HeaderType::ContentType(value) => HeaderType::ContentType(value.into_owned()),
+            HeaderType::ContentLocation(value) => HeaderType::ContentLocation(value.into_owned()),
+            HeaderType::Custom(value) => HeaderType::Custom(value),
+        }
+    }
+
+    pub fn as_content_type(&self) -> Option<&ContentType<'x>> {
         match self {
             HeaderType::ContentType(value) => Some(value),
             _ => None,
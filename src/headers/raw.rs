@@ -11,10 +11,13 @@
 
 use std::borrow::Cow;
 
+use crate::encoders::encode::check_header_value;
+
 use super::Header;
 
 /// Raw e-mail header.
 /// Raw headers are not encoded, only line-wrapped.
+#[derive(Debug, Clone)]
 pub struct Raw<'x> {
     pub raw: Cow<'x, str>,
 }
@@ -24,6 +27,13 @@ impl<'x> Raw<'x> {
     pub fn new(raw: impl Into<Cow<'x, str>>) -> Self {
         Self { raw: raw.into() }
     }
+
+    /// Convert the header into an owned, `'static` version
+    pub fn into_owned(self) -> Raw<'static> {
+        Raw {
+            raw: self.raw.into_owned().into(),
+        }
+    }
 }
 
 impl<'x, T> From<T> for Raw<'x>
@@ -38,9 +48,10 @@ where
 impl<'x> Header for Raw<'x> {
     fn write_header(
         &self,
-        mut output: impl std::io::Write,
+        output: &mut dyn std::io::Write,
         mut bytes_written: usize,
     ) -> std::io::Result<usize> {
+        check_header_value(&self.raw)?;
         for (pos, &ch) in self.raw.as_bytes().iter().enumerate() {
             if bytes_written >= 76 && ch.is_ascii_whitespace() && pos < self.raw.len() - 1 {
                 output.write_all(b"\r\n\t")?;
@@ -0,0 +1,161 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::borrow::Cow;
+
+use crate::encoders::encode::check_header_value;
+
+use super::{date::Date, Header};
+
+/// RFC5321 Received trace header
+#[derive(Debug, Clone)]
+pub struct Received<'x> {
+    pub from: Option<Cow<'x, str>>,
+    pub by: Option<Cow<'x, str>>,
+    pub via: Option<Cow<'x, str>>,
+    pub with: Option<Cow<'x, str>>,
+    pub id: Option<Cow<'x, str>>,
+    pub recipient: Option<Cow<'x, str>>,
+    pub date: Date,
+}
+
+impl<'x> Received<'x> {
+    /// Create a new Received header with the current date and no clauses.
+    pub fn new() -> Self {
+        Self {
+            from: None,
+            by: None,
+            via: None,
+            with: None,
+            id: None,
+            recipient: None,
+            date: Date::now(),
+        }
+    }
+
+    /// Set the `from` clause, for example `mail.example.com (mail.example.com [192.0.2.1])`.
+    pub fn from(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.from = Some(value.into());
+        self
+    }
+
+    /// Set the `by` clause, the host that received the message.
+    pub fn by(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.by = Some(value.into());
+        self
+    }
+
+    /// Set the `via` clause, the physical link used.
+    pub fn via(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.via = Some(value.into());
+        self
+    }
+
+    /// Set the `with` clause, the protocol used such as `ESMTPS`.
+    pub fn with(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.with = Some(value.into());
+        self
+    }
+
+    /// Set the `id` clause, the queue id assigned by the receiving host.
+    pub fn id(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.id = Some(value.into());
+        self
+    }
+
+    /// Set the `for` clause, the recipient address the message was received for.
+    pub fn recipient(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.recipient = Some(value.into());
+        self
+    }
+
+    /// Set the date the message was received on.
+    pub fn date(mut self, value: impl Into<Date>) -> Self {
+        self.date = value.into();
+        self
+    }
+
+    /// Convert the header into an owned, `'static` version
+    pub fn into_owned(self) -> Received<'static> {
+        Received {
+            from: self.from.map(|v| v.into_owned().into()),
+            by: self.by.map(|v| v.into_owned().into()),
+            via: self.via.map(|v| v.into_owned().into()),
+            with: self.with.map(|v| v.into_owned().into()),
+            id: self.id.map(|v| v.into_owned().into()),
+            recipient: self.recipient.map(|v| v.into_owned().into()),
+            date: self.date,
+        }
+    }
+}
+
+impl<'x> Default for Received<'x> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'x> Header for Received<'x> {
+    fn write_header(
+        &self,
+        output: &mut dyn std::io::Write,
+        mut bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        let recipient = self.recipient.as_ref().map(|recipient| {
+            if recipient.starts_with('<') {
+                Cow::from(recipient.as_ref())
+            } else {
+                Cow::from(format!("<{}>", recipient))
+            }
+        });
+        let mut is_first = true;
+        for (name, value) in [
+            ("from", &self.from),
+            ("by", &self.by),
+            ("via", &self.via),
+            ("with", &self.with),
+            ("id", &self.id),
+        ]
+        .iter()
+        .filter_map(|(name, value)| value.as_deref().map(|value| (*name, value)))
+        .chain(recipient.as_deref().map(|value| ("for", value)))
+        {
+            check_header_value(value)?;
+            if !is_first {
+                if bytes_written + name.len() + value.len() + 2 > 76 {
+                    output.write_all(b"\r\n\t")?;
+                    bytes_written = 1;
+                } else {
+                    output.write_all(b" ")?;
+                    bytes_written += 1;
+                }
+            }
+            output.write_all(name.as_bytes())?;
+            output.write_all(b" ")?;
+            output.write_all(value.as_bytes())?;
+            bytes_written += name.len() + value.len() + 1;
+            is_first = false;
+        }
+
+        let date = self.date.to_rfc822();
+        if is_first {
+            output.write_all(b";")?;
+        } else if bytes_written + date.len() + 2 > 76 {
+            output.write_all(b";\r\n\t")?;
+        } else {
+            output.write_all(b"; ")?;
+        }
+        output.write_all(date.as_bytes())?;
+        output.write_all(b"\r\n")?;
+
+        Ok(0)
+    }
+}
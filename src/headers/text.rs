@@ -13,13 +13,14 @@ use std::borrow::Cow;
 
 use crate::encoders::{
     base64::base64_encode,
-    encode::{get_encoding_type, EncodingType},
+    encode::{check_header_value, get_encoding_type, EncodingType},
     quoted_printable::quoted_printable_encode,
 };
 
-use super::Header;
+use super::{Header, HeaderOptions, MAX_LINE_LENGTH};
 
 /// Unstructured text e-mail header.
+#[derive(Debug, Clone)]
 pub struct Text<'x> {
     pub text: Cow<'x, str>,
 }
@@ -29,6 +30,13 @@ impl<'x> Text<'x> {
     pub fn new(text: impl Into<Cow<'x, str>>) -> Self {
         Self { text: text.into() }
     }
+
+    /// Convert the header into an owned, `'static` version
+    pub fn into_owned(self) -> Text<'static> {
+        Text {
+            text: self.text.into_owned().into(),
+        }
+    }
 }
 
 impl<'x, T> From<T> for Text<'x>
@@ -43,47 +51,213 @@ where
 impl<'x> Header for Text<'x> {
     fn write_header(
         &self,
-        mut output: impl std::io::Write,
-        mut bytes_written: usize,
+        output: &mut dyn std::io::Write,
+        bytes_written: usize,
     ) -> std::io::Result<usize> {
-        match get_encoding_type(self.text.as_bytes(), true, false) {
-            EncodingType::Base64 => {
-                for (pos, chunk) in self.text.as_bytes().chunks(76 - bytes_written).enumerate() {
-                    if pos > 0 {
-                        output.write_all(b"\t")?;
-                    }
-                    output.write_all(b"=?utf-8?B?")?;
-                    base64_encode(chunk, &mut output, true)?;
-                    output.write_all(b"?=\r\n")?;
+        self.write_header_with(output, bytes_written, HeaderOptions::default())
+    }
+
+    fn write_header_with(
+        &self,
+        output: &mut dyn std::io::Write,
+        bytes_written: usize,
+        options: HeaderOptions,
+    ) -> std::io::Result<usize> {
+        check_header_value(&self.text)?;
+        write_text(&self.text, output, bytes_written, options.uppercase_charset)
+    }
+}
+
+/// Returns the number of bytes a text header value would occupy once
+/// encoded, including any encoded-word overhead and folding but excluding
+/// the final CRLF, when written after `offset` bytes of the current line,
+/// usually the header name plus `": "`.
+pub fn encoded_word_len(text: &str, offset: usize) -> usize {
+    let mut output = Vec::with_capacity(text.len() * 2);
+    write_text(text, &mut output, offset, false).ok();
+    output.len().saturating_sub(2)
+}
+
+fn write_text(
+    text: &str,
+    mut output: &mut dyn std::io::Write,
+    mut bytes_written: usize,
+    uppercase_charset: bool,
+) -> std::io::Result<usize> {
+    let encoding_type = match get_encoding_type(text.as_bytes(), true, false) {
+        // Words too long to fit in a line are split into encoded-words.
+        EncodingType::None if has_long_word(text, bytes_written) => {
+            EncodingType::QuotedPrintable(true)
+        }
+        encoding_type => encoding_type,
+    };
+    match encoding_type {
+        EncodingType::Base64 => {
+            for (pos, chunk) in
+                split_encoded_words(text, bytes_written, 12, |chunk| chunk.len().div_ceil(3) * 4)
+                    .into_iter()
+                    .enumerate()
+            {
+                if pos > 0 {
+                    output.write_all(b"\t")?;
                 }
+                output.write_all(if uppercase_charset {
+                    b"=?UTF-8?B?"
+                } else {
+                    b"=?utf-8?B?"
+                })?;
+                base64_encode(chunk.as_bytes(), &mut output, true)?;
+                output.write_all(b"?=\r\n")?;
             }
-            EncodingType::QuotedPrintable(is_ascii) => {
-                for (pos, chunk) in self.text.as_bytes().chunks(76 - bytes_written).enumerate() {
-                    if pos > 0 {
-                        output.write_all(b"\t")?;
-                    }
-                    if !is_ascii {
-                        output.write_all(b"=?utf-8?Q?")?;
-                    } else {
-                        output.write_all(b"=?us-ascii?Q?")?;
-                    }
-                    quoted_printable_encode(chunk, &mut output, true, false)?;
-                    output.write_all(b"?=\r\n")?;
+        }
+        EncodingType::QuotedPrintable(is_ascii) => {
+            let prefix: &[u8] = match (is_ascii, uppercase_charset) {
+                (false, false) => b"=?utf-8?Q?",
+                (false, true) => b"=?UTF-8?Q?",
+                (true, false) => b"=?us-ascii?Q?",
+                (true, true) => b"=?US-ASCII?Q?",
+            };
+            for (pos, chunk) in
+                split_encoded_words(text, bytes_written, prefix.len() + 2, |chunk| {
+                    chunk
+                        .iter()
+                        .map(|&ch| {
+                            if matches!(ch, b'=' | b'?' | b'\t' | b'\r' | b'\n') || ch >= 127 {
+                                3
+                            } else {
+                                1
+                            }
+                        })
+                        .sum()
+                })
+                .into_iter()
+                .enumerate()
+            {
+                if pos > 0 {
+                    output.write_all(b"\t")?;
                 }
+                output.write_all(prefix)?;
+                quoted_printable_encode(chunk.as_bytes(), &mut output, true, false)?;
+                output.write_all(b"?=\r\n")?;
             }
-            EncodingType::None => {
-                for (pos, &ch) in self.text.as_bytes().iter().enumerate() {
-                    if bytes_written >= 76 && ch.is_ascii_whitespace() && pos < self.text.len() - 1
-                    {
-                        output.write_all(b"\r\n\t")?;
-                        bytes_written = 1;
-                    }
-                    output.write_all(&[ch])?;
-                    bytes_written += 1;
+        }
+        EncodingType::None => {
+            for (pos, &ch) in text.as_bytes().iter().enumerate() {
+                if bytes_written >= 76 && ch.is_ascii_whitespace() && pos < text.len() - 1 {
+                    output.write_all(b"\r\n\t")?;
+                    bytes_written = 1;
                 }
-                output.write_all(b"\r\n")?;
+                output.write_all(&[ch])?;
+                bytes_written += 1;
+            }
+            output.write_all(b"\r\n")?;
+        }
+    }
+    Ok(0)
+}
+
+/// Returns whether a word of the text would exceed the maximum line length
+/// even after folding at whitespace.
+fn has_long_word(text: &str, bytes_written: usize) -> bool {
+    text.split(|ch: char| ch.is_ascii_whitespace())
+        .enumerate()
+        .any(|(pos, word)| word.len() + if pos == 0 { bytes_written } else { 2 } > MAX_LINE_LENGTH)
+}
+
+/// Split a text into chunks to be written as separate encoded-words, one per
+/// line. Chunks never split a UTF-8 character, as required by RFC2047, and are
+/// sized so that each encoded line fits in 76 columns where possible.
+fn split_encoded_words(
+    text: &str,
+    bytes_written: usize,
+    overhead: usize,
+    encoded_len: impl Fn(&[u8]) -> usize,
+) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut max_len = 76usize.saturating_sub(bytes_written + overhead);
+
+    for (pos, ch) in text.char_indices() {
+        if pos > start && encoded_len(&text.as_bytes()[start..pos + ch.len_utf8()]) > max_len {
+            chunks.push(&text[start..pos]);
+            start = pos;
+            max_len = 76 - 1 - overhead;
+        }
+    }
+    chunks.push(&text[start..]);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headers::Header;
+
+    #[test]
+    fn encoded_word_len() {
+        let long_text = "é".repeat(10);
+        for (text, offset, expected_len) in [
+            // Plain text.
+            ("Hello world", 9, 11),
+            // Folded before the whitespace: "aaa", CRLF, TAB, " bbb".
+            ("aaa bbb", 76, 10),
+            // "=?utf-8?Q?" + "A=C3=B1o_nuevo" + "?=".
+            ("Año nuevo", 9, 26),
+            // "=?utf-8?B?" + 20 base64 chars for 13 bytes + "?=".
+            ("¡Año nuevo!", 9, 32),
+            // "=?utf-8?B?" + 28 base64 chars + "?=".
+            (long_text.as_str(), 0, 40),
+            // Split in two encoded-words of 9 and 1 characters, 24 and 4
+            // base64 chars: 36 bytes, CRLF, then TAB and 16 bytes.
+            (long_text.as_str(), 40, 55),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                super::encoded_word_len(text, *offset),
+                *expected_len,
+                "{:?} at {}",
+                text,
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn write_long_word() {
+        let base64_subject = "é".repeat(300);
+        let qp_subject = format!("Re:{}", "aé".repeat(200));
+        let ascii_subject = "x".repeat(1200);
+
+        for text in [&base64_subject, &qp_subject, &ascii_subject].iter() {
+            let mut output = Vec::new();
+            super::Text::new(text.as_str())
+                .write_header(&mut output, "Subject: ".len())
+                .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            let lines = output.split_terminator("\r\n").collect::<Vec<_>>();
+            assert!(lines.len() > 1, "{:?}", output);
+
+            for (pos, line) in lines.iter().enumerate() {
+                let word = if pos == 0 {
+                    assert!(line.len() + "Subject: ".len() <= 76, "{:?}", line);
+                    *line
+                } else {
+                    line.strip_prefix('\t').unwrap()
+                };
+                assert!(word.len() <= 75, "{:?}", word);
+                let (charset, encoded) = word
+                    .strip_prefix("=?")
+                    .and_then(|word| word.strip_suffix("?="))
+                    .and_then(|word| word.split_once('?'))
+                    .unwrap();
+                assert!(charset == "utf-8" || charset == "us-ascii");
+                assert!(encoded.starts_with("B?") || encoded.starts_with("Q?"));
             }
+
+            // The encoded-words decode back to the original subject.
+            let message = format!("Subject: {}\r\n", output);
+            let message = mail_parser::Message::parse(message.as_bytes()).unwrap();
+            assert_eq!(message.get_subject(), Some(text.as_str()));
         }
-        Ok(0)
     }
 }
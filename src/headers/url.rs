@@ -11,9 +11,13 @@
 
 use std::borrow::Cow;
 
+use crate::encoders::encode::check_header_value;
+
 use super::Header;
 
-/// URL header, used mostly on List-* headers
+/// URL header, used mostly on List-* headers. Multiple URLs are
+/// written enclosed in angle brackets and separated by commas.
+#[derive(Debug, Clone)]
 pub struct URL<'x> {
     pub url: Vec<Cow<'x, str>>,
 }
@@ -36,6 +40,17 @@ impl<'x> URL<'x> {
             url: urls.map(|s| s.into()).collect(),
         }
     }
+
+    /// Convert the header into an owned, `'static` version
+    pub fn into_owned(self) -> URL<'static> {
+        URL {
+            url: self
+                .url
+                .into_iter()
+                .map(|url| url.into_owned().into())
+                .collect(),
+        }
+    }
 }
 
 impl<'x> From<&'x str> for URL<'x> {
@@ -72,17 +87,18 @@ where
 impl<'x> Header for URL<'x> {
     fn write_header(
         &self,
-        mut output: impl std::io::Write,
+        output: &mut dyn std::io::Write,
         mut bytes_written: usize,
     ) -> std::io::Result<usize> {
         for (pos, url) in self.url.iter().enumerate() {
+            check_header_value(url)?;
             if pos > 0 {
-                if bytes_written + url.len() + 2 >= 76 {
-                    output.write_all(b"\r\n\t")?;
+                if bytes_written + url.len() + 4 >= 76 {
+                    output.write_all(b",\r\n\t")?;
                     bytes_written = 1;
                 } else {
-                    output.write_all(b" ")?;
-                    bytes_written += 1;
+                    output.write_all(b", ")?;
+                    bytes_written += 2;
                 }
             }
             output.write_all(b"<")?;
@@ -98,3 +114,38 @@ impl<'x> Header for URL<'x> {
         Ok(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::headers::Header;
+
+    #[test]
+    fn write_url_list() {
+        for (urls, expected_result) in [
+            (
+                vec!["http://example.com/archive"],
+                "<http://example.com/archive>\r\n",
+            ),
+            (
+                vec!["mailto:list@example.com", "http://example.com/help"],
+                "<mailto:list@example.com>, <http://example.com/help>\r\n",
+            ),
+            (
+                vec![
+                    "mailto:list-request@example.com?subject=unsubscribe",
+                    "https://www.example.com/lists/unsubscribe?id=12345",
+                ],
+                concat!(
+                    "<mailto:list-request@example.com?subject=unsubscribe>,\r\n\t",
+                    "<https://www.example.com/lists/unsubscribe?id=12345>\r\n"
+                ),
+            ),
+        ] {
+            let mut output = Vec::new();
+            super::URL::from(urls)
+                .write_header(&mut output, "List-Unsubscribe: ".len())
+                .unwrap();
+            assert_eq!(std::str::from_utf8(&output).unwrap(), expected_result);
+        }
+    }
+}
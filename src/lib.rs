@@ -175,6 +175,15 @@
 //!
 //! Please note that this library does not support parsing e-mail messages as this functionality is provided separately by the [`mail-parser`](https://crates.io/crates/mail-parser) crate.
 //!
+//! ## `no_std` support
+//!
+//! The message builder, MIME parts and header writers stream their output through
+//! `std::io::Write` and rely on `std::time` for generating dates and boundaries, so they
+//! require the `std` feature (enabled by default). When the crate is built with
+//! `default-features = false`, only the I/O-free encoding logic in the `encoders` module
+//! (such as `get_encoding_type` and `base64_encode_block`) is available, which compiles
+//! under `no_std`.
+//!
 //!
 //! ## Testing
 //!
@@ -207,38 +216,144 @@
 //!
 //! [COPYING]: https://github.com/stalwartlabs/mail-builder/blob/main/COPYING
 //!
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+pub mod dkim;
 #[forbid(unsafe_code)]
 pub mod encoders;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
 pub mod headers;
+#[cfg(feature = "std")]
+pub mod mdn;
+#[cfg(feature = "std")]
 pub mod mime;
+#[cfg(feature = "std")]
+pub mod personalize;
+#[cfg(feature = "std")]
+pub mod verify;
 
+#[cfg(feature = "std")]
 use std::{
     borrow::Cow,
     collections::BTreeMap,
     io::{self, Write},
+    sync::OnceLock,
 };
 
+#[cfg(feature = "std")]
+use dkim::{
+    sha256::Sha256,
+    signature::{sign_all, DkimSigner},
+};
+#[cfg(feature = "std")]
+pub use error::Error;
+#[cfg(feature = "std")]
 use headers::{
-    address::Address, date::Date, message_id::MessageId, text::Text, Header, HeaderType,
+    address::Address,
+    content_type::ContentType,
+    date::Date,
+    list_id::ListId,
+    message_id::{MessageId, MessageIdFormat},
+    raw::Raw,
+    received::Received,
+    text::Text,
+    url::URL,
+    Header, HeaderType,
 };
-use mime::{make_boundary, MimePart};
+#[cfg(feature = "std")]
+use mime::{make_boundary, write_header_value, BodyPart, MimeNode, MimePart, WriteOptions};
+
+/// Maximum size of an image downloaded by `inline_image_from_url`.
+#[cfg(feature = "ureq")]
+pub const MAX_IMAGE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Headers written at the top of the message in this order, all other
+/// headers follow sorted alphabetically.
+#[cfg(feature = "std")]
+const HEADER_ORDER: &[&str] = &[
+    "Return-Path",
+    "Received",
+    "Date",
+    "From",
+    "Sender",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Bcc",
+    "Subject",
+    "Message-ID",
+    "In-Reply-To",
+    "References",
+];
 
 /// Builds a RFC5322 compliant MIME email message.
+#[cfg(feature = "std")]
+#[derive(Debug)]
 pub struct MessageBuilder<'x> {
     pub headers: BTreeMap<Cow<'x, str>, Vec<HeaderType<'x>>>,
     pub html_body: Option<MimePart<'x>>,
     pub text_body: Option<MimePart<'x>>,
     pub attachments: Option<Vec<MimePart<'x>>>,
+    pub related_parts: Option<Vec<MimePart<'x>>>,
     pub body: Option<MimePart<'x>>,
     pub flowed: bool,
+    pub delsp: bool,
+    pub legacy_names: bool,
+    pub date_offset: i32,
+    pub simplify: bool,
+    pub assume_crlf: bool,
+    pub sort_attachments: bool,
+    pub raw_headers: Option<Cow<'x, [u8]>>,
+    pub escape_from: bool,
+    pub default_charset: Option<Cow<'x, str>>,
+    pub message_id_format: MessageIdFormat,
+    pub multipart_type: Option<ContentType<'x>>,
+    pub quoted_charset: bool,
+    pub fold_content_lines: bool,
+    pub strict: bool,
+    pub body_overwritten: bool,
+    pub dkim_signers: Vec<DkimSigner<'x>>,
+    pub omitted_headers: Vec<Cow<'x, str>>,
+    pub raw_mime_body: Option<(ContentType<'x>, Cow<'x, [u8]>)>,
+    pub empty_body: bool,
+    pub simple_mixed: bool,
+    pub boundary_prefix: Option<Cow<'x, str>>,
+    pub max_references: Option<usize>,
+    pub uppercase_charset_labels: bool,
+    pub generated_message_id: OnceLock<String>,
+}
+
+/// Statistics about the MIME parts of a message, see `MessageBuilder::stats`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MessageStats {
+    /// Number of MIME parts, excluding multipart containers.
+    pub parts: usize,
+    /// Number of attachments, which are parts with an `attachment` disposition
+    /// and non-text parts such as inline images.
+    pub attachments: usize,
+    /// Total size in bytes of the attachments, before encoding.
+    pub attachment_bytes: usize,
+    /// Whether the message has a text/plain body.
+    pub has_text: bool,
+    /// Whether the message has a text/html body.
+    pub has_html: bool,
+    /// Content-Type of the top-level MIME part, without parameters, or `None`
+    /// for messages written as headers only.
+    pub content_type: Option<String>,
 }
 
+#[cfg(feature = "std")]
 impl<'x> Default for MessageBuilder<'x> {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(feature = "std")]
 impl<'x> MessageBuilder<'x> {
     /// Create a new MessageBuilder.
     pub fn new() -> Self {
@@ -247,8 +362,33 @@ impl<'x> MessageBuilder<'x> {
             html_body: None,
             text_body: None,
             attachments: None,
+            related_parts: None,
             body: None,
             flowed: false,
+            delsp: false,
+            legacy_names: false,
+            date_offset: 0,
+            simplify: false,
+            assume_crlf: false,
+            sort_attachments: false,
+            raw_headers: None,
+            escape_from: false,
+            default_charset: None,
+            message_id_format: MessageIdFormat::default(),
+            multipart_type: None,
+            quoted_charset: false,
+            fold_content_lines: false,
+            strict: false,
+            body_overwritten: false,
+            dkim_signers: Vec::new(),
+            omitted_headers: Vec::new(),
+            raw_mime_body: None,
+            empty_body: false,
+            simple_mixed: false,
+            boundary_prefix: None,
+            max_references: None,
+            uppercase_charset_labels: false,
+            generated_message_id: OnceLock::new(),
         }
     }
 
@@ -273,6 +413,19 @@ impl<'x> MessageBuilder<'x> {
         self.header("Sender", value.into());
     }
 
+    /// Set a From header with several mailboxes, the authors of the message.
+    /// RFC5322 requires a Sender header in this case, so unless one is set
+    /// with `sender`, it is generated from the first mailbox.
+    pub fn from_multiple<T, U>(&mut self, addresses: T)
+    where
+        T: IntoIterator<Item = U>,
+        U: Into<Address<'x>>,
+    {
+        self.from(Address::new_list(
+            addresses.into_iter().map(Into::into).collect(),
+        ));
+    }
+
     /// Set the From header.
     pub fn from(&mut self, value: impl Into<Address<'x>>) {
         self.header("From", value.into());
@@ -298,35 +451,346 @@ impl<'x> MessageBuilder<'x> {
         self.header("Reply-To", value.into());
     }
 
+    /// Set the Reply-To header to a copy of the From address, so that
+    /// replies go to the sender. Returns `false` without changing the
+    /// message if no From address has been set yet.
+    pub fn reply_to_self(&mut self) -> bool {
+        let from = self
+            .headers_named("From")
+            .iter()
+            .find_map(|value| match value {
+                HeaderType::Address(address) => Some(address.clone()),
+                _ => None,
+            });
+        match from {
+            Some(from) => {
+                self.set_header("Reply-To", from);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the List-Archive header.
+    pub fn list_archive(&mut self, value: impl Into<URL<'x>>) {
+        self.header("List-Archive", value.into());
+    }
+
+    /// Set the List-Help header.
+    pub fn list_help(&mut self, value: impl Into<URL<'x>>) {
+        self.header("List-Help", value.into());
+    }
+
+    /// Set the RFC2919 List-ID header, with an optional description.
+    /// An error is returned when writing the message if the list id is
+    /// not a valid dot-atom such as `list.example.com`.
+    pub fn list_id(
+        &mut self,
+        description: Option<impl Into<Cow<'x, str>>>,
+        id: impl Into<Cow<'x, str>>,
+    ) {
+        self.header("List-ID", ListId::new(description, id));
+    }
+
+    /// Add a Received trace header. Received headers are written at the top
+    /// of the message, with the most recently added one first.
+    pub fn received(&mut self, value: Received<'x>) {
+        self.headers
+            .entry("Received".into())
+            .or_default()
+            .insert(0, value.into());
+    }
+
+    /// Set the List-Owner header.
+    pub fn list_owner(&mut self, value: impl Into<URL<'x>>) {
+        self.header("List-Owner", value.into());
+    }
+
+    /// Set the List-Post header.
+    pub fn list_post(&mut self, value: impl Into<URL<'x>>) {
+        self.header("List-Post", value.into());
+    }
+
+    /// Set the List-Post header to `NO`, indicating that posting to the
+    /// list is not allowed.
+    pub fn list_post_disabled(&mut self) {
+        self.header("List-Post", Raw::new("NO"));
+    }
+
+    /// Set the List-Subscribe header.
+    pub fn list_subscribe(&mut self, value: impl Into<URL<'x>>) {
+        self.header("List-Subscribe", value.into());
+    }
+
+    /// Set the List-Unsubscribe header.
+    pub fn list_unsubscribe(&mut self, value: impl Into<URL<'x>>) {
+        self.header("List-Unsubscribe", value.into());
+    }
+
     /// Set the Subject header.
     pub fn subject(&mut self, value: impl Into<Text<'x>>) {
         self.header("Subject", value.into());
     }
 
+    /// Set the User-Agent header, replacing any previous value.
+    pub fn user_agent(&mut self, value: impl Into<Text<'x>>) {
+        self.set_header("User-Agent", value.into());
+    }
+
+    /// Set the User-Agent header to `mail-builder/<version>`.
+    pub fn default_user_agent(&mut self) {
+        self.user_agent(concat!("mail-builder/", env!("CARGO_PKG_VERSION")));
+    }
+
+    /// Set the X-Mailer header, replacing any previous value.
+    pub fn x_mailer(&mut self, value: impl Into<Text<'x>>) {
+        self.set_header("X-Mailer", value.into());
+    }
+
     /// Set the Date header. If no Date header is set, one will be generated
     /// automatically.
     pub fn date(&mut self, value: impl Into<Date>) {
         self.header("Date", value.into());
     }
 
+    /// Set the timezone offset, in minutes east of UTC, of the automatically
+    /// generated Date header. Defaults to UTC (`+0000`).
+    pub fn date_timezone(&mut self, offset: i32) {
+        self.date_offset = offset;
+    }
+
     /// Add a custom header.
     pub fn header(&mut self, header: impl Into<Cow<'x, str>>, value: impl Into<HeaderType<'x>>) {
         self.headers
             .entry(header.into())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(value.into());
     }
 
+    /// Set a header, replacing any values previously added for it.
+    pub fn set_header(
+        &mut self,
+        header: impl Into<Cow<'x, str>>,
+        value: impl Into<HeaderType<'x>>,
+    ) {
+        let header = header.into();
+        self.remove_header(&header);
+        self.headers.insert(header, vec![value.into()]);
+    }
+
+    /// Remove all values of a header, matching its name case-insensitively.
+    /// Returns `true` if the header was present.
+    pub fn remove_header(&mut self, header: &str) -> bool {
+        let len = self.headers.len();
+        self.headers
+            .retain(|name, _| !name.eq_ignore_ascii_case(header));
+        self.headers.len() != len
+    }
+
+    /// Returns all the values set for a header, matching its name
+    /// case-insensitively, or an empty slice if the header is not present.
+    pub fn headers_named(&self, header: &str) -> &[HeaderType<'x>] {
+        self.headers
+            .get(header)
+            .or_else(|| {
+                self.headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(header))
+                    .map(|(_, values)| values)
+            })
+            .map_or(&[], |values| values.as_slice())
+    }
+
+    /// Leave out headers when the message is written, without removing them
+    /// from the builder. Names are matched case-insensitively, and a name
+    /// ending in `*` matches all headers starting with it, such as
+    /// `X-Internal-*`.
+    pub fn omit_headers(&mut self, names: &[&str]) {
+        self.omitted_headers
+            .extend(names.iter().map(|name| Cow::from(name.to_string())));
+    }
+
+    fn is_omitted(&self, header: &str) -> bool {
+        self.omitted_headers
+            .iter()
+            .any(|name| match name.strip_suffix('*') {
+                Some(prefix) => header
+                    .get(..prefix.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(prefix)),
+                None => header.eq_ignore_ascii_case(name),
+            })
+    }
+
     /// Mark body as format=flowed
     pub fn format_flowed(&mut self) {
         self.flowed = true
     }
 
+    /// Mark body as format=flowed with delsp=yes (RFC3676)
+    pub fn format_flowed_delsp(&mut self) {
+        self.flowed = true;
+        self.delsp = true;
+    }
+
+    /// Also include the filename of attachments as the Content-Type `name`
+    /// parameter, for compatibility with legacy clients.
+    pub fn legacy_attachment_names(&mut self) {
+        self.legacy_names = true
+    }
+
+    /// Remove redundant multipart wrappers containing a single body part
+    /// when assembling the message.
+    pub fn simplify(&mut self) {
+        self.simplify = true
+    }
+
+    /// Assume that all text bodies already use CRLF line endings and write
+    /// them verbatim when no encoding is required, skipping line ending
+    /// normalization. Bodies containing bare LFs will produce non-compliant
+    /// messages when this option is enabled.
+    pub fn assume_crlf(&mut self) {
+        self.assume_crlf = true
+    }
+
+    /// Protect text body lines starting with `From ` from being mangled by
+    /// mbox storage, by encoding such bodies as quoted-printable and writing
+    /// the leading `F` as `=46`.
+    pub fn escape_from_lines(&mut self) {
+        self.escape_from = true
+    }
+
+    /// Enable strict mode, in which writing the message fails if the text,
+    /// HTML or custom body was set more than once, which usually indicates
+    /// a bug in the code building the message.
+    pub fn strict(&mut self) {
+        self.strict = true
+    }
+
+    /// Sign the message with a DKIM key. Signers can be added more than
+    /// once, for example during key rotation, in which case one
+    /// DKIM-Signature header is prepended for each of them.
+    pub fn dkim_signer(&mut self, signer: DkimSigner<'x>) {
+        self.dkim_signers.push(signer);
+    }
+
+    /// Write messages without any body or attachment as headers only, with
+    /// no Content-Type and a zero-length body. By default such messages get
+    /// a `text/plain` body containing a single line break.
+    pub fn empty_body(&mut self) {
+        self.empty_body = true
+    }
+
+    /// When a message has text and HTML bodies as well as attachments, place
+    /// both bodies directly in the `multipart/mixed` part instead of nesting
+    /// them in a `multipart/alternative` part. This layout is only meant for
+    /// compatibility with clients that mishandle the standard one.
+    pub fn simple_mixed(&mut self) {
+        self.simple_mixed = true
+    }
+
+    /// Start generated MIME boundaries with a prefix, such as a company
+    /// name, to make them easier to recognize when debugging. Characters
+    /// that are not allowed in boundaries are removed from the prefix.
+    pub fn boundary_prefix(&mut self, prefix: impl AsRef<str>) {
+        let prefix = prefix
+            .as_ref()
+            .chars()
+            .filter(|ch| ch.is_ascii_alphanumeric() || "'()+_,-./:=?".contains(*ch))
+            .collect::<String>();
+        self.boundary_prefix = if !prefix.is_empty() {
+            Some(prefix.into())
+        } else {
+            None
+        };
+    }
+
+    /// Limit the References header to `max_ids` message ids when the message
+    /// is written, keeping the first id of the thread and the most recent
+    /// ones while dropping those in the middle, so that replies to long
+    /// threads do not produce enormous headers.
+    pub fn max_references(&mut self, max_ids: usize) {
+        self.max_references = Some(max_ids);
+    }
+
+    /// Fold content lines longer than 75 octets in text/vcard and text/calendar
+    /// parts, as required by RFC6350 and RFC5545, before they are encoded.
+    pub fn fold_content_lines(&mut self) {
+        self.fold_content_lines = true
+    }
+
+    /// Set the charset of the text bodies and text attachments added using
+    /// this builder, instead of `utf-8`. As text is always written in UTF-8,
+    /// the charset is only applied to parts containing ASCII text, unless it is
+    /// an alias of UTF-8 or `utf-7`, in which case the text is converted to UTF-7.
+    pub fn default_charset(&mut self, charset: impl Into<Cow<'x, str>>) {
+        self.default_charset = Some(charset.into());
+    }
+
+    /// Set the format of the Message-ID generated when the message does not
+    /// have one. Formats other than `MessageIdFormat::Boundary` use the domain
+    /// of the From address, or `localhost` when there is no sender.
+    pub fn message_id_format(&mut self, format: MessageIdFormat) {
+        self.message_id_format = format;
+    }
+
+    /// Returns the Message-ID that is generated for the message when no
+    /// Message-ID header is set, or `None` if one is set. The ID is generated
+    /// on the first call and reused when the message is written, so it can be
+    /// stored to correlate later replies. Formats that include a domain take
+    /// it from the From address, which should be set before calling this.
+    pub fn generated_message_id(&self) -> Option<String> {
+        if self
+            .headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case("Message-ID"))
+        {
+            return None;
+        }
+        Some(
+            self.generated_message_id
+                .get_or_init(|| {
+                    self.message_id_format
+                        .generate(self.sender_domain().unwrap_or("localhost"))
+                })
+                .clone(),
+        )
+    }
+
+    /// Set the Content-Type used instead of `multipart/mixed` for the top-level
+    /// part when the message has attachments, such as `multipart/report` with
+    /// a `report-type` parameter for delivery status notifications.
+    pub fn multipart_type(&mut self, value: ContentType<'x>) {
+        self.multipart_type = Some(value);
+    }
+
+    /// Compatibility mode that writes the `charset` parameter of all parts as
+    /// a quoted-string, such as `charset="utf-8"`, rather than as a token.
+    pub fn quoted_charset(&mut self) {
+        self.quoted_charset = true;
+    }
+
+    /// Compatibility mode that writes the charset label of encoded-words in
+    /// uppercase, such as `=?UTF-8?B?`, for old parsers that compare it case
+    /// sensitively. By default it is written in lowercase, such as `=?utf-8?B?`.
+    pub fn uppercase_charset_labels(&mut self) {
+        self.uppercase_charset_labels = true;
+    }
+
+    /// Sort attachments and inline parts alphabetically by filename when
+    /// assembling the message, rather than keeping them in insertion order.
+    /// Parts without a filename are placed first.
+    pub fn sort_attachments_by_name(&mut self) {
+        self.sort_attachments = true
+    }
+
     /// Set the plain text body of the message. Note that only one plain text body
     /// per message can be set using this function.
     /// To build more complex MIME body structures, use the `body` method instead.
     pub fn text_body(&mut self, value: impl Into<Cow<'x, str>>) {
-        if self.flowed {
+        self.body_overwritten |= self.text_body.is_some();
+        if self.delsp {
+            self.text_body = Some(MimePart::new_text_flowed(value).delsp());
+        } else if self.flowed {
             self.text_body = Some(MimePart::new_text_flowed(value));
         } else {
             self.text_body = Some(MimePart::new_text(value));
@@ -337,9 +801,30 @@ impl<'x> MessageBuilder<'x> {
     /// per message can be set using this function.
     /// To build more complex MIME body structures, use the `body` method instead.
     pub fn html_body(&mut self, value: impl Into<Cow<'x, str>>) {
+        self.body_overwritten |= self.html_body.is_some();
         self.html_body = Some(MimePart::new_html(value));
     }
 
+    /// Add a parameter to the Content-Type header of the plain text body.
+    /// The text body has to be set before calling this method.
+    pub fn text_body_attribute(
+        &mut self,
+        key: impl Into<Cow<'x, str>>,
+        value: impl Into<Cow<'x, str>>,
+    ) {
+        self.text_body = self.text_body.take().map(|part| part.attribute(key, value));
+    }
+
+    /// Add a parameter to the Content-Type header of the HTML body.
+    /// The HTML body has to be set before calling this method.
+    pub fn html_body_attribute(
+        &mut self,
+        key: impl Into<Cow<'x, str>>,
+        value: impl Into<Cow<'x, str>>,
+    ) {
+        self.html_body = self.html_body.take().map(|part| part.attribute(key, value));
+    }
+
     /// Add a binary attachment to the message.
     pub fn binary_attachment(
         &mut self,
@@ -347,9 +832,14 @@ impl<'x> MessageBuilder<'x> {
         filename: impl Into<Cow<'x, str>>,
         value: impl Into<Cow<'x, [u8]>>,
     ) {
+        let part = MimePart::new_binary(content_type, value);
         self.attachments
             .get_or_insert_with(Vec::new)
-            .push(MimePart::new_binary(content_type, value).attachment(filename));
+            .push(if self.legacy_names {
+                part.attachment_with_name(filename)
+            } else {
+                part.attachment(filename)
+            });
     }
 
     /// Add a text attachment to the message.
@@ -359,9 +849,81 @@ impl<'x> MessageBuilder<'x> {
         filename: impl Into<Cow<'x, str>>,
         value: impl Into<Cow<'x, str>>,
     ) {
+        let part = MimePart::new_text_other(content_type, value);
+        self.attachments
+            .get_or_insert_with(Vec::new)
+            .push(if self.legacy_names {
+                part.attachment_with_name(filename)
+            } else {
+                part.attachment(filename)
+            });
+    }
+
+    /// Add a text attachment with the given charset parameter, or without
+    /// one when `charset` is `None`, for types such as
+    /// `application/x-ndjson` that do not take a charset.
+    pub fn text_attachment_with_charset(
+        &mut self,
+        content_type: impl Into<Cow<'x, str>>,
+        filename: impl Into<Cow<'x, str>>,
+        value: impl Into<Cow<'x, str>>,
+        charset: Option<impl Into<Cow<'x, str>>>,
+    ) {
+        let part = MimePart::new_text_with_charset(content_type, charset, value);
+        self.attachments
+            .get_or_insert_with(Vec::new)
+            .push(if self.legacy_names {
+                part.attachment_with_name(filename)
+            } else {
+                part.attachment(filename)
+            });
+    }
+
+    /// Add a contact card as a `contact.vcf` attachment of type text/vcard.
+    /// Long vCard lines are folded as required by RFC6350.
+    pub fn vcard(&mut self, value: impl AsRef<str>) {
+        let part = MimePart::new_vcard(value);
+        self.attachments
+            .get_or_insert_with(Vec::new)
+            .push(if self.legacy_names {
+                part.attachment_with_name("contact.vcf")
+            } else {
+                part.attachment("contact.vcf")
+            });
+    }
+
+    /// Add a binary attachment whose contents are read from an async reader
+    /// and base64 encoded on the fly by `write_to_async`, so that large
+    /// attachments are never fully loaded in memory. Messages with async
+    /// attachments cannot be written with `write_to` nor DKIM signed.
+    #[cfg(feature = "tokio")]
+    pub fn async_attachment(
+        &mut self,
+        content_type: impl Into<Cow<'x, str>>,
+        filename: impl Into<Cow<'x, str>>,
+        value: impl tokio::io::AsyncRead + Send + Unpin + 'static,
+    ) {
+        let part = MimePart::new_async(content_type, value);
+        self.attachments
+            .get_or_insert_with(Vec::new)
+            .push(if self.legacy_names {
+                part.attachment_with_name(filename)
+            } else {
+                part.attachment(filename)
+            });
+    }
+
+    /// Add an iCalendar object as a downloadable attachment of type
+    /// text/calendar, keeping its `METHOD` in the Content-Type.
+    pub fn ics_attachment(&mut self, filename: impl Into<Cow<'x, str>>, value: impl AsRef<str>) {
+        let part = MimePart::new_ics(value);
         self.attachments
             .get_or_insert_with(Vec::new)
-            .push(MimePart::new_text_other(content_type, value).attachment(filename));
+            .push(if self.legacy_names {
+                part.attachment_with_name(filename)
+            } else {
+                part.attachment(filename)
+            });
     }
 
     /// Add an inline binary to the message.
@@ -376,103 +938,927 @@ impl<'x> MessageBuilder<'x> {
             .push(MimePart::new_binary(content_type, value).inline().cid(cid));
     }
 
-    /// Set a custom MIME body structure.
+    /// Add an inline binary with both a Content-ID and a filename in its
+    /// Content-Disposition, which some clients need to display embedded
+    /// images properly.
+    pub fn binary_inline_with_filename(
+        &mut self,
+        content_type: impl Into<Cow<'x, str>>,
+        cid: impl Into<Cow<'x, str>>,
+        filename: impl Into<Cow<'x, str>>,
+        value: impl Into<Cow<'x, [u8]>>,
+    ) {
+        self.attachments.get_or_insert_with(Vec::new).push(
+            MimePart::new_binary(content_type, value)
+                .inline_with_filename(filename)
+                .cid(cid),
+        );
+    }
+
+    /// Add an inline binary that is referenced by the HTML body, such as a font
+    /// or an image used from CSS. Unlike `binary_inline`, the part is placed
+    /// together with the HTML body inside a `multipart/related` part rather than
+    /// as a top-level attachment. If the message has no HTML body, the part is
+    /// added to the attachments instead.
+    pub fn binary_related(
+        &mut self,
+        content_type: impl Into<Cow<'x, str>>,
+        cid: impl Into<Cow<'x, str>>,
+        value: impl Into<Cow<'x, [u8]>>,
+    ) {
+        self.related_parts
+            .get_or_insert_with(Vec::new)
+            .push(MimePart::new_binary(content_type, value).inline().cid(cid));
+    }
+
+    /// Add a binary, such as an image, both inline and as an attachment, for
+    /// clients that only display one of them. The inline part is referenced
+    /// by the HTML body through its Content-ID and placed with it in a
+    /// `multipart/related` part, while the attachment is added to the
+    /// `multipart/mixed` part. Both parts share the same filename.
+    pub fn binary_inline_and_attachment(
+        &mut self,
+        content_type: impl Into<Cow<'x, str>>,
+        cid: impl Into<Cow<'x, str>>,
+        filename: impl Into<Cow<'x, str>>,
+        value: impl Into<Cow<'x, [u8]>>,
+    ) {
+        let content_type = content_type.into();
+        let filename = filename.into();
+        let value = value.into();
+        self.related_parts.get_or_insert_with(Vec::new).push(
+            MimePart::new_binary(content_type.clone(), value.clone())
+                .inline_with_filename(filename.clone())
+                .cid(cid),
+        );
+        self.binary_attachment(content_type, filename, value);
+    }
+
+    /// Add an inline binary with an automatically generated Content-ID,
+    /// which is returned so it can be referenced from the HTML body
+    /// using a `cid:` URL. The domain part of the Content-ID is taken from
+    /// the From address, so the sender should be set before calling this.
+    pub fn binary_inline_auto_cid(
+        &mut self,
+        content_type: impl Into<Cow<'x, str>>,
+        value: impl Into<Cow<'x, [u8]>>,
+    ) -> String {
+        let cid = format!(
+            "{}@{}",
+            make_boundary(),
+            self.sender_domain().unwrap_or("localhost")
+        );
+        self.binary_inline(content_type, cid.clone(), value);
+        cid
+    }
+
+    /// Download an image and add it as an inline part, returning its
+    /// generated Content-ID as `binary_inline_auto_cid` does. The content
+    /// type is taken from the response, which must be an `image/*` type,
+    /// and images larger than `MAX_IMAGE_SIZE` are rejected.
+    #[cfg(feature = "ureq")]
+    pub fn inline_image_from_url(&mut self, url: &str) -> io::Result<String> {
+        use std::io::Read;
+
+        let response = ureq::get(url).call().map_err(io::Error::other)?;
+        let content_type = response.content_type().to_ascii_lowercase();
+        if !content_type.starts_with("image/") {
+            return Err(Error::NotAnImage(content_type).into());
+        }
+        let mut value = Vec::new();
+        response
+            .into_reader()
+            .take(MAX_IMAGE_SIZE as u64 + 1)
+            .read_to_end(&mut value)?;
+        if value.len() > MAX_IMAGE_SIZE {
+            return Err(Error::ImageTooLarge.into());
+        }
+        Ok(self.binary_inline_auto_cid(content_type, value))
+    }
+
+    /// Returns the Sender header to generate when the From header contains
+    /// more than one mailbox and no Sender is set, see `from_multiple`.
+    fn generated_sender(&self) -> Option<Address<'x>> {
+        if self
+            .headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case("Sender"))
+        {
+            return None;
+        }
+        let from = self
+            .headers
+            .get("From")?
+            .iter()
+            .filter_map(|value| match value {
+                HeaderType::Address(address) => Some(address),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if from
+            .iter()
+            .map(|address| address.mailbox_count())
+            .sum::<usize>()
+            > 1
+        {
+            from.iter()
+                .find_map(|address| address.first_address())
+                .map(|address| Address::Address(address.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the domain of the first From address, if any.
+    fn sender_domain(&self) -> Option<&str> {
+        self.headers
+            .get("From")
+            .and_then(|values| {
+                values.iter().find_map(|value| match value {
+                    HeaderType::Address(address) => address.first_email(),
+                    _ => None,
+                })
+            })
+            .and_then(|email| email.rsplit_once('@'))
+            .map(|(_, domain)| domain)
+    }
+
+    /// Add a block of already formatted headers, such as a chain of
+    /// `Received` headers, which is written verbatim before all other headers.
+    /// Each line must be terminated with a CRLF and the block cannot contain
+    /// an empty line, otherwise an error is returned when the message is written.
+    pub fn raw_headers(&mut self, value: impl Into<Cow<'x, [u8]>>) {
+        self.raw_headers = Some(value.into());
+    }
+
+    /// Set a custom MIME body structure. The text and HTML bodies set using
+    /// this builder are ignored, while attachments and related parts are
+    /// appended to the custom body if it is a multipart/mixed part, otherwise
+    /// the custom body and the attachments are wrapped in a multipart/mixed part.
     pub fn body(&mut self, value: MimePart<'x>) {
+        self.body_overwritten |= self.body.is_some();
         self.body = Some(value);
     }
 
-    /// Build the message.
-    pub fn write_to(self, mut output: impl Write) -> io::Result<()> {
-        let mut has_date = false;
-        let mut has_message_id = false;
+    /// Set a complete MIME body built elsewhere, such as by a gateway. The
+    /// Content-Type is written as a header and the bytes following the
+    /// header section are written verbatim, so only the top-level headers
+    /// are managed by the builder. Writing the message fails if any other
+    /// body or attachment is also set.
+    pub fn raw_mime_body(
+        &mut self,
+        content_type: ContentType<'x>,
+        value: impl Into<Cow<'x, [u8]>>,
+    ) {
+        self.raw_mime_body = Some((content_type, value.into()));
+    }
 
-        for (header_name, header_values) in &self.headers {
-            if !has_date && header_name == "Date" {
-                has_date = true;
-            } else if !has_message_id && header_name == "Message-ID" {
-                has_message_id = true;
-            }
+    /// Convert the builder into an owned, `'static` version that does not
+    /// borrow any of its inputs, allowing it to be stored or sent across threads.
+    pub fn into_owned(self) -> MessageBuilder<'static> {
+        MessageBuilder {
+            headers: self
+                .headers
+                .into_iter()
+                .map(|(name, values)| {
+                    (
+                        name.into_owned().into(),
+                        values.into_iter().map(|v| v.into_owned()).collect(),
+                    )
+                })
+                .collect(),
+            html_body: self.html_body.map(|p| p.into_owned()),
+            text_body: self.text_body.map(|p| p.into_owned()),
+            attachments: self
+                .attachments
+                .map(|parts| parts.into_iter().map(|p| p.into_owned()).collect()),
+            related_parts: self
+                .related_parts
+                .map(|parts| parts.into_iter().map(|p| p.into_owned()).collect()),
+            body: self.body.map(|p| p.into_owned()),
+            flowed: self.flowed,
+            delsp: self.delsp,
+            legacy_names: self.legacy_names,
+            date_offset: self.date_offset,
+            simplify: self.simplify,
+            assume_crlf: self.assume_crlf,
+            sort_attachments: self.sort_attachments,
+            raw_headers: self.raw_headers.map(|raw| raw.into_owned().into()),
+            escape_from: self.escape_from,
+            default_charset: self.default_charset.map(|c| c.into_owned().into()),
+            message_id_format: self.message_id_format,
+            multipart_type: self.multipart_type.map(|ct| ct.into_owned()),
+            quoted_charset: self.quoted_charset,
+            fold_content_lines: self.fold_content_lines,
+            strict: self.strict,
+            body_overwritten: self.body_overwritten,
+            dkim_signers: self
+                .dkim_signers
+                .into_iter()
+                .map(|signer| signer.into_owned())
+                .collect(),
+            omitted_headers: self
+                .omitted_headers
+                .into_iter()
+                .map(|name| name.into_owned().into())
+                .collect(),
+            raw_mime_body: self.raw_mime_body.map(|(content_type, contents)| {
+                (content_type.into_owned(), contents.into_owned().into())
+            }),
+            empty_body: self.empty_body,
+            simple_mixed: self.simple_mixed,
+            boundary_prefix: self
+                .boundary_prefix
+                .map(|prefix| prefix.into_owned().into()),
+            max_references: self.max_references,
+            uppercase_charset_labels: self.uppercase_charset_labels,
+            generated_message_id: self.generated_message_id,
+        }
+    }
 
-            for header_value in header_values {
-                output.write_all(header_name.as_bytes())?;
-                output.write_all(b": ")?;
-                header_value.write_header(&mut output, header_name.len() + 2)?;
-            }
+    /// Replace all `{{name}}` variables found in text headers and text bodies
+    /// with the values provided.
+    pub fn substitute(mut self, vars: &personalize::Vars) -> Self {
+        for value in self.headers.values_mut().flatten() {
+            personalize::substitute_header(value, vars);
         }
+        for part in self
+            .text_body
+            .iter_mut()
+            .chain(self.html_body.iter_mut())
+            .chain(self.body.iter_mut())
+            .chain(self.attachments.iter_mut().flatten())
+            .chain(self.related_parts.iter_mut().flatten())
+        {
+            personalize::substitute_part(part, vars);
+        }
+        self
+    }
 
-        if !has_message_id {
-            output.write_all(b"Message-ID: <")?;
-            output.write_all(make_boundary().as_bytes())?;
-            output.write_all(b">\r\n")?;
+    /// Returns the Content-Type, without parameters, of the top-level MIME part
+    /// that will be produced when the message is written, or `None` when the
+    /// message is written as headers only and has no Content-Type.
+    pub fn content_type(&self) -> Option<String> {
+        let part_type = |part: &MimePart| {
+            let part = if self.simplify {
+                part.simplified()
+            } else {
+                part
+            };
+            part.content_type().unwrap_or("text/plain").to_string()
+        };
+
+        if self.is_headers_only() {
+            return None;
+        } else if let Some((content_type, _)) = &self.raw_mime_body {
+            return Some(content_type.c_type.to_string());
         }
 
-        if !has_date {
-            output.write_all(b"Date: ")?;
-            output.write_all(b"Thu, 13 Feb 1969 23:32:54 -0330");
-            output.write_all(b"\r\n")?;
+        if let Some(body) = &self.body {
+            return Some(
+                if self
+                    .attachments
+                    .iter()
+                    .chain(self.related_parts.iter())
+                    .flatten()
+                    .next()
+                    .is_none()
+                {
+                    part_type(body)
+                } else if body.is_mixed() {
+                    "multipart/mixed".to_string()
+                } else {
+                    self.multipart_type
+                        .as_ref()
+                        .map_or("multipart/mixed", |ct| ct.c_type.as_ref())
+                        .to_string()
+                },
+            );
+        }
+        let has_related = self.related_parts.as_ref().map_or(0, |parts| parts.len()) > 0;
+        let attachments = self.attachments.as_ref().map_or(0, |parts| parts.len())
+            + if self.html_body.is_none() {
+                self.related_parts.as_ref().map_or(0, |parts| parts.len())
+            } else {
+                0
+            };
+        Some(match (&self.text_body, &self.html_body, attachments) {
+            (None, None, 1) if self.simplify => part_type(
+                self.attachments
+                    .iter()
+                    .chain(self.related_parts.iter())
+                    .flatten()
+                    .next()
+                    .unwrap(),
+            ),
+            (_, _, 1..) => self
+                .multipart_type
+                .as_ref()
+                .map_or("multipart/mixed", |ct| ct.c_type.as_ref())
+                .to_string(),
+            (Some(_), Some(_), 0) => "multipart/alternative".to_string(),
+            (Some(text), None, 0) => part_type(text),
+            (None, Some(_), 0) if has_related => "multipart/related".to_string(),
+            (None, Some(html), 0) => part_type(html),
+            (None, None, 0) => "text/plain".to_string(),
+        })
+    }
+
+    /// Returns a SHA-256 fingerprint of the message, which can be used to
+    /// detect duplicate messages. It is the hash of the message as written by
+    /// `write_to`, without the Date and Message-ID headers generated when
+    /// writing it nor any DKIM signatures, and with numbered MIME boundaries,
+    /// so that equivalent builders always produce the same fingerprint.
+    /// The contents of async attachments, which are only read when the
+    /// message is written, are not included.
+    pub fn fingerprint(&self) -> io::Result<[u8; 32]> {
+        let mut message = self.borrowed()?;
+        message.dkim_signers.clear();
+        let mut hasher = Sha256::new();
+        mime::with_fixed_boundaries(|| message.write_message(&mut hasher, false))?;
+        Ok(hasher.finalize())
+    }
+
+    /// Returns a copy of the builder borrowing its contents, see
+    /// `MimePart::borrowed`.
+    fn borrowed(&self) -> io::Result<MessageBuilder<'_>> {
+        let uppercase_charset = self.uppercase_charset_labels;
+        fn parts<'y>(
+            parts: &'y Option<Vec<MimePart<'_>>>,
+            uppercase_charset: bool,
+        ) -> io::Result<Option<Vec<MimePart<'y>>>> {
+            parts
+                .as_ref()
+                .map(|parts| {
+                    parts
+                        .iter()
+                        .map(|part| part.borrowed(uppercase_charset))
+                        .collect()
+                })
+                .transpose()
         }
 
-        (if let Some(body) = self.body {
-            body
+        Ok(MessageBuilder {
+            headers: self
+                .headers
+                .iter()
+                .map(|(name, values)| {
+                    Ok((
+                        name.as_ref().into(),
+                        values
+                            .iter()
+                            .map(|value| value.try_clone(name.len() + 2, uppercase_charset))
+                            .collect::<io::Result<_>>()?,
+                    ))
+                })
+                .collect::<io::Result<_>>()?,
+            html_body: self
+                .html_body
+                .as_ref()
+                .map(|part| part.borrowed(uppercase_charset))
+                .transpose()?,
+            text_body: self
+                .text_body
+                .as_ref()
+                .map(|part| part.borrowed(uppercase_charset))
+                .transpose()?,
+            attachments: parts(&self.attachments, uppercase_charset)?,
+            related_parts: parts(&self.related_parts, uppercase_charset)?,
+            body: self
+                .body
+                .as_ref()
+                .map(|part| part.borrowed(uppercase_charset))
+                .transpose()?,
+            flowed: self.flowed,
+            delsp: self.delsp,
+            legacy_names: self.legacy_names,
+            date_offset: self.date_offset,
+            simplify: self.simplify,
+            assume_crlf: self.assume_crlf,
+            sort_attachments: self.sort_attachments,
+            raw_headers: self.raw_headers.as_deref().map(Cow::from),
+            escape_from: self.escape_from,
+            default_charset: self.default_charset.as_deref().map(Cow::from),
+            message_id_format: self.message_id_format,
+            multipart_type: self.multipart_type.clone(),
+            quoted_charset: self.quoted_charset,
+            fold_content_lines: self.fold_content_lines,
+            strict: self.strict,
+            body_overwritten: self.body_overwritten,
+            dkim_signers: self.dkim_signers.clone(),
+            omitted_headers: self.omitted_headers.clone(),
+            raw_mime_body: self
+                .raw_mime_body
+                .as_ref()
+                .map(|(content_type, contents)| (content_type.clone(), contents.as_ref().into())),
+            empty_body: self.empty_body,
+            simple_mixed: self.simple_mixed,
+            boundary_prefix: self.boundary_prefix.clone(),
+            max_references: self.max_references,
+            uppercase_charset_labels: self.uppercase_charset_labels,
+            generated_message_id: self.generated_message_id.clone(),
+        })
+    }
+
+    /// Returns statistics about the MIME parts of the message, computed
+    /// from the assembled MIME tree without writing it. The size of file
+    /// parts is read from the file system, returning any error.
+    pub fn stats(&self) -> io::Result<MessageStats> {
+        let content_type = self.content_type();
+        if self.is_headers_only() {
+            return Ok(MessageStats {
+                content_type,
+                ..Default::default()
+            });
+        }
+        let message = self.borrowed()?;
+        let body = if let Some((content_type, contents)) = message.raw_mime_body {
+            MimePart::new(content_type, BodyPart::Binary(contents))
         } else {
-            match (self.text_body, self.html_body, self.attachments) {
-                (Some(text), Some(html), Some(attachments)) => {
-                    let mut parts = Vec::with_capacity(attachments.len() + 1);
-                    parts.push(MimePart::new_multipart(
-                        "multipart/alternative",
-                        vec![text, html],
-                    ));
-                    parts.extend(attachments);
+            message.into_body().0
+        };
 
-                    MimePart::new_multipart("multipart/mixed", parts)
-                }
-                (Some(text), Some(html), None) => {
-                    MimePart::new_multipart("multipart/alternative", vec![text, html])
-                }
-                (Some(text), None, Some(attachments)) => {
-                    let mut parts = Vec::with_capacity(attachments.len() + 1);
-                    parts.push(text);
-                    parts.extend(attachments);
-                    MimePart::new_multipart("multipart/mixed", parts)
-                }
-                (Some(text), None, None) => text,
-                (None, Some(html), Some(attachments)) => {
-                    let mut parts = Vec::with_capacity(attachments.len() + 1);
-                    parts.push(html);
-                    parts.extend(attachments);
-                    MimePart::new_multipart("multipart/mixed", parts)
+        let mut stats = MessageStats {
+            content_type,
+            ..Default::default()
+        };
+        let mut parts = vec![&body];
+        while let Some(part) = parts.pop() {
+            let size = match &part.contents {
+                BodyPart::Multipart(subparts) => {
+                    parts.extend(subparts);
+                    continue;
                 }
-                (None, Some(html), None) => html,
-                (None, None, Some(attachments)) => {
-                    MimePart::new_multipart("multipart/mixed", attachments)
+                BodyPart::Text(text) => text.len(),
+                BodyPart::Binary(binary) => binary.len(),
+                #[cfg(feature = "bytes")]
+                BodyPart::Bytes(bytes) => bytes.len(),
+                BodyPart::File(path) => std::fs::metadata(path)?.len() as usize,
+                BodyPart::Verbatim { body, .. } => body.len(),
+                // The size of streamed contents is not known in advance.
+                #[cfg(feature = "tokio")]
+                BodyPart::Async(_) => 0,
+            };
+            let is_text = part.content_type().is_some_and(|content_type| {
+                content_type
+                    .get(..5)
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case("text/"))
+            });
+            let is_attachment = part
+                .headers
+                .get("Content-Disposition")
+                .and_then(|value| value.as_content_type())
+                .is_some_and(|disposition| disposition.is_attachment())
+                || !is_text;
+
+            stats.parts += 1;
+            if is_attachment {
+                stats.attachments += 1;
+                stats.attachment_bytes += size;
+            } else if let Some(content_type) = part.content_type() {
+                if content_type.eq_ignore_ascii_case("text/plain") {
+                    stats.has_text = true;
+                } else if content_type.eq_ignore_ascii_case("text/html") {
+                    stats.has_html = true;
                 }
-                (None, None, None) => MimePart::new_text("\n"),
             }
-        })
-        .write_part(output)?;
+        }
 
-        Ok(())
+        Ok(stats)
     }
-}
-
-#[cfg(test)]
-mod tests {
 
-    use mail_parser::Message;
+    /// Build the message and encode it using unpadded base64url, without line
+    /// breaks, as required by web APIs that accept raw RFC822 messages.
+    pub fn write_base64url(self) -> io::Result<String> {
+        let mut output = Vec::new();
+        self.write_to(&mut output)?;
+        Ok(encoders::base64::base64url_encode(&output))
+    }
 
-    use crate::{
-        headers::{address::Address, url::URL},
-        mime::MimePart,
-        MessageBuilder,
-    };
+    /// Build the message for an SMTP DATA command: lines starting with a dot
+    /// are dot-stuffed and the message is terminated with `CRLF.CRLF`.
+    pub fn write_smtp_data(self, mut output: impl Write) -> io::Result<()> {
+        let mut message = Vec::new();
+        self.write_to(&mut message)?;
+        for line in message.split_inclusive(|&ch| ch == b'\n') {
+            if line.starts_with(b".") {
+                output.write_all(b".")?;
+            }
+            output.write_all(line)?;
+        }
+        if !message.ends_with(b"\r\n") {
+            output.write_all(b"\r\n")?;
+        }
+        output.write_all(b".\r\n")
+    }
 
-    #[test]
-    fn build_nested_message() {
-        let mut message = MessageBuilder::new();
+    /// Build the message, returning its bytes and the offset where the body
+    /// begins, just after the empty line ending the header section.
+    pub fn build_bytes(self) -> io::Result<(Vec<u8>, usize)> {
+        let mut output = Vec::new();
+        self.write_to(&mut output)?;
+        let body_offset = output.len() - dkim::canonicalize::split_message(&output).1.len();
+        Ok((output, body_offset))
+    }
 
-        message.from(Address::new_address("John Doe".into(), "john@doe.com"));
-        message.to(Address::new_address("Jane Doe".into(), "jane@doe.com"));
-        message.subject("RFC 8621 Section 4.1.4 test");
+    /// Checks a serialized message for common compliance issues, such as
+    /// lines over 998 octets, bare CR or LF characters, non-ASCII octets in
+    /// 7bit parts, a missing MIME-Version header or boundaries that must be
+    /// quoted.
+    pub fn verify_output(bytes: &[u8]) -> Result<(), Vec<verify::Violation>> {
+        verify::verify_output(bytes)
+    }
+
+    /// Returns the MIME structure of the message body, with each leaf part
+    /// annotated with the Content-Transfer-Encoding it will be written with,
+    /// or `None` if the message is written without a body. This is useful
+    /// for auditing encoding decisions, for example when planning message
+    /// sizes.
+    pub fn mime_tree(self) -> io::Result<Option<MimeNode>> {
+        if self.is_headers_only() {
+            return Ok(None);
+        }
+        if let Some((content_type, _)) = self.raw_mime_body {
+            return Ok(Some(MimeNode {
+                content_type: content_type.c_type.into_owned(),
+                encoding: None,
+                children: Vec::new(),
+            }));
+        }
+        let (body, options) = self.into_body();
+        body.into_mime_node(options).map(Some)
+    }
+
+    /// Returns whether the message is written as headers only, see `empty_body`.
+    fn is_headers_only(&self) -> bool {
+        self.empty_body
+            && self.raw_mime_body.is_none()
+            && self.body.is_none()
+            && self.text_body.is_none()
+            && self.html_body.is_none()
+            && self
+                .attachments
+                .iter()
+                .chain(self.related_parts.iter())
+                .flatten()
+                .next()
+                .is_none()
+    }
+
+    /// Build the message, streaming the contents of async attachments.
+    #[cfg(feature = "tokio")]
+    pub async fn write_to_async(
+        mut self,
+        mut output: impl tokio::io::AsyncWrite + Unpin,
+    ) -> io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        // Async parts are written as markers, which are then replaced with
+        // the encoded contents of their readers.
+        let token = make_boundary();
+        let mut readers = Vec::new();
+        for part in self
+            .body
+            .iter_mut()
+            .chain(self.text_body.iter_mut())
+            .chain(self.html_body.iter_mut())
+            .chain(self.attachments.iter_mut().flatten())
+            .chain(self.related_parts.iter_mut().flatten())
+        {
+            part.take_async_readers(&token, &mut readers);
+        }
+        if !readers.is_empty() && !self.dkim_signers.is_empty() {
+            return Err(Error::AsyncAttachment.into());
+        }
+        let mut message = Vec::new();
+        self.write_to(&mut message)?;
+
+        let mut markers = Vec::with_capacity(readers.len());
+        for (marker, reader) in readers {
+            let pos = message
+                .windows(marker.len())
+                .position(|window| window == marker.as_bytes())
+                .ok_or(Error::AsyncAttachment)?;
+            markers.push((pos, marker.len(), reader));
+        }
+        markers.sort_unstable_by_key(|(pos, _, _)| *pos);
+
+        let mut offset = 0;
+        for (pos, len, reader) in markers {
+            output.write_all(&message[offset..pos]).await?;
+            encoders::base64::base64_encode_async(reader, &mut output).await?;
+            offset = pos + len;
+        }
+        output.write_all(&message[offset..]).await?;
+        output.flush().await
+    }
+
+    /// Build the message.
+    pub fn write_to(self, output: impl Write) -> io::Result<()> {
+        self.write_message(output, true)
+    }
+
+    /// Writes the message, generating the Date and Message-ID headers when
+    /// missing if `generate_headers` is set.
+    fn write_message(mut self, mut output: impl Write, generate_headers: bool) -> io::Result<()> {
+        self.check_body()?;
+
+        if !self.dkim_signers.is_empty() {
+            let signers = std::mem::take(&mut self.dkim_signers);
+            let mut message = Vec::new();
+            self.write_to(&mut message)?;
+            output.write_all(sign_all(&signers, &message)?.as_bytes())?;
+            return output.write_all(&message);
+        }
+
+        if let Some(max_ids) = self.max_references {
+            for value in self.headers.get_mut("References").into_iter().flatten() {
+                if let HeaderType::MessageId(message_id) = value {
+                    message_id.truncate_middle(max_ids);
+                }
+            }
+        }
+
+        if let Some(raw_headers) = &self.raw_headers {
+            let is_valid = raw_headers.ends_with(b"\r\n")
+                && !raw_headers.starts_with(b"\r\n")
+                && !raw_headers.windows(4).any(|window| window == b"\r\n\r\n")
+                && raw_headers
+                    .iter()
+                    .enumerate()
+                    .all(|(pos, &ch)| ch != b'\n' || (pos > 0 && raw_headers[pos - 1] == b'\r'));
+            if !is_valid {
+                return Err(Error::InvalidRawHeaders.into());
+            }
+            output.write_all(raw_headers)?;
+        }
+
+        let has_header = |name: &str| {
+            self.headers
+                .keys()
+                .any(|header_name| header_name.eq_ignore_ascii_case(name))
+        };
+        let date = if generate_headers && !has_header("Date") {
+            Some([HeaderType::Date(Date::now().with_offset(self.date_offset))])
+        } else {
+            None
+        };
+        let message_id = self
+            .generated_message_id()
+            .filter(|_| generate_headers)
+            .map(|id| [HeaderType::MessageId(MessageId::new(id))]);
+        let sender = self
+            .generated_sender()
+            .map(|sender| [HeaderType::Address(sender)]);
+        let mime_version = if !has_header("MIME-Version")
+            && !self.raw_headers.as_ref().is_some_and(|raw_headers| {
+                raw_headers.split(|&ch| ch == b'\n').any(|line| {
+                    line.len() >= 13 && line[..13].eq_ignore_ascii_case(b"MIME-Version:")
+                })
+            }) {
+            Some([HeaderType::Raw(Raw::new("1.0"))])
+        } else {
+            None
+        };
+
+        let mut headers = self
+            .headers
+            .iter()
+            .map(|(header_name, header_values)| (header_name.as_ref(), header_values.as_slice()))
+            .chain(date.as_ref().map(|date| ("Date", date.as_slice())))
+            .chain(message_id.as_ref().map(|id| ("Message-ID", id.as_slice())))
+            .chain(sender.as_ref().map(|sender| ("Sender", sender.as_slice())))
+            .chain(
+                mime_version
+                    .as_ref()
+                    .map(|version| ("MIME-Version", version.as_slice())),
+            )
+            .filter(|(header_name, _)| !self.is_omitted(header_name))
+            .collect::<Vec<_>>();
+        headers.sort_by_key(|(header_name, _)| {
+            HEADER_ORDER
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(header_name))
+                .unwrap_or(HEADER_ORDER.len())
+        });
+
+        let options = WriteOptions {
+            uppercase_charset_labels: self.uppercase_charset_labels,
+            ..Default::default()
+        };
+        for (header_name, header_values) in headers {
+            for header_value in header_values {
+                output.write_all(header_name.as_bytes())?;
+                output.write_all(b": ")?;
+                write_header_value(header_value, &mut output, header_name.len() + 2, options)?;
+            }
+        }
+
+        if self.is_headers_only() {
+            return output.write_all(b"\r\n");
+        }
+        self.write_mime_body(output)
+    }
+
+    /// Write only the body of the message, which is everything following
+    /// the empty line that ends the header section. The Content-Type and
+    /// Content-Transfer-Encoding of the top-level part are message headers
+    /// and are not written, as this is the part of the message covered by
+    /// the DKIM body hash, so it can be hashed independently of the headers.
+    /// Nothing is written for messages without a body, see `empty_body`.
+    pub fn write_body_only(self, mut output: impl Write) -> io::Result<()> {
+        self.check_body()?;
+        if self.is_headers_only() {
+            return Ok(());
+        }
+        let mut message = Vec::new();
+        self.write_mime_body(&mut message)?;
+        let body_pos = message
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map_or(message.len(), |pos| pos + 4);
+        output.write_all(&message[body_pos..])
+    }
+
+    /// Returns an error if the body of the message cannot be written.
+    fn check_body(&self) -> io::Result<()> {
+        if self.strict && self.body_overwritten {
+            return Err(Error::BodyOverwritten.into());
+        }
+        if self.raw_mime_body.is_some()
+            && (self.body.is_some()
+                || self.text_body.is_some()
+                || self.html_body.is_some()
+                || self
+                    .attachments
+                    .iter()
+                    .chain(self.related_parts.iter())
+                    .flatten()
+                    .next()
+                    .is_some())
+        {
+            return Err(Error::ConflictingBody.into());
+        }
+        Ok(())
+    }
+
+    /// Write the top-level MIME part of the message and its contents.
+    fn write_mime_body(self, mut output: impl Write) -> io::Result<()> {
+        if let Some((mut content_type, contents)) = self.raw_mime_body {
+            content_type.quote_charset |= self.quoted_charset;
+            output.write_all(b"Content-Type: ")?;
+            content_type.write_header(&mut output, "Content-Type: ".len())?;
+            output.write_all(b"\r\n")?;
+            return output.write_all(&contents);
+        }
+
+        let (body, options) = self.into_body();
+        body.write_part_with(output, options)?;
+
+        Ok(())
+    }
+
+    /// Assemble the MIME structure of the message body, returning it along
+    /// with the options used to write it.
+    fn into_body(self) -> (MimePart<'x>, WriteOptions) {
+        let mut text_body = self.text_body;
+        let mut html_body = self.html_body;
+        let mut attachments = self.attachments;
+        let mut related_parts = self.related_parts;
+        if let Some(charset) = &self.default_charset {
+            for part in text_body
+                .iter_mut()
+                .chain(html_body.iter_mut())
+                .chain(attachments.iter_mut().flatten())
+                .chain(related_parts.iter_mut().flatten())
+            {
+                part.set_charset(charset);
+            }
+        }
+        if self.sort_attachments {
+            for parts in attachments.iter_mut().chain(related_parts.iter_mut()) {
+                parts.sort_by(|a, b| a.filename().cmp(&b.filename()));
+            }
+        }
+        let mixed = self
+            .multipart_type
+            .unwrap_or_else(|| ContentType::new("multipart/mixed"));
+
+        let mut body = if let Some(mut body) = self.body {
+            // Attachments added using the builder are merged into the custom body.
+            let attachments = attachments
+                .into_iter()
+                .chain(related_parts)
+                .flatten()
+                .collect::<Vec<_>>();
+            if attachments.is_empty() {
+                body
+            } else if body.is_mixed() {
+                if let BodyPart::Multipart(parts) = &mut body.contents {
+                    parts.extend(attachments);
+                }
+                body
+            } else {
+                let mut parts = Vec::with_capacity(attachments.len() + 1);
+                parts.push(body);
+                parts.extend(attachments);
+                MimePart::new(mixed, BodyPart::Multipart(parts))
+            }
+        } else {
+            if let Some(related_parts) = related_parts.filter(|parts| !parts.is_empty()) {
+                if let Some(html) = html_body {
+                    let mut parts = Vec::with_capacity(related_parts.len() + 1);
+                    parts.push(html);
+                    parts.extend(related_parts);
+                    html_body = Some(MimePart::new_multipart("multipart/related", parts));
+                } else {
+                    attachments
+                        .get_or_insert_with(Vec::new)
+                        .extend(related_parts);
+                }
+            }
+            match (text_body, html_body, attachments) {
+                (Some(text), Some(html), Some(attachments)) => {
+                    let mut parts = Vec::with_capacity(attachments.len() + 2);
+                    if self.simple_mixed {
+                        parts.push(text);
+                        parts.push(html);
+                    } else {
+                        parts.push(MimePart::new_multipart(
+                            "multipart/alternative",
+                            vec![text, html],
+                        ));
+                    }
+                    parts.extend(attachments);
+
+                    MimePart::new(mixed, BodyPart::Multipart(parts))
+                }
+                (Some(text), Some(html), None) => {
+                    MimePart::new_multipart("multipart/alternative", vec![text, html])
+                }
+                (Some(text), None, Some(attachments)) => {
+                    let mut parts = Vec::with_capacity(attachments.len() + 1);
+                    parts.push(text);
+                    parts.extend(attachments);
+                    MimePart::new(mixed, BodyPart::Multipart(parts))
+                }
+                (Some(text), None, None) => text,
+                (None, Some(html), Some(attachments)) => {
+                    let mut parts = Vec::with_capacity(attachments.len() + 1);
+                    parts.push(html);
+                    parts.extend(attachments);
+                    MimePart::new(mixed, BodyPart::Multipart(parts))
+                }
+                (None, Some(html), None) => html,
+                (None, None, Some(attachments)) => {
+                    MimePart::new(mixed, BodyPart::Multipart(attachments))
+                }
+                (None, None, None) => MimePart::new_text("\n"),
+            }
+        };
+        if self.quoted_charset {
+            body.quote_charset();
+        }
+        if let Some(prefix) = &self.boundary_prefix {
+            body.prefix_boundaries(prefix);
+        }
+
+        let options = WriteOptions {
+            assume_crlf: self.assume_crlf,
+            escape_from: self.escape_from,
+            fold_content_lines: self.fold_content_lines,
+            uppercase_charset_labels: self.uppercase_charset_labels,
+            ..Default::default()
+        };
+        if self.simplify {
+            (body.simplify(), options)
+        } else {
+            (body, options)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+
+    use mail_parser::{HeaderValue, Message, MimeHeaders};
+
+    use crate::{
+        headers::{
+            address::Address, content_type::ContentType, date::Date, raw::Raw, received::Received,
+            text::Text, url::URL, Header, HeaderType,
+        },
+        mime::{content_type_from_path, BodyPart, MimeNode, MimePart},
+        MessageBuilder,
+    };
+
+    #[test]
+    fn build_nested_message() {
+        let mut message = MessageBuilder::new();
+
+        message.from(Address::new_address("John Doe".into(), "john@doe.com"));
+        message.to(Address::new_address("Jane Doe".into(), "jane@doe.com"));
+        message.subject("RFC 8621 Section 4.1.4 test");
 
         message.body(MimePart::new_multipart(
             "multipart/mixed",
@@ -575,4 +1961,3262 @@ mod tests {
         message.write_to(&mut output).unwrap();
         Message::parse(&output).unwrap();
     }
+
+    #[test]
+    fn build_owned_message() {
+        fn build_message<'x>(subject: &'x str, body: &'x str) -> MessageBuilder<'x> {
+            let mut message = MessageBuilder::new();
+            message.message_id("my-id@example.com");
+            message.date(Date::new(0));
+            message.from(("John Doe", "john@doe.com"));
+            message.to(vec![
+                ("Jane Doe", "jane@doe.com"),
+                ("Ñandú", "nandu@doe.com"),
+            ]);
+            message.subject(subject);
+            message.header("List-Archive", URL::new(subject));
+            message.body(MimePart::new(
+                ContentType::new("multipart/mixed").attribute("boundary", "my-boundary"),
+                BodyPart::Multipart(vec![
+                    MimePart::new_text(body),
+                    MimePart::new_binary("image/png", body.as_bytes()).attachment(subject),
+                ]),
+            ));
+            message
+        }
+
+        let subject = String::from("Hello, world!");
+        let body = "Hello, world!\n".repeat(10);
+
+        let mut expected = Vec::new();
+        build_message(&subject, &body)
+            .write_to(&mut expected)
+            .unwrap();
+
+        let message: MessageBuilder<'static> = build_message(&subject, &body).into_owned();
+        drop(subject);
+        drop(body);
+
+        let mut output = Vec::new();
+        std::thread::spawn(move || message.write_to(&mut output).map(|_| output))
+            .join()
+            .unwrap()
+            .map(|output| assert_eq!(output, expected))
+            .unwrap();
+    }
+
+    #[test]
+    fn build_unique_boundaries_across_threads() {
+        let threads = (0..16)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    let mut boundaries = Vec::new();
+                    for _ in 0..100 {
+                        let mut message = MessageBuilder::new();
+                        message.text_body("Hello, world!");
+                        message.html_body("<p>Hello, world!</p>");
+
+                        let mut output = Vec::new();
+                        message.write_to(&mut output).unwrap();
+                        let output = String::from_utf8(output).unwrap();
+                        let boundary = output
+                            .split("boundary=\"")
+                            .nth(1)
+                            .and_then(|s| s.split('"').next())
+                            .unwrap()
+                            .to_string();
+                        assert!(!boundary.is_empty());
+                        boundaries.push(boundary);
+                    }
+                    boundaries
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut boundaries = std::collections::HashSet::new();
+        for thread in threads {
+            for boundary in thread.join().unwrap() {
+                assert!(boundaries.insert(boundary), "Duplicate boundary found.");
+            }
+        }
+        assert_eq!(boundaries.len(), 16 * 100);
+    }
+
+    #[test]
+    fn build_attachment_with_name() {
+        let mut message = MessageBuilder::new();
+        message.legacy_attachment_names();
+        message.text_body("Hello, world!");
+        message.binary_attachment("image/png", "image.png", [1, 2, 3, 4].as_ref());
+        message.binary_attachment("image/png", "ハロー.png", [1, 2, 3, 4].as_ref());
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Content-Type: image/png; name=\"image.png\"\r\n"));
+        assert!(output.contains("Content-Disposition: attachment; filename=\"image.png\"\r\n"));
+        assert!(output
+            .contains("Content-Type: image/png; name*=utf-8''%E3%83%8F%E3%83%AD%E3%83%BC.png\r\n"));
+        assert!(output.contains("filename*=utf-8''%E3%83%8F%E3%83%AD%E3%83%BC.png\r\n"));
+
+        let message = Message::parse(output.as_bytes()).unwrap();
+        assert_eq!(
+            message
+                .get_attachment(1)
+                .unwrap()
+                .unwrap_binary()
+                .get_content_type()
+                .unwrap()
+                .get_attribute("name"),
+            Some("ハロー.png")
+        );
+    }
+
+    #[test]
+    fn build_inline_with_filename() {
+        let mut message = MessageBuilder::new();
+        message.body(MimePart::new_multipart(
+            "multipart/related",
+            vec![
+                MimePart::new_html("<img src=\"cid:image\"/>"),
+                MimePart::new_binary("image/png", [1, 2, 3, 4].as_ref())
+                    .inline_with_filename("image.png")
+                    .cid("image"),
+                MimePart::new_binary("image/png", [1, 2, 3, 4].as_ref())
+                    .inline_with_filename("ñandú.png"),
+            ],
+        ));
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Content-Disposition: inline; filename=\"image.png\"\r\n"));
+        assert!(output
+            .contains("Content-Disposition: inline; filename*=utf-8''%C3%B1and%C3%BA.png\r\n"));
+        Message::parse(output.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn build_auto_date_timezone() {
+        for (offset, expected_offset) in
+            [(None, "+0000"), (Some(-180), "-0300"), (Some(345), "+0545")]
+        {
+            let mut message = MessageBuilder::new();
+            if let Some(offset) = offset {
+                message.date_timezone(offset);
+            }
+            message.text_body("Hello, world!");
+
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            let date = output
+                .lines()
+                .find_map(|line| line.strip_prefix("Date: "))
+                .unwrap();
+            assert!(date.ends_with(expected_offset), "{}", date);
+            assert!(Message::parse(output.as_bytes())
+                .unwrap()
+                .get_date()
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn build_custom_transfer_encoding() {
+        let mut message = MessageBuilder::new();
+        message.body(MimePart::new_multipart(
+            "multipart/mixed",
+            vec![
+                MimePart::new_text("Hello, world!\n")
+                    .header("Content-Transfer-Encoding", Raw::new("quoted-printable")),
+                MimePart::new_binary("application/octet-stream", b"Hello, world!".as_ref())
+                    .header("Content-Transfer-Encoding", Raw::new("7bit")),
+                // The header is matched by name, whatever its type.
+                MimePart::new_binary("image/png", [0u8, 1, 2].as_ref())
+                    .header("Content-Transfer-Encoding", ContentType::new("BASE64")),
+            ],
+        ));
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("Content-Transfer-Encoding:").count(), 3);
+        assert!(output.contains(concat!(
+            "Content-Transfer-Encoding: BASE64\r\n",
+            "Content-Type: image/png\r\n\r\n",
+            "AAEC\r\n"
+        )));
+        assert!(output.contains("Content-Transfer-Encoding: quoted-printable\r\n"));
+        assert!(output.contains(concat!(
+            "Content-Transfer-Encoding: 7bit\r\n",
+            "Content-Type: application/octet-stream\r\n\r\n",
+            "Hello, world!\r\n"
+        )));
+
+        let message = Message::parse(output.as_bytes()).unwrap();
+        assert_eq!(message.get_text_body(0).unwrap(), "Hello, world!\n");
+    }
+
+    #[test]
+    fn build_empty_multipart() {
+        let mut message = MessageBuilder::new();
+        message.body(MimePart::new_multipart(
+            "multipart/mixed",
+            vec![
+                MimePart::new_text("Hello, world!"),
+                MimePart::new_multipart("multipart/alternative", vec![]),
+            ],
+        ));
+
+        let mut output = Vec::new();
+        assert_eq!(
+            message.write_to(&mut output).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn build_simplified_message() {
+        let mut message = MessageBuilder::new();
+        message.simplify();
+        message.binary_attachment("image/png", "image.png", [1, 2, 3, 4].as_ref());
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("multipart/"));
+        assert!(output.contains("Content-Disposition: attachment; filename=\"image.png\"\r\n"));
+        assert!(output.contains("Content-Type: image/png\r\n"));
+
+        let mut message = MessageBuilder::new();
+        message.simplify();
+        message.body(MimePart::new_multipart(
+            "multipart/mixed",
+            vec![
+                MimePart::new_multipart(
+                    "multipart/alternative",
+                    vec![MimePart::new_text("Hello, world!")],
+                ),
+                MimePart::new_multipart(
+                    "multipart/related",
+                    vec![MimePart::new_html("<p>Hello, world!</p>")],
+                )
+                .language("en"),
+            ],
+        ));
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("multipart/mixed"));
+        assert!(!output.contains("multipart/alternative"));
+        assert!(output.contains("multipart/related"));
+        Message::parse(output.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn build_custom_header() {
+        struct Priority(u8);
+
+        impl Header for Priority {
+            fn write_header(
+                &self,
+                output: &mut dyn std::io::Write,
+                _bytes_written: usize,
+            ) -> std::io::Result<usize> {
+                write!(
+                    output,
+                    "{} ({})\r\n",
+                    self.0,
+                    ["Highest", "Normal"][self.0 as usize / 3]
+                )?;
+                Ok(0)
+            }
+        }
+
+        let mut message = MessageBuilder::new();
+        message.header("X-Priority", HeaderType::Custom(Box::new(Priority(1))));
+        message.text_body("Hello, world!");
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("X-Priority: 1 (Highest)\r\n"));
+        Message::parse(output.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn build_list_headers() {
+        let mut message = MessageBuilder::new();
+        message.list_archive("http://example.com/archive");
+        message.list_id(Some("Example list"), "list.example.com");
+        message.list_help(vec![
+            "mailto:list@example.com?subject=help",
+            "http://example.com/help",
+        ]);
+        message.list_owner("mailto:owner@example.com");
+        message.list_post_disabled();
+        message.list_subscribe(vec![
+            "mailto:list-request@example.com?subject=subscribe",
+            "http://example.com/subscribe",
+        ]);
+        message.text_body("Hello, world!");
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        for header in [
+            "List-Archive: <http://example.com/archive>\r\n",
+            "List-ID: \"Example list\" <list.example.com>\r\n",
+            "List-Help: <mailto:list@example.com?subject=help>,\r\n\t<http://example.com/help>\r\n",
+            "List-Owner: <mailto:owner@example.com>\r\n",
+            "List-Post: NO\r\n",
+            concat!(
+                "List-Subscribe: <mailto:list-request@example.com?subject=subscribe>,\r\n\t",
+                "<http://example.com/subscribe>\r\n"
+            ),
+        ] {
+            assert!(output.contains(header), "{}", header);
+        }
+
+        let message = Message::parse(output.as_bytes()).unwrap();
+        assert!(!matches!(message.get_list_help(), HeaderValue::Empty));
+        assert!(!matches!(message.get_list_post(), HeaderValue::Empty));
+        assert!(!matches!(message.get_list_id(), HeaderValue::Empty));
+
+        let mut message = MessageBuilder::new();
+        message.list_id(None::<&str>, "list.example.com");
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("List-ID: <list.example.com>\r\n"));
+
+        let mut message = MessageBuilder::new();
+        message.list_id(Some("Example list"), "not a list id");
+        message.text_body("Hello, world!");
+        assert!(message.write_to(&mut Vec::new()).is_err());
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn build_bytes_attachment() {
+        let contents = "Hello, world!\n".repeat(20).into_bytes();
+        let bytes = bytes::Bytes::from(contents.clone());
+
+        let mut outputs = Vec::new();
+        for part in [
+            MimePart::new_binary("application/octet-stream", contents),
+            MimePart::new_bytes("application/octet-stream", bytes),
+        ] {
+            let mut output = Vec::new();
+            part.attachment("hello.bin")
+                .write_part(&mut output)
+                .unwrap();
+            outputs.push(output);
+        }
+        assert_eq!(outputs[0], outputs[1]);
+    }
+
+    #[test]
+    fn build_assume_crlf() {
+        for (assume_crlf, expected_body) in [
+            (false, "Line 1\r\nLine 2\r\nLine 3\r\n"),
+            (true, "Line 1\r\nLine 2\nLine 3\r\n"),
+        ] {
+            let mut message = MessageBuilder::new();
+            if assume_crlf {
+                message.assume_crlf();
+            }
+            message.text_body("Line 1\r\nLine 2\nLine 3\r\n");
+
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.ends_with(&format!("7bit\r\n\r\n{}", expected_body)));
+        }
+    }
+
+    #[test]
+    fn build_quoted_boundaries() {
+        for (content_type, expected_result) in [
+            (
+                HeaderType::from(
+                    ContentType::new("multipart/mixed").attribute("boundary", "my=boundary"),
+                ),
+                "Content-Type: multipart/mixed; boundary=\"my=boundary\"\r\n",
+            ),
+            (
+                Raw::new("multipart/mixed; boundary=my-boundary").into(),
+                "Content-Type: multipart/mixed; boundary=\"my-boundary\"\r\n",
+            ),
+            (
+                Raw::new("multipart/mixed; boundary=my-boundary; charset=utf-8").into(),
+                "Content-Type: multipart/mixed; boundary=\"my-boundary\"; charset=utf-8\r\n",
+            ),
+            (
+                Raw::new("multipart/mixed; boundary=\"my boundary\"").into(),
+                "Content-Type: multipart/mixed; boundary=\"my boundary\"\r\n",
+            ),
+            (
+                Raw::new("multipart/mixed; x-boundary=other; BOUNDARY=my-boundary").into(),
+                "Content-Type: multipart/mixed; x-boundary=other; BOUNDARY=\"my-boundary\"\r\n",
+            ),
+            (
+                Raw::new("multipart/mixed; name=\"a; boundary=b\"; boundary=\"my-boundary\"")
+                    .into(),
+                "Content-Type: multipart/mixed; name=\"a; boundary=b\"; boundary=\"my-boundary\"\r\n",
+            ),
+        ] {
+            let mut output = Vec::new();
+            MimePart::new_multipart("multipart/mixed", vec![MimePart::new_text("Hello")])
+                .header("Content-Type", content_type)
+                .write_part(&mut output)
+                .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.starts_with(expected_result), "{}", output);
+            Message::parse(output.as_bytes()).unwrap();
+        }
+
+        let mut output = Vec::new();
+        MimePart::new_multipart("multipart/mixed", vec![MimePart::new_text("Hello")])
+            .write_part(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("boundary=\""));
+
+        let mut output = Vec::new();
+        MimePart::new_multipart("multipart/mixed", vec![MimePart::new_text("Hello")])
+            .header("Content-Type", Raw::new("multipart/mixed"))
+            .write_part(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Content-Type: multipart/mixed; boundary=\""));
+    }
+
+    #[test]
+    fn build_multipart_report() {
+        let mut message = MessageBuilder::new();
+        message.body(MimePart::new(
+            ContentType::new("multipart/report")
+                .attribute("report-type", "delivery-status")
+                .attribute("charset", "utf-8"),
+            BodyPart::Multipart(vec![
+                MimePart::new_text("Your message could not be delivered."),
+                MimePart::new_text_other(
+                    "message/delivery-status",
+                    "Reporting-MTA: dns; mx.example.com\n\nFinal-Recipient: rfc822; jane@example.com\nAction: failed\nStatus: 5.1.1\n",
+                ),
+            ]),
+        ));
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let message = Message::parse(output.as_bytes()).unwrap();
+        let content_type = message.get_content_type().unwrap();
+        assert_eq!(content_type.get_type(), "multipart");
+        assert_eq!(content_type.get_subtype(), Some("report"));
+        assert_eq!(
+            content_type.get_attribute("report-type"),
+            Some("delivery-status")
+        );
+        assert_eq!(content_type.get_attribute("charset"), Some("utf-8"));
+        assert!(content_type.get_attribute("boundary").is_some());
+    }
+
+    #[test]
+    fn build_nul_bytes() {
+        let mut message = MessageBuilder::new();
+        message.subject("Hello\0world");
+        message.text_body("Hello, world!");
+        assert_eq!(
+            message.write_to(&mut Vec::new()).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+
+        let mut message = MessageBuilder::new();
+        message.to(("Jane\0Doe", "jane@doe.com"));
+        message.text_body("Hello, world!");
+        assert!(message.write_to(&mut Vec::new()).is_err());
+
+        // Content-Type values are written unfolded, so line breaks are
+        // rejected as well.
+        for (content_type, expected) in [
+            (
+                ContentType::new("text/plain\r\nBcc: jane@doe.com"),
+                crate::Error::LineBreakInHeader,
+            ),
+            (
+                ContentType::new("text/plain").attribute("name", "a\nb.txt"),
+                crate::Error::LineBreakInHeader,
+            ),
+            (
+                ContentType::new("text/plain").attribute("format\r\n", "flowed"),
+                crate::Error::LineBreakInHeader,
+            ),
+            (
+                ContentType::new("text/plain").attribute("name", "ñ\0.txt"),
+                crate::Error::NulInHeader,
+            ),
+        ] {
+            let mut message = MessageBuilder::new();
+            message.body(MimePart::new(
+                content_type,
+                crate::mime::BodyPart::Text("Hello, world!".into()),
+            ));
+            let err = message.write_to(&mut Vec::new()).unwrap_err();
+            assert_eq!(crate::Error::from_io(&err), Some(&expected));
+        }
+
+        let mut message = MessageBuilder::new();
+        message.text_body("Hello\0world");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Content-Transfer-Encoding: base64\r\n"));
+        assert!(!output.contains('\0'));
+        assert_eq!(
+            Message::parse(output.as_bytes())
+                .unwrap()
+                .get_text_body(0)
+                .unwrap(),
+            "Hello\0world"
+        );
+    }
+
+    #[test]
+    fn build_long_header_lines() {
+        let mut message = MessageBuilder::new();
+        message.references(
+            (0..200)
+                .map(|i| format!("message-id-{}@example.com", i))
+                .collect::<Vec<_>>(),
+        );
+        message.header("X-Long", Raw::new("word ".repeat(500)));
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        for line in String::from_utf8(output).unwrap().split("\r\n") {
+            assert!(line.len() <= 998, "{}", line);
+        }
+
+        let mut message = MessageBuilder::new();
+        message.header("X-Long", Raw::new("a".repeat(1000)));
+        message.text_body("Hello, world!");
+        assert_eq!(
+            message.write_to(&mut Vec::new()).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn build_sorted_attachments() {
+        for (sort, expected) in [
+            (false, ["c.txt", "a.txt", "b.txt"]),
+            (true, ["a.txt", "b.txt", "c.txt"]),
+        ] {
+            let mut message = MessageBuilder::new();
+            message.text_body("Hello, world!");
+            for name in ["c.txt", "a.txt", "b.txt"] {
+                message.text_attachment("text/plain", name, "Attachment");
+            }
+            if sort {
+                message.sort_attachments_by_name();
+            }
+
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            let positions = expected
+                .iter()
+                .map(|name| output.find(&format!("filename=\"{}\"", name)).unwrap())
+                .collect::<Vec<_>>();
+            assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", output);
+        }
+    }
+
+    #[test]
+    fn build_long_line_body() {
+        for body in ["a".repeat(2000), format!("{}\r\nHello", "a".repeat(2000))] {
+            let mut message = MessageBuilder::new();
+            message.text_body(body.as_str());
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("Content-Transfer-Encoding: quoted-printable\r\n"));
+            assert!(output.split("\r\n").all(|line| line.len() <= 998));
+            assert_eq!(
+                Message::parse(output.as_bytes())
+                    .unwrap()
+                    .get_text_body(0)
+                    .unwrap()
+                    .trim_end(),
+                body.replace("\r\n", "\n")
+            );
+        }
+
+        let mut message = MessageBuilder::new();
+        message.text_body("a".repeat(998));
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Content-Transfer-Encoding: 7bit\r\n"));
+    }
+
+    #[test]
+    fn build_inline_auto_cid() {
+        let mut message = MessageBuilder::new();
+        message.from(("John Doe", "john@example.com"));
+        let cid = message.binary_inline_auto_cid("image/png", [1, 2, 3, 4].as_ref());
+        assert!(cid.ends_with("@example.com"));
+        assert_ne!(
+            cid,
+            message.binary_inline_auto_cid("image/png", [1, 2, 3, 4].as_ref())
+        );
+        message.html_body(format!("<img src=\"cid:{}\">", cid));
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!("Content-ID: <{}>\r\n", cid)));
+
+        let mut message = MessageBuilder::new();
+        assert!(message
+            .binary_inline_auto_cid("image/png", [1, 2, 3, 4].as_ref())
+            .ends_with("@localhost"));
+    }
+
+    #[test]
+    fn build_quoted_display_names() {
+        for (name, expected) in [
+            ("Doe, John", "From: \"Doe, John\" <john@example.com>\r\n"),
+            (
+                "John \"JD\" Doe",
+                "From: \"John \\\"JD\\\" Doe\" <john@example.com>\r\n",
+            ),
+            (
+                "J. Doe; Sales: @HQ",
+                "From: \"J. Doe; Sales: @HQ\" <john@example.com>\r\n",
+            ),
+        ] {
+            let mut message = MessageBuilder::new();
+            message.from((name, "john@example.com"));
+            message.text_body("Hello, world!");
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            assert!(
+                String::from_utf8_lossy(&output).contains(expected),
+                "{}",
+                expected
+            );
+
+            match Message::parse(&output).unwrap().get_from() {
+                HeaderValue::Address(address) => {
+                    assert_eq!(address.name.as_deref(), Some(name));
+                    assert_eq!(address.address.as_deref(), Some("john@example.com"));
+                }
+                other => panic!("Unexpected value {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn build_mixed_encoded_subject() {
+        for subject in [
+            "Re: こんにちは (reply)",
+            "Re: Fwd: こんにちは、世界! This is a (rather) long subject line that needs folding, ¿no?",
+            "Ceci n'est pas une pipe = ça n'est qu'une représentation (très longue) d'une pipe",
+        ] {
+            let mut message = MessageBuilder::new();
+            message.subject(subject);
+            message.text_body("Hello, world!");
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(
+                output.split("\r\n").all(|line| line.len() <= 76),
+                "{}",
+                output
+            );
+            assert_eq!(
+                Message::parse(output.as_bytes()).unwrap().get_subject(),
+                Some(subject)
+            );
+        }
+    }
+
+    #[test]
+    fn build_part_from_path() {
+        let path = std::env::temp_dir().join(format!("report-{}.PDF", std::process::id()));
+        std::fs::write(&path, b"%PDF-1.4 test").unwrap();
+        let filename = path.file_name().unwrap().to_str().unwrap().to_string();
+
+        let mut message = MessageBuilder::new();
+        message.text_body("Hello, world!");
+        message.body(MimePart::new_multipart(
+            "multipart/mixed",
+            vec![
+                MimePart::new_text("Hello, world!"),
+                MimePart::from_path(&path, false).unwrap(),
+                MimePart::from_path(&path, true).unwrap(),
+            ],
+        ));
+        std::fs::remove_file(&path).unwrap();
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output.matches("Content-Type: application/pdf\r\n").count(),
+            2
+        );
+        assert!(output.contains(&format!(
+            "Content-Disposition: attachment; filename=\"{}\"",
+            filename
+        )));
+        assert!(output.contains(&format!(
+            "Content-Disposition: inline; filename=\"{}\"",
+            filename
+        )));
+
+        assert_eq!(
+            MimePart::from_path(&path, false).err().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn build_lazy_file_attachment() {
+        let path = std::env::temp_dir().join(format!("lazy-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let build = || {
+            let mut message = MessageBuilder::new();
+            message.text_body("Hello, world!");
+            message.attachments = Some(vec![MimePart::new_file(
+                "application/octet-stream",
+                path.clone(),
+            )
+            .attachment("lazy.bin")]);
+            message
+        };
+
+        // The file does not exist yet when the message is built.
+        let message = build();
+        std::fs::write(&path, [0u8, 1, 2, 3, 255]).unwrap();
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        assert_eq!(
+            Message::parse(&output)
+                .unwrap()
+                .get_attachment(0)
+                .unwrap()
+                .unwrap_binary()
+                .body
+                .as_ref(),
+            &[0u8, 1, 2, 3, 255]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            build().write_to(&mut Vec::new()).unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn build_raw_headers() {
+        let received = concat!(
+            "Received: from mx.example.com (mx.example.com [192.0.2.1])\r\n",
+            "\tby mail.example.org; Thu, 01 Jan 1970 00:00:00 +0000\r\n",
+            "Received: from localhost by mx.example.com;\r\n",
+            "\tThu, 01 Jan 1970 00:00:00 +0000\r\n",
+        );
+        let mut message = MessageBuilder::new();
+        message.raw_headers(received.as_bytes());
+        message.subject("Hello, world!");
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        assert!(output.starts_with(received.as_bytes()));
+
+        let message = Message::parse(&output).unwrap();
+        assert_eq!(message.get_subject(), Some("Hello, world!"));
+        assert_eq!(
+            message.get_text_body(0).unwrap().trim_end(),
+            "Hello, world!"
+        );
+
+        for raw_headers in [
+            "Received: from localhost",
+            "Received: from localhost\n",
+            "Received: from localhost\r\n\r\n",
+            "\r\n",
+        ] {
+            let mut message = MessageBuilder::new();
+            message.raw_headers(raw_headers.as_bytes());
+            message.text_body("Hello, world!");
+            assert_eq!(
+                message.write_to(&mut Vec::new()).unwrap_err().kind(),
+                std::io::ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn build_empty_binary_content_type() {
+        for content_type in ["", " \t"] {
+            let mut message = MessageBuilder::new();
+            message.text_body("Hello, world!");
+            message.binary_attachment(content_type, "data.bin", [1, 2, 3].as_ref());
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("Content-Type: application/octet-stream\r\n"));
+            assert!(!output.contains("Content-Type: \r\n"));
+        }
+    }
+
+    #[test]
+    fn build_addresses_from_strings() {
+        let addresses = vec![
+            "jane@example.com".to_string(),
+            "john@example.com".to_string(),
+        ];
+        let expected = "To: <jane@example.com>, <john@example.com>\r\n";
+
+        let mut message = MessageBuilder::new();
+        message.to(addresses.as_slice());
+        message.cc(addresses.clone());
+        message.bcc(vec![("", "jane@example.com"), (" ", "john@example.com")]);
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(expected), "{}", output);
+        assert!(
+            output.contains(&expected.replace("To:", "Cc:")),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains(&expected.replace("To:", "Bcc:")),
+            "{}",
+            output
+        );
+
+        match Message::parse(output.as_bytes()).unwrap().get_to() {
+            HeaderValue::AddressList(list) => {
+                assert_eq!(
+                    list.iter()
+                        .map(|addr| addr.address.as_deref().unwrap())
+                        .collect::<Vec<_>>(),
+                    addresses
+                );
+            }
+            other => panic!("Unexpected value {:?}", other),
+        }
+    }
+
+    #[test]
+    fn build_optional_display_name() {
+        for (name, expected) in [
+            (Some("John Doe"), "From: \"John Doe\" <john@doe.com>\r\n"),
+            (None, "From: <john@doe.com>\r\n"),
+        ] {
+            let mut message = MessageBuilder::new();
+            message.from((name, "john@doe.com"));
+            message.to((
+                name.map(|name| name.to_string()),
+                "jane@doe.com".to_string(),
+            ));
+            message.text_body("Hello, world!");
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains(expected), "{}", output);
+
+            match Message::parse(output.as_bytes()).unwrap().get_from() {
+                HeaderValue::Address(address) => {
+                    assert_eq!(address.name.as_deref(), name);
+                    assert_eq!(address.address.as_deref(), Some("john@doe.com"));
+                }
+                other => panic!("Unexpected value {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn build_debug_message_builder() {
+        let mut message = MessageBuilder::new();
+        message.from(("John Doe", "john@doe.com"));
+        message.subject("Hello, world!");
+        message.text_body("Hello, world!");
+        message.binary_attachment("image/png", "image.png", vec![0u8; 10_000_000]);
+        message.header("X-Custom", HeaderType::Custom(Box::new(Raw::new("value"))));
+
+        let debug = format!("{:?}", message);
+        assert!(debug.len() < 2000, "{}", debug);
+        assert!(debug.contains("Binary(10000000 bytes)"), "{}", debug);
+        assert!(debug.contains("john@doe.com"), "{}", debug);
+        assert!(debug.contains("Custom(..)"), "{}", debug);
+    }
+
+    #[test]
+    fn build_long_rfc2231_filename() {
+        let filename = format!("{}.txt", "ファイル名 ñandú ".repeat(20));
+        let mut message = MessageBuilder::new();
+        message.text_body("Hello, world!");
+        message.text_attachment("text/plain", filename.as_str(), "Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let mut segments = 0;
+        for line in output.split("\r\n") {
+            assert!(line.len() <= 76, "{}", line);
+            if let Some(param) = line.trim_start().strip_prefix("filename*") {
+                assert!(param.starts_with(&format!("{}*=", segments)), "{}", line);
+                segments += 1;
+            }
+        }
+        assert!(segments > 1, "{}", output);
+        assert!(output.contains("filename*0*=utf-8''"));
+
+        let message = Message::parse(output.as_bytes()).unwrap();
+        assert_eq!(
+            message
+                .get_attachment(0)
+                .unwrap()
+                .unwrap_text()
+                .get_content_disposition()
+                .unwrap()
+                .get_attribute("filename"),
+            Some(filename.as_str())
+        );
+    }
+
+    #[test]
+    fn build_replace_headers() {
+        let mut message = MessageBuilder::new();
+        message.from(("John Doe", "john@doe.com"));
+        message.subject("Template");
+        message.header("X-Campaign", Raw::new("1"));
+        message.header("X-Campaign", Raw::new("2"));
+        message.text_body("Hello, world!");
+
+        message.set_header("Subject", Text::new("Hello, Jane!"));
+        assert!(message.remove_header("x-campaign"));
+        assert!(!message.remove_header("X-Campaign"));
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("Subject: ").count(), 1);
+        assert!(output.contains("Subject: Hello, Jane!\r\n"));
+        assert!(!output.contains("X-Campaign"));
+        assert!(output.contains("From: \"John Doe\" <john@doe.com>\r\n"));
+    }
+
+    #[test]
+    fn build_flowed_space_stuffing() {
+        for delsp in [false, true] {
+            let mut message = MessageBuilder::new();
+            if delsp {
+                message.format_flowed_delsp();
+            } else {
+                message.format_flowed();
+            }
+            message.text_body("Hello,\n> quoted line\n indented\nFrom here\nFromage\n");
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output
+                .contains("Hello,\r\n > quoted line\r\n  indented\r\n From here\r\nFromage\r\n"));
+            assert!(output.contains("format=\"flowed\""));
+            assert_eq!(output.contains("delsp=\"yes\""), delsp, "{}", output);
+        }
+    }
+
+    #[test]
+    fn build_flowed_wrapping() {
+        let paragraph = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod \
+                         tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam.";
+        let long_word = "x".repeat(100);
+        let mut message = MessageBuilder::new();
+        message.format_flowed();
+        message.text_body(format!(
+            "{}\nShort line   \n{} tail\n{}\n",
+            paragraph,
+            long_word,
+            paragraph.replace("tempor", "From tempor")
+        ));
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let body = &output[output.find("\r\n\r\n").unwrap() + 4..];
+
+        assert_eq!(
+            body,
+            concat!(
+                "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod \r\n",
+                "tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim \r\n",
+                "veniam.\r\n",
+                "Short line\r\n",
+                "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx \r\n",
+                "tail\r\n",
+                "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod \r\n",
+                " From tempor incididunt ut labore et dolore magna aliqua. Ut enim ad \r\n",
+                "minim veniam.\r\n",
+            )
+        );
+        for line in body.split("\r\n") {
+            assert!(line.trim_end().len() <= 100, "{}", line);
+        }
+    }
+
+    #[test]
+    fn build_escape_from_lines() {
+        let body = "Hello,\nFrom the team\nFromage is not escaped\n";
+        for escape in [false, true] {
+            let mut message = MessageBuilder::new();
+            if escape {
+                message.escape_from_lines();
+            }
+            message.text_body(body);
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(output.contains("\r\nFrom the team"), !escape, "{}", output);
+            if escape {
+                assert!(output.contains("Content-Transfer-Encoding: quoted-printable\r\n"));
+                assert!(output.contains("\r\n=46rom the team\r\nFromage"));
+            }
+            assert_eq!(
+                Message::parse(output.as_bytes())
+                    .unwrap()
+                    .get_text_body(0)
+                    .unwrap()
+                    .replace('\r', ""),
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn build_top_level_content_type() {
+        let attachment =
+            || MimePart::new_binary("image/png", [1, 2, 3].as_ref()).attachment("a.png");
+        for (text, html, attachments, simplify, custom, expected) in [
+            (true, true, true, false, false, "multipart/mixed"),
+            (true, true, false, false, false, "multipart/alternative"),
+            (true, false, true, false, false, "multipart/mixed"),
+            (true, false, false, false, false, "text/plain"),
+            (false, true, true, false, false, "multipart/mixed"),
+            (false, true, false, false, false, "text/html"),
+            (false, false, true, false, false, "multipart/mixed"),
+            (false, false, true, true, false, "image/png"),
+            (false, false, false, false, false, "text/plain"),
+            (false, false, false, false, true, "multipart/related"),
+            (false, false, false, true, true, "text/html"),
+        ] {
+            let mut message = MessageBuilder::new();
+            if text {
+                message.text_body("Hello, world!");
+            }
+            if html {
+                message.html_body("<p>Hello, world!</p>");
+            }
+            if attachments {
+                message.attachments = Some(vec![attachment()]);
+            }
+            if custom {
+                message.body(MimePart::new_multipart(
+                    "multipart/related",
+                    vec![MimePart::new_html("<p>Hello, world!</p>")],
+                ));
+            }
+            if simplify {
+                message.simplify();
+            }
+            assert_eq!(message.content_type().as_deref(), Some(expected));
+
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            assert_eq!(
+                Message::parse(&output)
+                    .unwrap()
+                    .get_content_type()
+                    .map(|ct| format!("{}/{}", ct.get_type(), ct.get_subtype().unwrap_or_default()))
+                    .unwrap_or_else(|| "text/plain".to_string()),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn build_default_charset() {
+        let mut message = MessageBuilder::new();
+        message.default_charset("iso-8859-1");
+        message.text_body("Hello, world!");
+        message.html_body("<p>¡Hola, mundo!</p>");
+        message.text_attachment("text/csv", "data.csv", "a,b,c");
+        message.binary_attachment("image/png", "image.png", [1, 2, 3].as_ref());
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("Content-Type: text/plain; charset=iso-8859-1\r\n"));
+        assert!(output.contains("Content-Type: text/csv; charset=iso-8859-1\r\n"));
+        // Non-ASCII text is written as UTF-8 regardless of the default charset.
+        assert!(output.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(output.contains("Content-Type: image/png\r\n"));
+    }
+
+    #[test]
+    fn build_binary_quoted_printable() {
+        let csv = "id,name,amount\n1,Jane Doe,10.00\n2,John Doe,20.00\n".repeat(10);
+        let random = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+        let mut sizes = Vec::new();
+        for (contents, prefer_qp, expected) in [
+            (csv.as_bytes(), false, "base64"),
+            (csv.as_bytes(), true, "quoted-printable"),
+            (random.as_slice(), true, "base64"),
+        ] {
+            let mut part =
+                MimePart::new_binary("application/octet-stream", contents).attachment("data.bin");
+            if prefer_qp {
+                part = part.prefer_quoted_printable();
+            }
+            let mut message = MessageBuilder::new();
+            message.text_body("Hello, world!");
+            message.attachments = Some(vec![part]);
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            sizes.push(output.len());
+            let output_str = String::from_utf8(output.clone()).unwrap();
+            assert!(
+                output_str.contains(&format!("Content-Transfer-Encoding: {}\r\n", expected)),
+                "{}",
+                output_str
+            );
+
+            assert_eq!(
+                Message::parse(&output)
+                    .unwrap()
+                    .get_attachment(0)
+                    .unwrap()
+                    .unwrap_binary()
+                    .body
+                    .as_ref(),
+                contents
+            );
+        }
+        assert!(sizes[1] < sizes[0], "{:?}", sizes);
+    }
+
+    #[test]
+    fn build_normalized_message_ids() {
+        for id in ["id@host", "<id@host>", " <id@host> "] {
+            let mut message = MessageBuilder::new();
+            message.message_id(id);
+            message.in_reply_to(id.to_string());
+            message.references(vec![id, "<other@host>"]);
+            message.text_body("Hello, world!");
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("Message-ID: <id@host>\r\n"), "{}", output);
+            assert!(output.contains("In-Reply-To: <id@host>\r\n"), "{}", output);
+            assert!(
+                output.contains("References: <id@host> <other@host>\r\n"),
+                "{}",
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn build_long_references() {
+        let ids = (0..50)
+            .map(|i| format!("{}.{}@mail{}.example.com", "x".repeat(i % 20), i, i))
+            .collect::<Vec<_>>();
+        let mut message = MessageBuilder::new();
+        message.references(ids.clone());
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let mut lines = output
+            .split("\r\n")
+            .skip_while(|line| !line.starts_with("References: "));
+        let header = lines
+            .next()
+            .into_iter()
+            .chain(lines.take_while(|line| line.starts_with('\t')))
+            .collect::<Vec<_>>();
+        assert!(header.len() > 1);
+        for line in header {
+            assert!(line.len() <= 76, "{:?}", line);
+            assert!(line.ends_with('>'), "{:?}", line);
+        }
+        match Message::parse(output.as_bytes()).unwrap().get_references() {
+            HeaderValue::TextList(references) => assert_eq!(references, &ids),
+            other => panic!("Unexpected value {:?}", other),
+        }
+
+        let mut message = MessageBuilder::new();
+        message.references(vec![
+            "a@b".to_string(),
+            format!("{}@example.com", "x".repeat(1000)),
+        ]);
+        message.text_body("Hello, world!");
+        assert!(message.write_to(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn build_non_ascii_sender() {
+        let mut message = MessageBuilder::new();
+        message.sender(("Jöhn Dœ, Secretary", "john@example.com"));
+        message.from(("Ñandú Ltd.", "info@example.com"));
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        assert!(output.is_ascii());
+
+        let message = Message::parse(&output).unwrap();
+        for (value, name, email) in [
+            (
+                message.get_sender(),
+                "Jöhn Dœ, Secretary",
+                "john@example.com",
+            ),
+            (message.get_from(), "Ñandú Ltd.", "info@example.com"),
+        ] {
+            match value {
+                HeaderValue::Address(address) => {
+                    assert_eq!(address.name.as_deref(), Some(name));
+                    assert_eq!(address.address.as_deref(), Some(email));
+                }
+                other => panic!("Unexpected value {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn build_modern_content_types() {
+        for (path, expected) in [
+            ("logo.svg", "image/svg+xml"),
+            ("photo.webp", "image/webp"),
+            ("photo.AVIF", "image/avif"),
+            ("font.woff2", "font/woff2"),
+            ("module.wasm", "application/wasm"),
+            ("unknown.xyz", "application/octet-stream"),
+        ] {
+            assert_eq!(content_type_from_path(path), expected);
+        }
+
+        let svg = "<svg xmlns=\"http://www.w3.org/2000/svg\">\n<circle r=\"10\"/>\n</svg>\n";
+        let mut message = MessageBuilder::new();
+        message.text_body("Hello, world!");
+        message.binary_attachment(
+            content_type_from_path("logo.svg"),
+            "logo.svg",
+            svg.as_bytes(),
+        );
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("base64"), "{}", output);
+        assert!(
+            output.contains("Content-Transfer-Encoding: quoted-printable\r\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn build_text_like_attachments() {
+        for (c_type, is_text) in [
+            ("text/plain", true),
+            ("Text/CSV", true),
+            ("application/json", true),
+            ("application/xml", true),
+            ("application/javascript", true),
+            ("image/svg+xml", true),
+            ("application/atom+xml", true),
+            ("application/ld+json", true),
+            ("application/pdf", false),
+            ("image/png", false),
+        ] {
+            assert_eq!(ContentType::new(c_type).is_text(), is_text, "{}", c_type);
+        }
+
+        for (c_type, contents) in [
+            ("application/json", "{\"hello\": \"world\"}"),
+            (
+                "image/svg+xml",
+                "<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+            ),
+        ] {
+            let mut message = MessageBuilder::new();
+            message.text_body("Hello, world!");
+            message.binary_attachment(c_type, "data", contents.as_bytes());
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(!output.contains("base64"), "{}", output);
+        }
+    }
+
+    #[test]
+    fn build_invalid_messages() {
+        let mut message = MessageBuilder::new();
+        message.body(
+            MimePart::new_multipart("multipart/mixed", vec![MimePart::new_text("Hello")])
+                .header("Content-Type", Text::new("multipart/mixed")),
+        );
+        let err = message.write_to(Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(
+            crate::Error::from_io(&err),
+            Some(&crate::Error::UnsupportedContentType)
+        );
+
+        for address in [
+            Address::new_list(vec![Address::new_list(vec![Address::from(
+                "jdoe@example.com",
+            )])]),
+            Address::new_group(
+                "Friends".into(),
+                vec![Address::new_group(
+                    "Nested".into(),
+                    vec![Address::from("jdoe@example.com")],
+                )],
+            ),
+        ] {
+            let mut message = MessageBuilder::new();
+            message.to(address);
+            message.text_body("Hello, world!");
+            let err = message.write_to(Vec::new()).unwrap_err();
+            assert_eq!(
+                crate::Error::from_io(&err),
+                Some(&crate::Error::NestedAddress)
+            );
+        }
+    }
+
+    #[test]
+    fn build_related_parts() {
+        let mut message = MessageBuilder::new();
+        message.text_body("Hello, world!");
+        message.html_body("<p style=\"font-family: brand\">Hello, world!</p>");
+        message.binary_related("font/woff2", "brand-font", [0, 1, 2, 3].as_ref());
+        message.binary_attachment("application/pdf", "report.pdf", [4, 5, 6].as_ref());
+        assert_eq!(message.content_type().as_deref(), Some("multipart/mixed"));
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let positions = [
+            "multipart/mixed",
+            "multipart/alternative",
+            "text/plain",
+            "multipart/related",
+            "text/html",
+            "font/woff2",
+            "application/pdf",
+        ]
+        .iter()
+        .map(|c_type| output.find(c_type).unwrap())
+        .collect::<Vec<_>>();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", output);
+        assert!(output.contains("Content-ID: <brand-font>"), "{}", output);
+
+        let mut message = MessageBuilder::new();
+        message.html_body("<p>Hello, world!</p>");
+        message.binary_related("font/woff2", "brand-font", [0, 1, 2, 3].as_ref());
+        assert_eq!(message.content_type().as_deref(), Some("multipart/related"));
+    }
+
+    #[test]
+    fn build_body_attributes() {
+        let mut message = MessageBuilder::new();
+        message.text_body("Hello, world!");
+        message.html_body("<p>Hello, world!</p>");
+        message.html_body_attribute("x-vendor-id", "1234");
+        message.text_body_attribute("charset", "us-ascii");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("Content-Type: text/html; charset=utf-8; x-vendor-id=\"1234\"\r\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("Content-Type: text/plain; charset=us-ascii\r\n"),
+            "{}",
+            output
+        );
+
+        let message = Message::parse(output.as_bytes()).unwrap();
+        assert_eq!(
+            message.get_html_body(0).unwrap().trim_end(),
+            "<p>Hello, world!</p>"
+        );
+    }
+
+    #[test]
+    fn build_header_order() {
+        let mut message = MessageBuilder::new();
+        message.header("X-Mailer", Text::new("mail-builder"));
+        message.subject("Hello, world!");
+        message.to("jdoe@example.com");
+        message.header("Comments", Text::new("Ordering test"));
+        message.from(("John Doe", "john@example.com"));
+        message.cc("jane@example.com");
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let names = output
+            .split("\r\n\r\n")
+            .next()
+            .unwrap()
+            .split("\r\n")
+            .filter(|line| !line.starts_with('\t'))
+            .map(|line| line.split(':').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "Date",
+                "From",
+                "To",
+                "Cc",
+                "Subject",
+                "Message-ID",
+                "Comments",
+                "X-Mailer",
+                "MIME-Version",
+                "Content-Type",
+                "Content-Transfer-Encoding"
+            ]
+        );
+    }
+
+    #[test]
+    fn build_received_headers() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.received(
+            Received::new()
+                .from("client.example.com (client.example.com [192.0.2.1])")
+                .by("mail.example.com")
+                .with("ESMTPSA")
+                .id("A1B2C3")
+                .recipient("jane@example.org")
+                .date(1634418000),
+        );
+        message.received(
+            Received::new()
+                .from("mail.example.com (mail.example.com [198.51.100.2])")
+                .by("mx.example.org")
+                .with("ESMTPS")
+                .id("D4E5F6")
+                .recipient("<jane@example.org>")
+                .date(1634418005),
+        );
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(
+            output.starts_with(concat!(
+                "Received: from mail.example.com (mail.example.com [198.51.100.2])\r\n",
+                "\tby mx.example.org with ESMTPS id D4E5F6 for <jane@example.org>;\r\n",
+                "\tSat, 16 Oct 2021 21:00:05 +0000\r\n",
+                "Received: from client.example.com (client.example.com [192.0.2.1])\r\n",
+                "\tby mail.example.com with ESMTPSA id A1B2C3 for <jane@example.org>;\r\n",
+                "\tSat, 16 Oct 2021 21:00:00 +0000\r\n",
+                "Date: ",
+            )),
+            "{}",
+            output
+        );
+        Message::parse(output.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn build_message_id_formats() {
+        use crate::headers::message_id::MessageIdFormat;
+
+        let is_hex = |value: &str| value.chars().all(|ch| matches!(ch, '0'..='9' | 'a'..='f'));
+
+        for format in [
+            MessageIdFormat::Boundary,
+            MessageIdFormat::Uuid,
+            MessageIdFormat::TimestampRandom,
+            MessageIdFormat::RandomToken,
+        ] {
+            let mut message = MessageBuilder::new();
+            message.from("john@example.com");
+            message.message_id_format(format);
+            message.text_body("Hello, world!");
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let message = Message::parse(&output).unwrap();
+            let id = message.get_message_id().unwrap();
+
+            if format == MessageIdFormat::Boundary {
+                assert!(!id.contains('@'), "{}", id);
+                let parts = id.split('_').collect::<Vec<_>>();
+                assert_eq!(parts.len(), 3, "{}", id);
+                assert!(parts.iter().all(|part| is_hex(part)), "{}", id);
+                continue;
+            }
+
+            let (local, domain) = id.rsplit_once('@').unwrap();
+            assert_eq!(domain, "example.com");
+            match format {
+                MessageIdFormat::Uuid => {
+                    let groups = local.split('-').collect::<Vec<_>>();
+                    assert_eq!(
+                        groups.iter().map(|g| g.len()).collect::<Vec<_>>(),
+                        [8, 4, 4, 4, 12],
+                        "{}",
+                        id
+                    );
+                    assert!(groups.iter().all(|g| is_hex(g)), "{}", id);
+                    assert!(groups[2].starts_with('4'), "{}", id);
+                    assert!(groups[3].starts_with(['8', '9', 'a', 'b']), "{}", id);
+                }
+                MessageIdFormat::TimestampRandom => {
+                    let (timestamp, random) = local.split_once('.').unwrap();
+                    assert!(timestamp.parse::<u64>().unwrap() > 1_600_000_000_000);
+                    assert_eq!(random.len(), 16, "{}", id);
+                    assert!(is_hex(random), "{}", id);
+                }
+                MessageIdFormat::RandomToken => {
+                    assert_eq!(local.len(), 32, "{}", id);
+                    assert!(is_hex(local), "{}", id);
+                }
+                MessageIdFormat::Boundary => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn build_html_quoted_printable() {
+        let html = "<p class=\"jp\">日本</p>\n".repeat(10) + "<img src=\"cid:logo\">\n";
+        let mut message = MessageBuilder::new();
+        message.html_body(html.as_str());
+        message.binary_related("image/png", "logo", [0, 1, 2, 3].as_ref());
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("Content-Transfer-Encoding: quoted-printable\r\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("<img src=3D\"cid:logo\">\r\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("<p class=3D\"jp\">=E6=97=A5=E6=9C=AC</p>\r\n"),
+            "{}",
+            output
+        );
+
+        let message = Message::parse(output.as_bytes()).unwrap();
+        assert_eq!(message.get_html_body(0).unwrap().replace('\r', ""), html);
+
+        // Contents that are not mostly ASCII are still encoded as base64.
+        let mut message = MessageBuilder::new();
+        message.html_body("<p>日本</p>");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("Content-Transfer-Encoding: base64\r\n"),
+            "{}",
+            output
+        );
+
+        // The same contents in a plain text part use the smaller encoding,
+        // even when the part prefers quoted-printable.
+        let mut message = MessageBuilder::new();
+        message.text_body = Some(MimePart::new_text(html.as_str()).prefer_quoted_printable());
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("Content-Transfer-Encoding: base64\r\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn build_fingerprint() {
+        let build = |subject: &'static str| {
+            let mut message = MessageBuilder::new();
+            message.from(("John Doe", "john@example.com"));
+            message.to("jane@example.com");
+            message.subject(subject);
+            message.text_body("Hello, world!");
+            message.html_body("<p>Hello, world!</p>");
+            message.binary_attachment("image/png", "image.png", [1, 2, 3, 4].as_ref());
+            message
+        };
+
+        let fingerprint = build("Hi!").fingerprint().unwrap();
+        assert_eq!(build("Hi!").fingerprint().unwrap(), fingerprint);
+        assert_eq!(
+            build("Hi!").into_owned().fingerprint().unwrap(),
+            fingerprint
+        );
+        assert_ne!(build("Hello!").fingerprint().unwrap(), fingerprint);
+
+        let mut message = build("Hi!");
+        message.text_attachment("text/plain", "notes.txt", "Notes");
+        assert_ne!(message.fingerprint().unwrap(), fingerprint);
+
+        // Options that change the output change the fingerprint.
+        let mut message = build("Hi!");
+        message.boundary_prefix("mixed");
+        assert_ne!(message.fingerprint().unwrap(), fingerprint);
+        let mut message = build("Hi!");
+        message.text_body = message.text_body.map(|part| part.content_length());
+        assert_ne!(message.fingerprint().unwrap(), fingerprint);
+
+        // Options without effect on the output keep the fingerprint, while a
+        // Date header set by the user changes it.
+        let mut message = build("Hi!");
+        message.text_body = message.text_body.map(|part| part.prefer_quoted_printable());
+        assert_eq!(message.fingerprint().unwrap(), fingerprint);
+        let mut message = build("Hi!");
+        message.uppercase_charset_labels();
+        assert_eq!(message.fingerprint().unwrap(), fingerprint);
+        let mut message = build("Hi!");
+        message.legacy_attachment_names();
+        assert_eq!(message.fingerprint().unwrap(), fingerprint);
+        let mut message = build("Hi!");
+        message.date(1651103113);
+        assert_ne!(message.fingerprint().unwrap(), fingerprint);
+
+        // Errors reading the contents are returned.
+        let mut message = build("Hi!");
+        message.body(MimePart::new_file(
+            "application/pdf",
+            "/nonexistent/missing.pdf",
+        ));
+        assert!(message.fingerprint().is_err());
+    }
+
+    #[test]
+    fn build_utf7_parts() {
+        use crate::encoders::utf7::utf7_decode;
+
+        let mut message = MessageBuilder::new();
+        message.default_charset("utf-7");
+        message.text_body("Grüße, 1 + 1 = 2");
+        message.binary_attachment(
+            "application/octet-stream",
+            "data.bin",
+            [0xc3, 0xbc].as_ref(),
+        );
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains(concat!(
+                "Content-Type: text/plain; charset=utf-7\r\n",
+                "Content-Transfer-Encoding: 7bit\r\n\r\n",
+                "Gr+APwA3w-e, 1 +- 1 = 2\r\n"
+            )),
+            "{}",
+            output
+        );
+        assert_eq!(
+            utf7_decode("Gr+APwA3w-e, 1 +- 1 = 2", false).unwrap(),
+            "Grüße, 1 + 1 = 2"
+        );
+        assert!(output.contains("Content-Transfer-Encoding: base64\r\n"));
+
+        let part = MimePart::new_html("<p>Grüße</p>").utf7();
+        assert!(matches!(&part.contents, BodyPart::Text(text) if text == "<p>Gr+APwA3w-e</p>"));
+    }
+
+    #[test]
+    fn build_headers_named() {
+        let mut message = MessageBuilder::new();
+        message.subject("Hello, world!");
+        message.received(Received::new().by("mail.example.com").id("first"));
+        message.received(Received::new().by("mx.example.org").id("second"));
+
+        let received = message.headers_named("received");
+        assert_eq!(received.len(), 2);
+        for (value, id) in received.iter().zip(["second", "first"]) {
+            match value {
+                HeaderType::Received(received) => assert_eq!(received.id.as_deref(), Some(id)),
+                _ => panic!("Unexpected header {:?}", value),
+            }
+        }
+        assert!(matches!(
+            message.headers_named("Subject"),
+            [HeaderType::Text(text)] if text.text == "Hello, world!"
+        ));
+        assert!(message.headers_named("Cc").is_empty());
+    }
+
+    #[test]
+    fn build_base64url() {
+        let decode = |input: &str| {
+            let mut output = Vec::new();
+            let mut bits = 0u32;
+            let mut bit_count = 0;
+            for ch in input.bytes() {
+                let value = match ch {
+                    b'A'..=b'Z' => ch - b'A',
+                    b'a'..=b'z' => ch - b'a' + 26,
+                    b'0'..=b'9' => ch - b'0' + 52,
+                    b'-' => 62,
+                    b'_' => 63,
+                    _ => panic!("Invalid base64url character {:?}", ch as char),
+                };
+                bits = (bits << 6) | value as u32;
+                bit_count += 6;
+                if bit_count >= 8 {
+                    bit_count -= 8;
+                    output.push((bits >> bit_count) as u8);
+                }
+            }
+            output
+        };
+        let build = || {
+            let mut message = MessageBuilder::new();
+            message.from(("John Doe", "john@example.com"));
+            message.to("jane@example.com");
+            message.message_id("unique-id@example.com");
+            message.header("Date", Date::new(1634418000));
+            message.subject("¿Hola? ~>");
+            message.text_body("Hello, world!");
+            message
+        };
+
+        let mut expected = Vec::new();
+        build().write_to(&mut expected).unwrap();
+        let encoded = build().write_base64url().unwrap();
+        assert!(!encoded.contains(['+', '/', '=', '\r', '\n']));
+        assert_eq!(decode(&encoded), expected);
+    }
+
+    #[test]
+    fn build_content_length() {
+        let text = "Hello,\nworld!\n";
+        let data = [0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let mut message = MessageBuilder::new();
+        message.body(MimePart::new_multipart(
+            "multipart/mixed",
+            vec![
+                MimePart::new_text(text).content_length(),
+                MimePart::new_binary("message/rfc822", "Subject: Hi\r\n\r\nHi!\r\n".as_bytes())
+                    .content_length(),
+                MimePart::new_binary("application/octet-stream", data.as_ref())
+                    .attachment("data.bin")
+                    .content_length(),
+            ],
+        ));
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("Content-Length: 0"), "{}", output);
+
+        let lengths = output
+            .split("\r\n")
+            .filter_map(|line| line.strip_prefix("Content-Length: "))
+            .map(|len| len.parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lengths,
+            [
+                "Hello,\r\nworld!\r\n".len(),
+                "Subject: Hi\r\n\r\nHi!\r\n".len(),
+                data.len()
+            ],
+            "{}",
+            output
+        );
+        Message::parse(output.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn build_multipart_type() {
+        let mut message = MessageBuilder::new();
+        message.from("mailer-daemon@example.com");
+        message.to("john@example.com");
+        message.multipart_type(
+            ContentType::new("multipart/report").attribute("report-type", "delivery-status"),
+        );
+        message.text_body("Your message could not be delivered.");
+        message.text_attachment(
+            "message/delivery-status",
+            "status.txt",
+            "Reporting-MTA: dns; mx.example.com\r\n",
+        );
+        assert_eq!(message.content_type().as_deref(), Some("multipart/report"));
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let message = Message::parse(&output).unwrap();
+        let content_type = message.get_content_type().unwrap();
+        assert_eq!(content_type.get_type(), "multipart");
+        assert_eq!(content_type.get_subtype(), Some("report"));
+        assert_eq!(
+            content_type.get_attribute("report-type"),
+            Some("delivery-status")
+        );
+        assert!(content_type.get_attribute("boundary").is_some());
+        assert_eq!(
+            message.get_text_body(0).unwrap().trim_end(),
+            "Your message could not be delivered."
+        );
+    }
+
+    #[test]
+    fn build_stats() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.text_body("Hello, world!");
+        message.html_body("<p>Hello, world!</p>");
+        message.binary_related("image/png", "logo", [0, 1, 2, 3].as_ref());
+        message.binary_attachment("application/pdf", "report.pdf", [0; 100].as_ref());
+        message.text_attachment("text/csv", "data.csv", "a,b\n1,2\n");
+        assert_eq!(
+            message.stats().unwrap(),
+            crate::MessageStats {
+                parts: 5,
+                attachments: 3,
+                attachment_bytes: 112,
+                has_text: true,
+                has_html: true,
+                content_type: Some("multipart/mixed".to_string()),
+            }
+        );
+
+        let mut message = MessageBuilder::new();
+        message.html_body("<p>Hello, world!</p>");
+        assert_eq!(
+            message.stats().unwrap(),
+            crate::MessageStats {
+                parts: 1,
+                has_html: true,
+                content_type: Some("text/html".to_string()),
+                ..Default::default()
+            }
+        );
+
+        // Stats are computed from the parts that are written.
+        let mut message = MessageBuilder::new();
+        message.text_body("Replaced");
+        message.body(MimePart::new_binary("application/pdf", [0; 10].as_ref()));
+        assert_eq!(
+            message.stats().unwrap(),
+            crate::MessageStats {
+                parts: 1,
+                attachments: 1,
+                attachment_bytes: 10,
+                content_type: Some("application/pdf".to_string()),
+                ..Default::default()
+            }
+        );
+
+        let mut message = MessageBuilder::new();
+        message.raw_mime_body(ContentType::new("text/plain"), b"Hello\r\n".as_ref());
+        assert_eq!(
+            message.stats().unwrap(),
+            crate::MessageStats {
+                parts: 1,
+                has_text: true,
+                content_type: Some("text/plain".to_string()),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            MessageBuilder::new().stats().unwrap(),
+            crate::MessageStats {
+                parts: 1,
+                has_text: true,
+                content_type: Some("text/plain".to_string()),
+                ..Default::default()
+            }
+        );
+
+        // The message can still be written, and errors reading the size of
+        // files are returned.
+        let mut message = MessageBuilder::new();
+        message.text_body("Hello, world!");
+        assert_eq!(message.stats().unwrap().parts, 1);
+        message.write_to(Vec::new()).unwrap();
+        let mut message = MessageBuilder::new();
+        message.body(MimePart::new_file(
+            "application/pdf",
+            "/nonexistent/missing.pdf",
+        ));
+        assert!(message.stats().is_err());
+    }
+
+    #[test]
+    fn build_charset_rendering() {
+        for (quoted, expected_text, expected_html) in [
+            (
+                false,
+                "Content-Type: text/plain; charset=utf-8; format=\"flowed\"\r\n",
+                "Content-Type: text/html; charset=utf-8\r\n",
+            ),
+            (
+                true,
+                "Content-Type: text/plain; charset=\"utf-8\"; format=\"flowed\"\r\n",
+                "Content-Type: text/html; charset=\"UTF-8\"\r\n",
+            ),
+        ] {
+            let mut message = MessageBuilder::new();
+            message.format_flowed();
+            message.text_body("Hello, world!");
+            message.html_body("<p>Hello, world!</p>");
+            message.html_body_attribute("charset", "UTF-8");
+            if quoted {
+                message.quoted_charset();
+            }
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains(expected_text), "{}", output);
+            assert!(output.contains(expected_html), "{}", output);
+        }
+
+        let mut output = Vec::new();
+        ContentType::new("text/plain")
+            .attribute("a-param", "value")
+            .attribute("charset", "ISO-8859-1")
+            .write_header(&mut output, 14)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "text/plain; charset=iso-8859-1; a-param=\"value\"\r\n"
+        );
+    }
+
+    #[test]
+    fn build_vcard() {
+        let note = "Met at the conference, interested in the project and ".repeat(2) + "ünïcödé.";
+        let vcard = format!(
+            "BEGIN:VCARD\nVERSION:4.0\nFN:Jane Doe\nEMAIL:jane@example.com\nNOTE:{}\nEND:VCARD\n",
+            note
+        );
+        let mut message = MessageBuilder::new();
+        message.text_body("Please find my contact card attached.");
+        message.vcard(&vcard);
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("Content-Type: text/vcard; charset=utf-8\r\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("Content-Disposition: attachment; filename=\"contact.vcf\"\r\n"),
+            "{}",
+            output
+        );
+
+        let message = Message::parse(output.as_bytes()).unwrap();
+        let contents = message
+            .get_attachment(0)
+            .unwrap()
+            .unwrap_text()
+            .body
+            .as_ref();
+        assert!(contents.lines().count() > 6, "{}", contents);
+        assert!(
+            contents.split("\r\n").all(|line| line.len() <= 75),
+            "{}",
+            contents
+        );
+        assert!(contents.contains("\r\n "), "{}", contents);
+        assert_eq!(contents.replace("\r\n ", "").replace("\r\n", "\n"), vcard);
+    }
+
+    #[test]
+    fn build_folded_content_lines() {
+        for (note, encoding) in [
+            ("x".repeat(195) + "äöü", "quoted-printable"),
+            ("日本語".repeat(23), "base64"),
+        ] {
+            let vcard = format!("BEGIN:VCARD\r\nNOTE:{}\r\nEND:VCARD\r\n", note);
+            let mut message = MessageBuilder::new();
+            message.fold_content_lines();
+            message.text_body("Contact card attached.");
+            message.text_attachment("text/vcard", "contact.vcf", vcard.as_str());
+            message.binary_attachment("text/calendar", "invite.ics", vcard.as_bytes());
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(
+                output.contains(&format!("Content-Transfer-Encoding: {}\r\n", encoding)),
+                "{}",
+                output
+            );
+
+            let message = Message::parse(output.as_bytes()).unwrap();
+            for pos in 0..2 {
+                let contents = match message.get_attachment(pos).unwrap() {
+                    mail_parser::MessagePart::Text(part) => part.body.to_string(),
+                    mail_parser::MessagePart::Binary(part) => {
+                        String::from_utf8(part.body.to_vec()).unwrap()
+                    }
+                    _ => unreachable!(),
+                };
+                assert!(contents.lines().count() > 4, "{}", contents);
+                assert!(
+                    contents.lines().all(|line| line.len() <= 75),
+                    "{}",
+                    contents
+                );
+                assert_eq!(contents.replace("\r\n ", ""), vcard);
+            }
+        }
+    }
+
+    #[test]
+    fn build_body_with_attachments() {
+        // A multipart/mixed body gets the attachments appended.
+        let mut message = MessageBuilder::new();
+        message.body(MimePart::new_multipart(
+            "multipart/mixed",
+            vec![
+                MimePart::new_text("Hello, world!"),
+                MimePart::new_binary("image/png", [1, 2, 3].as_ref()).attachment("a.png"),
+            ],
+        ));
+        message.binary_attachment("image/png", "b.png", [4, 5, 6].as_ref());
+        assert_eq!(message.content_type().as_deref(), Some("multipart/mixed"));
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("multipart/mixed").count(), 1, "{}", output);
+        let message = Message::parse(output.as_bytes()).unwrap();
+        assert_eq!(message.get_attachment_count(), 2);
+        assert_eq!(
+            message.get_text_body(0).unwrap().trim_end(),
+            "Hello, world!"
+        );
+
+        // Other bodies are wrapped together with the attachments.
+        let mut message = MessageBuilder::new();
+        message.body(MimePart::new_multipart(
+            "multipart/alternative",
+            vec![
+                MimePart::new_text("Hello, world!"),
+                MimePart::new_html("<p>Hello, world!</p>"),
+            ],
+        ));
+        message.binary_attachment("image/png", "b.png", [4, 5, 6].as_ref());
+        assert_eq!(message.content_type().as_deref(), Some("multipart/mixed"));
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.find("multipart/mixed").unwrap() < output.find("multipart/alternative").unwrap(),
+            "{}",
+            output
+        );
+        let message = Message::parse(output.as_bytes()).unwrap();
+        assert_eq!(message.get_attachment_count(), 1);
+        assert_eq!(
+            message.get_html_body(0).unwrap().trim_end(),
+            "<p>Hello, world!</p>"
+        );
+    }
+
+    #[test]
+    fn build_strict_mode() {
+        let mut message = MessageBuilder::new();
+        message.strict();
+        message.text_body("Hello, world!");
+        message.html_body("<p>Hello, world!</p>");
+        message.write_to(Vec::new()).unwrap();
+
+        for strict in [false, true] {
+            let mut message = MessageBuilder::new();
+            if strict {
+                message.strict();
+            }
+            message.text_body("Hello, world!");
+            message.text_body("Goodbye, world!");
+            let result = message.write_to(Vec::new());
+            if strict {
+                assert_eq!(
+                    crate::Error::from_io(&result.unwrap_err()),
+                    Some(&crate::Error::BodyOverwritten)
+                );
+            } else {
+                result.unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn build_bytes_body_offset() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.subject("Offsets");
+        message.text_body("Hello\r\n\r\nworld");
+        let (bytes, body_offset) = message.build_bytes().unwrap();
+
+        let (headers, body) = bytes.split_at(body_offset);
+        assert!(headers.ends_with(b"\r\n\r\n"));
+        assert_eq!(
+            headers
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+                .unwrap(),
+            headers.len() - 4
+        );
+        assert!(headers.starts_with(b"Date: "));
+        assert_eq!(body, b"Hello\r\n\r\nworld");
+    }
+
+    #[test]
+    fn build_binary_inline_with_filename() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.html_body("<img src=\"cid:logo@example.com\">");
+        message.binary_inline_with_filename(
+            "image/png",
+            "logo@example.com",
+            "img.png",
+            [0, 1, 2].as_ref(),
+        );
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let part = output
+            .split("\r\n\r\n")
+            .find(|block| block.contains("image/png"))
+            .unwrap()
+            .to_string()
+            + "\r\n";
+        assert!(part.contains("Content-Type: image/png\r\n"));
+        assert!(part.contains("Content-ID: <logo@example.com>\r\n"));
+        assert!(part.contains("Content-Disposition: inline; filename=\"img.png\"\r\n"));
+    }
+
+    #[test]
+    fn build_ics_attachment() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.text_body("You are invited.");
+        message.ics_attachment(
+            "invite.ics",
+            concat!(
+                "BEGIN:VCALENDAR\r\n",
+                "VERSION:2.0\r\n",
+                "METHOD:REQUEST\r\n",
+                "BEGIN:VEVENT\r\n",
+                "SUMMARY:Planning\r\n",
+                "END:VEVENT\r\n",
+                "END:VCALENDAR\r\n"
+            ),
+        );
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(
+            output.contains("Content-Type: text/calendar; charset=utf-8; method=\"REQUEST\"\r\n")
+        );
+        assert!(output.contains("Content-Disposition: attachment; filename=\"invite.ics\"\r\n"));
+
+        let message = Message::parse(output.as_bytes()).unwrap();
+        let attachment = message.get_attachment(0).unwrap();
+        assert!(attachment.unwrap_text().body.contains("METHOD:REQUEST\r\n"));
+    }
+
+    #[test]
+    fn build_write_smtp_data() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.subject("Dots");
+        message.text_body(".\r\n.hidden\r\nnot.stuffed");
+        let mut output = Vec::new();
+        message.write_smtp_data(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.ends_with("\r\n\r\n..\r\n..hidden\r\nnot.stuffed\r\n.\r\n"));
+        assert_eq!(output.matches("\r\n.\r\n").count(), 1);
+    }
+
+    #[test]
+    fn build_omit_headers() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.to("jane@example.com");
+        message.bcc("hidden@example.com");
+        message.subject("Omitted");
+        message.header("X-Internal-Queue", Raw::new("outbound"));
+        message.header("x-internal-id", Raw::new("1234"));
+        message.header("X-Mailer", Raw::new("mail-builder"));
+        message.text_body("Hello");
+        message.omit_headers(&["bcc", "X-Internal-*"]);
+        assert_eq!(message.headers_named("Bcc").len(), 1);
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(!output.contains("Bcc:"));
+        assert!(!output.contains("hidden@example.com"));
+        assert!(!output.to_ascii_lowercase().contains("x-internal"));
+        assert!(output.contains("To: <jane@example.com>\r\n"));
+        assert!(output.contains("X-Mailer: mail-builder\r\n"));
+    }
+
+    #[test]
+    fn build_raw_mime_body() {
+        let body = concat!(
+            "This is a multi-part message.\r\n",
+            "--gw-boundary\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "Hello from the gateway\r\n",
+            "--gw-boundary--\r\n"
+        );
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.subject("Wrapped");
+        message.raw_mime_body(
+            ContentType::new("multipart/mixed").attribute("boundary", "gw-boundary"),
+            body.as_bytes(),
+        );
+        assert_eq!(message.content_type().as_deref(), Some("multipart/mixed"));
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.ends_with(&format!(
+            "Content-Type: multipart/mixed; boundary=\"gw-boundary\"\r\n\r\n{}",
+            body
+        )));
+        let parsed = Message::parse(output.as_bytes()).unwrap();
+        assert_eq!(parsed.get_subject(), Some("Wrapped"));
+        assert!(parsed
+            .get_text_body(0)
+            .unwrap()
+            .starts_with("Hello from the gateway"));
+
+        let mut message = MessageBuilder::new();
+        message.text_body("Hello");
+        message.raw_mime_body(ContentType::new("text/plain"), b"Hello".as_ref());
+        let err = message.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(
+            crate::Error::from_io(&err),
+            Some(&crate::Error::ConflictingBody)
+        );
+    }
+
+    #[test]
+    fn build_user_agent() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.default_user_agent();
+        message.x_mailer("Correo Électronique 2.0");
+        message.text_body("Hello");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains(&format!(
+            "User-Agent: mail-builder/{}\r\n",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(output.contains("X-Mailer: =?utf-8?Q?Correo_=C3=89lectronique_2.0?=\r\n"));
+
+        let mut message = MessageBuilder::new();
+        message.default_user_agent();
+        message.user_agent("Custom Mailer");
+        assert_eq!(message.headers_named("User-Agent").len(), 1);
+    }
+
+    #[test]
+    fn build_empty_body() {
+        let build = |empty_body: bool| {
+            let mut message = MessageBuilder::new();
+            message.from("john@example.com");
+            message.subject("Ping");
+            message.date(1651103113);
+            message.message_id("ping@example.com");
+            if empty_body {
+                message.empty_body();
+            }
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert!(build(false).ends_with(concat!(
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "Content-Transfer-Encoding: 7bit\r\n",
+            "\r\n",
+            "\r\n"
+        )));
+        assert_eq!(
+            build(true),
+            concat!(
+                "Date: Wed, 27 Apr 2022 23:45:13 +0000\r\n",
+                "From: <john@example.com>\r\n",
+                "Subject: Ping\r\n",
+                "Message-ID: <ping@example.com>\r\n",
+                "MIME-Version: 1.0\r\n",
+                "\r\n"
+            )
+        );
+
+        // Headers-only messages have no Content-Type.
+        let mut message = MessageBuilder::new();
+        message.empty_body();
+        assert_eq!(message.content_type(), None);
+        assert_eq!(message.stats().unwrap(), crate::MessageStats::default());
+
+        // The option has no effect on messages with a body.
+        let mut message = MessageBuilder::new();
+        message.empty_body();
+        message.text_body("Hello");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .ends_with("Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 7bit\r\n\r\nHello"));
+    }
+
+    #[test]
+    fn build_mime_tree() {
+        let build = || {
+            let mut message = MessageBuilder::new();
+            message.from("john@example.com");
+            message.text_body("Plain ASCII text");
+            message.html_body("<p>Hélène écrit en français</p>");
+            message.text_attachment("text/plain", "notes.txt", "ありがとう");
+            message.binary_attachment("image/png", "image.png", [0u8, 1, 2, 3].as_ref());
+            message.text_attachment("text/csv", "data.csv", "a,b\r\nc,d");
+            message
+        };
+        let leaf = |content_type: &str, encoding: &str| MimeNode {
+            content_type: content_type.to_string(),
+            encoding: Some(encoding.to_string()),
+            children: Vec::new(),
+        };
+
+        let tree = build().mime_tree().unwrap().unwrap();
+        assert_eq!(
+            tree,
+            MimeNode {
+                content_type: "multipart/mixed".to_string(),
+                encoding: None,
+                children: vec![
+                    MimeNode {
+                        content_type: "multipart/alternative".to_string(),
+                        encoding: None,
+                        children: vec![
+                            leaf("text/plain", "7bit"),
+                            leaf("text/html", "quoted-printable"),
+                        ],
+                    },
+                    leaf("text/plain", "base64"),
+                    leaf("image/png", "base64"),
+                    leaf("text/csv", "7bit"),
+                ],
+            }
+        );
+
+        // The encodings match the serialized output, in the same order.
+        let mut output = Vec::new();
+        build().write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let written = output
+            .lines()
+            .filter_map(|line| line.strip_prefix("Content-Transfer-Encoding: "))
+            .collect::<Vec<_>>();
+        let mut expected = Vec::new();
+        let mut nodes = vec![&tree];
+        while let Some(node) = nodes.pop() {
+            expected.extend(node.encoding.as_deref());
+            nodes.extend(node.children.iter().rev());
+        }
+        assert_eq!(written, expected);
+
+        let mut message = MessageBuilder::new();
+        message.empty_body();
+        assert_eq!(message.mime_tree().unwrap(), None);
+    }
+
+    #[test]
+    fn build_reply_to_self() {
+        let mut message = MessageBuilder::new();
+        assert!(!message.reply_to_self());
+        assert!(message.headers_named("Reply-To").is_empty());
+
+        message.from(("John Doe", "john@example.com"));
+        message.reply_to("old@example.com");
+        assert!(message.reply_to_self());
+        message.text_body("Hello");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("From: \"John Doe\" <john@example.com>\r\n"));
+        assert!(output.contains("Reply-To: \"John Doe\" <john@example.com>\r\n"));
+        assert!(!output.contains("old@example.com"));
+    }
+
+    #[test]
+    fn build_simple_mixed_layout() {
+        let build = |simple_mixed: bool| {
+            let mut message = MessageBuilder::new();
+            message.text_body("Hello");
+            message.html_body("<p>Hello</p>");
+            message.binary_attachment("image/png", "image.png", [0u8, 1, 2].as_ref());
+            if simple_mixed {
+                message.simple_mixed();
+            }
+            let tree = message.mime_tree().unwrap().unwrap();
+            assert_eq!(tree.content_type, "multipart/mixed");
+            tree.children
+                .into_iter()
+                .map(|child| {
+                    let mut content_type = child.content_type;
+                    for grandchild in child.children {
+                        content_type = format!("{} {}", content_type, grandchild.content_type);
+                    }
+                    content_type
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            build(false),
+            ["multipart/alternative text/plain text/html", "image/png"]
+        );
+        assert_eq!(build(true), ["text/plain", "text/html", "image/png"]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn build_async_attachment() {
+        let contents = (0..200_000u32)
+            .map(|n| (n.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect::<Vec<_>>();
+
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.text_body("See attached.");
+        message.async_attachment(
+            "application/octet-stream",
+            "large.bin",
+            std::io::Cursor::new(contents.clone()),
+        );
+
+        let (writer, mut reader) = tokio::io::duplex(4096);
+        let (result, output) = tokio::join!(message.write_to_async(writer), async {
+            let mut output = Vec::new();
+            tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut output)
+                .await
+                .unwrap();
+            output
+        });
+        result.unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("filename=\"large.bin\""));
+        assert!(output.lines().all(|line| line.len() <= 76));
+        let parsed = Message::parse(output.as_bytes()).unwrap();
+        assert_eq!(
+            parsed.get_attachment(0).unwrap().unwrap_binary().body,
+            contents
+        );
+
+        let mut message = MessageBuilder::new();
+        message.async_attachment("text/plain", "a.txt", std::io::Cursor::new(Vec::new()));
+        let err = message.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(
+            crate::Error::from_io(&err),
+            Some(&crate::Error::AsyncAttachment)
+        );
+
+        // Async parts are streamed even when other parts are rewritten, and
+        // do not change the fingerprint of the message.
+        let invite = "BEGIN:VCALENDAR\r\nMETHOD:REQUEST\r\nEND:VCALENDAR\r\n";
+        let build = || {
+            let mut message = MessageBuilder::new();
+            message.from("john@example.com");
+            message.fold_content_lines();
+            message.text_body("Invitation attached.");
+            message.async_attachment(
+                "text/calendar",
+                "invite.ics",
+                std::io::Cursor::new(invite.as_bytes().to_vec()),
+            );
+            message
+        };
+        assert_eq!(
+            build().fingerprint().unwrap(),
+            build().fingerprint().unwrap()
+        );
+        assert_eq!(build().stats().unwrap().attachments, 1);
+        let mut output = Vec::new();
+        build().write_to_async(&mut output).await.unwrap();
+        let parsed = Message::parse(&output).unwrap();
+        assert_eq!(parsed.get_attachment(0).unwrap().unwrap_text().body, invite);
+    }
+
+    #[test]
+    fn build_binary_inline_and_attachment() {
+        let image = [0x89u8, b'P', b'N', b'G', 0, 1, 2, 3];
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.text_body("Logo attached");
+        message.html_body("<img src=\"cid:logo@example.com\">");
+        message.binary_inline_and_attachment(
+            "image/png",
+            "logo@example.com",
+            "logo.png",
+            image.as_ref(),
+        );
+
+        let tree = message.mime_tree().unwrap().unwrap();
+        assert_eq!(tree.content_type, "multipart/mixed");
+        assert_eq!(tree.children[1].content_type, "image/png");
+        let alternative = &tree.children[0];
+        assert_eq!(alternative.children[1].content_type, "multipart/related");
+        assert_eq!(
+            alternative.children[1].children[1].content_type,
+            "image/png"
+        );
+
+        let mut message = MessageBuilder::new();
+        message.html_body("<img src=\"cid:logo@example.com\">");
+        message.binary_inline_and_attachment(
+            "image/png",
+            "logo@example.com",
+            "logo.png",
+            image.as_ref(),
+        );
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Content-ID: <logo@example.com>\r\n"));
+        assert!(output.contains("Content-Disposition: inline; filename=\"logo.png\"\r\n"));
+        assert!(output.contains("Content-Disposition: attachment; filename=\"logo.png\"\r\n"));
+
+        let parsed = Message::parse(output.as_bytes()).unwrap();
+        let parts = (0..2)
+            .map(|n| {
+                parsed
+                    .get_attachment(n)
+                    .unwrap()
+                    .unwrap_binary()
+                    .body
+                    .to_vec()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(parts, [image.to_vec(), image.to_vec()]);
+    }
+
+    #[test]
+    fn build_boundary_prefix() {
+        let build = |prefix: &str| {
+            let mut message = MessageBuilder::new();
+            message.boundary_prefix(prefix);
+            message.text_body("Hello");
+            message.html_body("<p>Hello</p>");
+            message.binary_attachment("image/png", "image.png", [0u8, 1, 2].as_ref());
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            output
+                .split("boundary=\"")
+                .skip(1)
+                .map(|value| value.split('"').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let boundaries = build("mycompany");
+        assert_eq!(boundaries.len(), 2);
+        assert!(boundaries
+            .iter()
+            .all(|boundary| boundary.starts_with("mycompany_")));
+
+        let boundaries = build("my company; \"x\"\r\n");
+        assert!(boundaries
+            .iter()
+            .all(|boundary| boundary.starts_with("mycompanyx_") && boundary.len() <= 70));
+
+        let boundaries = build(&"long".repeat(30));
+        assert!(boundaries
+            .iter()
+            .all(|boundary| boundary.starts_with("long") && boundary.len() <= 70));
+    }
+
+    #[test]
+    fn build_binary_text_if_utf8() {
+        let write = |part: MimePart| {
+            let mut message = MessageBuilder::new();
+            message.body(part);
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let part = MimePart::new_binary("text/plain", "Hola, ¿qué tal?".as_bytes()).text_if_utf8();
+        assert!(matches!(part.contents, BodyPart::Text(_)));
+        let output = write(part);
+        assert!(output.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(output.contains("Content-Transfer-Encoding: quoted-printable\r\n"));
+
+        let output = write(MimePart::new_binary("text/csv", b"a,b\r\n1,2".to_vec()).text_if_utf8());
+        assert!(output.contains("Content-Type: text/csv; charset=utf-8\r\n"));
+        assert!(output.contains("Content-Transfer-Encoding: 7bit\r\n"));
+
+        // Invalid UTF-8 and non-textual parts are left untouched.
+        let part = MimePart::new_binary("text/plain", vec![0xff, 0xfe, b'a']).text_if_utf8();
+        assert!(
+            matches!(&part.contents, BodyPart::Binary(binary) if binary.as_ref() == [0xff, 0xfe, b'a'])
+        );
+        let part = MimePart::new_binary("image/png", "abc".as_bytes()).text_if_utf8();
+        assert!(matches!(part.contents, BodyPart::Binary(_)));
+    }
+
+    #[test]
+    fn build_generated_message_id() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.subject("New thread");
+        message.text_body("Hello");
+        message.message_id_format(crate::headers::message_id::MessageIdFormat::Uuid);
+        let id = message.generated_message_id().unwrap();
+        assert!(id.ends_with("@example.com"));
+        assert_eq!(message.generated_message_id().unwrap(), id);
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!("Message-ID: <{}>\r\n", id)));
+
+        let mut message = MessageBuilder::new();
+        message.message_id("custom@example.com");
+        assert_eq!(message.generated_message_id(), None);
+    }
+
+    #[test]
+    fn build_text_attachment_with_charset() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.text_body("Hello");
+        message.default_charset("us-ascii");
+        message.text_attachment_with_charset(
+            "application/x-ndjson",
+            "events.ndjson",
+            "{\"id\":1}\r\n{\"id\":2}\r\n",
+            None::<&str>,
+        );
+        message.text_attachment_with_charset(
+            "text/plain",
+            "legacy.txt",
+            "plain ascii",
+            Some("iso-8859-1"),
+        );
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("Content-Type: application/x-ndjson\r\n"));
+        assert!(!output.contains("application/x-ndjson; charset"));
+        assert!(output.contains("Content-Type: text/plain; charset=iso-8859-1\r\n"));
+        assert!(output.contains("Content-Type: text/plain; charset=us-ascii\r\n"));
+    }
+
+    #[cfg(feature = "ureq")]
+    #[test]
+    fn build_inline_image_from_url() {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let large = vec![0u8; crate::MAX_IMAGE_SIZE + 1];
+            for stream in listener.incoming().take(3) {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                reader.read_line(&mut request_line).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let (content_type, body): (&str, &[u8]) = if request_line.contains("/logo.png") {
+                    ("image/png", b"\x89PNG\r\n\x1a\n")
+                } else if request_line.contains("/large.png") {
+                    ("image/png", &large)
+                } else {
+                    ("text/html; charset=utf-8", b"<html>Not found</html>")
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    content_type,
+                    body.len()
+                )
+                .unwrap();
+                // The client stops reading large responses.
+                stream.write_all(body).ok();
+            }
+        });
+
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.html_body("<img src=\"logo\">");
+        let cid = message
+            .inline_image_from_url(&format!("http://{}/logo.png", addr))
+            .unwrap();
+        assert!(cid.ends_with("@example.com"));
+
+        let err = message
+            .inline_image_from_url(&format!("http://{}/missing", addr))
+            .unwrap_err();
+        assert_eq!(
+            crate::Error::from_io(&err),
+            Some(&crate::Error::NotAnImage("text/html".into()))
+        );
+
+        let err = message
+            .inline_image_from_url(&format!("http://{}/large.png", addr))
+            .unwrap_err();
+        assert_eq!(
+            crate::Error::from_io(&err),
+            Some(&crate::Error::ImageTooLarge)
+        );
+        server.join().unwrap();
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!("Content-ID: <{}>\r\n", cid)));
+        assert!(output.contains("Content-Type: image/png\r\n"));
+        assert_eq!(output.matches("Content-ID").count(), 1);
+
+        let parsed = Message::parse(output.as_bytes()).unwrap();
+        assert_eq!(
+            parsed
+                .get_attachment(0)
+                .unwrap()
+                .unwrap_binary()
+                .body
+                .as_ref(),
+            b"\x89PNG\r\n\x1a\n"
+        );
+    }
+
+    #[test]
+    fn build_custom_body_encoder() {
+        use crate::encoders::body::{Base64Encoder, BodyEncoder};
+
+        struct Rot13;
+
+        impl BodyEncoder for Rot13 {
+            fn name(&self) -> &str {
+                "x-rot13"
+            }
+
+            fn encode(&self, input: &[u8], output: &mut dyn std::io::Write) -> std::io::Result<()> {
+                let encoded = input
+                    .iter()
+                    .map(|&ch| match ch {
+                        b'a'..=b'z' => (ch - b'a' + 13) % 26 + b'a',
+                        b'A'..=b'Z' => (ch - b'A' + 13) % 26 + b'A',
+                        _ => ch,
+                    })
+                    .collect::<Vec<_>>();
+                output.write_all(&encoded)?;
+                output.write_all(b"\r\n")
+            }
+        }
+
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.body(MimePart::new_multipart(
+            "multipart/mixed",
+            vec![
+                MimePart::new_text("Hello world").encoder(Base64Encoder),
+                MimePart::new_binary("application/octet-stream", &b"Secret"[..])
+                    .header("Content-Transfer-Encoding", Raw::new("base64"))
+                    .encoder(Rot13),
+            ],
+        ));
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains(concat!(
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "Content-Transfer-Encoding: base64\r\n\r\n",
+            "SGVsbG8gd29ybGQ=\r\n"
+        )));
+        assert!(output.contains(concat!(
+            "Content-Type: application/octet-stream\r\n",
+            "Content-Transfer-Encoding: x-rot13\r\n\r\n",
+            "Frperg\r\n"
+        )));
+        assert_eq!(output.matches("Content-Transfer-Encoding").count(), 2);
+
+        // Encoder names must be valid tokens.
+        struct Invalid;
+
+        impl BodyEncoder for Invalid {
+            fn name(&self) -> &str {
+                "base64\r\nBcc: jane@example.com"
+            }
+
+            fn encode(&self, input: &[u8], output: &mut dyn std::io::Write) -> std::io::Result<()> {
+                Base64Encoder.encode(input, output)
+            }
+        }
+
+        let mut message = MessageBuilder::new();
+        message.body(MimePart::new_text("Hello world").encoder(Invalid));
+        let err = message.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(
+            crate::Error::from_io(&err),
+            Some(&crate::Error::InvalidEncoderName(
+                "base64\r\nBcc: jane@example.com".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn build_long_content_location() {
+        let url = format!(
+            "https://cdn.example.com/newsletters/2022/{}/header.png?v=1",
+            "x".repeat(1200)
+        );
+        let cid = format!("{}@example.com", "y".repeat(90));
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.body(MimePart::new_multipart(
+            "multipart/related",
+            vec![
+                MimePart::new_html("<img src=\"header.png\">"),
+                MimePart::new_binary("image/png", &b"\x89PNG"[..])
+                    .inline()
+                    .cid(cid.as_str())
+                    .location(url.as_str()),
+            ],
+        ));
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        assert_eq!(MessageBuilder::verify_output(&output), Ok(()));
+
+        let output = String::from_utf8(output).unwrap();
+        // Content-IDs are kept intact.
+        assert!(output.contains(&format!("Content-ID: <{}>\r\n", cid)));
+        assert!(output
+            .split("\r\n")
+            .all(|line| line.len() <= 78 || line.starts_with("Content-ID:")));
+
+        let (_, location) = output.split_once("Content-Location:").unwrap();
+        let location = &location[..location.find("\r\nContent-").unwrap()];
+        assert_eq!(location.replace("\r\n ", "").trim(), url);
+    }
+
+    #[test]
+    fn build_raw_part_passthrough() {
+        let signature = b"MIIBhgYJKoZIhvcNAQcCoIIBdzCCAXMCAQExDzANBglghkgBZQMEAgEFADAL\r\nBgkqhkiG9w0BBwExggFOMIIBSgIBATA=  \r\n";
+        let headers = vec![
+            (
+                "Content-Type",
+                "application/pkcs7-signature; name=smime.p7s;\r\n\tsmime-type=signed-data",
+            ),
+            ("Content-Transfer-Encoding", "base64"),
+            ("Content-Disposition", "attachment; filename=\"smime.p7s\""),
+            (
+                "X-Unfolded",
+                "a value that is much longer than seventy-six characters and is not folded at all",
+            ),
+        ];
+        let mut expected = Vec::new();
+        for (name, value) in &headers {
+            expected.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        expected.extend_from_slice(b"\r\n");
+        expected.extend_from_slice(signature);
+
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.body(
+            MimePart::new_multipart(
+                "multipart/signed",
+                vec![
+                    MimePart::new_text("Signed text"),
+                    MimePart::raw_part(headers, &signature[..]),
+                ],
+            )
+            .attribute("protocol", "application/pkcs7-signature")
+            .attribute("micalg", "sha-256"),
+        );
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+
+        let start = output
+            .windows(expected.len())
+            .position(|window| window == expected.as_slice())
+            .expect("raw part not written verbatim");
+        assert!(output[start + expected.len()..].starts_with(b"\r\n--"));
+        assert_eq!(
+            output
+                .windows(b"Content-Transfer-Encoding".len())
+                .filter(|window| window == b"Content-Transfer-Encoding")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn build_content_type_attribute_order() {
+        let mut output = Vec::new();
+        ContentType::new("text/plain")
+            .attribute("format", "flowed")
+            .attribute("delsp", "yes")
+            .attribute("charset", "utf-8")
+            .write_header(&mut output, 14)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "text/plain; charset=utf-8; delsp=\"yes\"; format=\"flowed\"\r\n"
+        );
+
+        let mut output = Vec::new();
+        ContentType::new("multipart/related")
+            .attribute("boundary", "my-boundary")
+            .attribute("type", "text/html")
+            .attribute("start", "<root@example.com>")
+            .write_header(&mut output, 14)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "multipart/related; start=\"<root@example.com>\"; \r\n",
+                "\ttype=\"text/html\"; boundary=\"my-boundary\"\r\n"
+            )
+        );
+    }
+
+    #[test]
+    fn build_max_references() {
+        let ids = (0..100)
+            .map(|num| format!("{}@example.com", num))
+            .collect::<Vec<_>>();
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.references(ids.clone());
+        message.max_references(5);
+        message.text_body("Hello");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        match Message::parse(output.as_bytes()).unwrap().get_references() {
+            HeaderValue::TextList(references) => assert_eq!(
+                references,
+                &[
+                    "0@example.com",
+                    "96@example.com",
+                    "97@example.com",
+                    "98@example.com",
+                    "99@example.com"
+                ]
+            ),
+            other => panic!("Unexpected References: {:?}", other),
+        }
+
+        // Short chains are left unchanged.
+        let mut message = MessageBuilder::new();
+        message.references(vec!["1@example.com", "2@example.com"]);
+        message.max_references(5);
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("References: <1@example.com> <2@example.com>\r\n"));
+    }
+
+    #[test]
+    fn build_write_body_only() {
+        let build = || {
+            let mut message = MessageBuilder::new();
+            message.from("john@example.com");
+            message.subject("Body only");
+            message.text_body("Hello world");
+            message.html_body("<p>Hello world</p>");
+            message
+        };
+
+        let mut body = Vec::new();
+        build().write_body_only(&mut body).unwrap();
+        let body = String::from_utf8(body).unwrap();
+        // The body does not start at the top-level Content-Type, which is a
+        // message header, but at what the DKIM body hash covers.
+        assert!(body.starts_with("\r\n--"));
+        assert!(!body.contains("From:"));
+        assert!(!body.contains("Subject:"));
+        assert!(!body.contains("multipart/alternative"));
+
+        // It matches the end of the full message, apart from the boundaries.
+        let mut message = Vec::new();
+        build().write_to(&mut message).unwrap();
+        let message = String::from_utf8(message).unwrap();
+        let boundary = message.split('"').nth(1).unwrap();
+        let body_boundary = body[4..].split("\r\n").next().unwrap();
+        assert_eq!(
+            &message[message.find("\r\n\r\n").unwrap() + 4..],
+            body.replace(body_boundary, boundary)
+        );
+
+        // Single part messages start with the encoded contents.
+        let mut message = MessageBuilder::new();
+        message.text_body("Hello world");
+        let mut body = Vec::new();
+        message.write_body_only(&mut body).unwrap();
+        assert_eq!(body, b"Hello world");
+
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.empty_body();
+        let mut body = Vec::new();
+        message.write_body_only(&mut body).unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn build_from_multiple() {
+        let mut message = MessageBuilder::new();
+        message.from_multiple(vec![
+            ("Jane Doe", "jane@example.com"),
+            ("John Doe", "john@example.com"),
+        ]);
+        message.to("team@example.com");
+        message.text_body("Written together");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains(concat!(
+                "From: \"Jane Doe\" <jane@example.com>, \"John Doe\" <john@example.com>\r\n",
+                "Sender: \"Jane Doe\" <jane@example.com>\r\n",
+                "To: <team@example.com>\r\n",
+            )),
+            "{}",
+            output
+        );
+
+        // An explicit Sender is kept.
+        let mut message = MessageBuilder::new();
+        message.from_multiple(vec!["jane@example.com", "john@example.com"]);
+        message.sender("secretary@example.com");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Sender: <secretary@example.com>\r\n"));
+        assert_eq!(output.matches("Sender:").count(), 1);
+
+        // A single mailbox does not need a Sender.
+        let mut message = MessageBuilder::new();
+        message.from_multiple(vec!["jane@example.com"]);
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        assert!(!String::from_utf8(output).unwrap().contains("Sender:"));
+    }
+
+    #[test]
+    fn build_uppercase_charset_labels() {
+        for uppercase in [false, true].iter() {
+            let mut message = MessageBuilder::new();
+            message.from(("Jöhn Dœ", "john@example.com"));
+            message.subject("Héllo wörld");
+            message.body(MimePart::new_multipart(
+                "multipart/mixed",
+                vec![
+                    MimePart::new_text("Hello"),
+                    MimePart::new_binary("application/pdf", &b"%PDF"[..])
+                        .header("Content-Description", Text::new("Rapport annuel ✓")),
+                ],
+            ));
+            if *uppercase {
+                message.uppercase_charset_labels();
+            }
+            let mut output = Vec::new();
+            message.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+
+            let (expected, unexpected) = if *uppercase {
+                ("=?UTF-8?", "=?utf-8?")
+            } else {
+                ("=?utf-8?", "=?UTF-8?")
+            };
+            assert!(
+                output.contains(&format!("From: {}", expected)),
+                "{}",
+                output
+            );
+            assert!(
+                output.contains(&format!("Subject: {}", expected)),
+                "{}",
+                output
+            );
+            assert!(
+                output.contains(&format!("Content-Description: {}", expected)),
+                "{}",
+                output
+            );
+            assert!(!output.contains(unexpected), "{}", output);
+
+            // Encoded-words decode the same way with either casing.
+            let parsed = Message::parse(output.as_bytes()).unwrap();
+            assert_eq!(parsed.get_subject().unwrap(), "Héllo wörld");
+        }
+
+        // Literal text is not changed, custom headers choose their casing.
+        struct Custom(Text<'static>);
+
+        impl Header for Custom {
+            fn write_header(
+                &self,
+                output: &mut dyn std::io::Write,
+                bytes_written: usize,
+            ) -> std::io::Result<usize> {
+                self.0.write_header(output, bytes_written)
+            }
+
+            fn write_header_with(
+                &self,
+                output: &mut dyn std::io::Write,
+                bytes_written: usize,
+                options: crate::headers::HeaderOptions,
+            ) -> std::io::Result<usize> {
+                self.0.write_header_with(output, bytes_written, options)
+            }
+        }
+
+        let mut message = MessageBuilder::new();
+        message.uppercase_charset_labels();
+        message.header("X-Raw", Raw::new("=?utf-8?q?raw?="));
+        message.header("X-Url", URL::new("https://example.com/=?utf-8?"));
+        message.header(
+            "X-Custom",
+            HeaderType::Custom(Box::new(Custom(Text::new("Ünïcode")))),
+        );
+        message.text_body("Hello");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("X-Raw: =?utf-8?q?raw?=\r\n"), "{}", output);
+        assert!(
+            output.contains("X-Url: <https://example.com/=?utf-8?>\r\n"),
+            "{}",
+            output
+        );
+        assert!(output.contains("X-Custom: =?UTF-8?"), "{}", output);
+    }
+
+    #[test]
+    fn build_flowed_signature_and_quotes() {
+        let quote = format!("> {}", "quoted words ".repeat(10).trim_end());
+        let mut message = MessageBuilder::new();
+        message.format_flowed();
+        message.text_body(format!("Hello   \n{}\n-- \nJohn\n", quote));
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let body = &output[output.find("\r\n\r\n").unwrap() + 4..];
+
+        assert_eq!(body, format!("Hello\r\n {}\r\n-- \r\nJohn\r\n", quote));
+    }
+
+    #[test]
+    fn build_flowed_delsp_wrapping() {
+        let mut message = MessageBuilder::new();
+        message.format_flowed_delsp();
+        message.text_body(format!(
+            "{}\n-- \nJohn\n",
+            "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod \
+             tempor incididunt ut labore."
+        ));
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let body = &output[output.find("\r\n\r\n").unwrap() + 4..];
+
+        assert_eq!(
+            body,
+            concat!(
+                "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod  \r\n",
+                "tempor incididunt ut labore.\r\n",
+                "-- \r\n",
+                "John\r\n",
+            )
+        );
+    }
+
+    #[test]
+    fn build_mime_version() {
+        let mut message = MessageBuilder::new();
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("MIME-Version: 1.0\r\n").count(), 1);
+
+        // A MIME-Version set by the user is not repeated.
+        let mut message = MessageBuilder::new();
+        message.header("Mime-Version", Raw::new("1.0 (Produced by Mailer)"));
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Mime-Version: 1.0 (Produced by Mailer)\r\n"));
+        assert!(!output.contains("MIME-Version"));
+
+        let mut message = MessageBuilder::new();
+        message.raw_headers(b"Received: from localhost\r\nmime-version: 1.0\r\n".as_ref());
+        message.text_body("Hello, world!");
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("MIME-Version"));
+    }
 }
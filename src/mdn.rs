@@ -0,0 +1,232 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{borrow::Cow, convert::TryFrom, fmt::Write, io};
+
+use crate::{
+    encoders::encode::check_header_line,
+    headers::content_type::ContentType,
+    mime::{BodyPart, MimePart},
+};
+
+/// RFC8098 disposition type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispositionType {
+    Displayed,
+    Deleted,
+    Dispatched,
+    Processed,
+}
+
+impl DispositionType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DispositionType::Displayed => "displayed",
+            DispositionType::Deleted => "deleted",
+            DispositionType::Dispatched => "dispatched",
+            DispositionType::Processed => "processed",
+        }
+    }
+}
+
+/// RFC8098 Message Disposition Notification
+#[derive(Debug)]
+pub struct MDN<'x> {
+    pub text: Cow<'x, str>,
+    pub reporting_ua: Option<Cow<'x, str>>,
+    pub original_recipient: Option<Cow<'x, str>>,
+    pub final_recipient: Cow<'x, str>,
+    pub original_message_id: Option<Cow<'x, str>>,
+    pub manual_action: bool,
+    pub disposition: DispositionType,
+}
+
+impl<'x> MDN<'x> {
+    /// Create a new MDN for the specified final recipient, with a
+    /// human-readable description of the disposition.
+    pub fn new(
+        final_recipient: impl Into<Cow<'x, str>>,
+        disposition: DispositionType,
+        text: impl Into<Cow<'x, str>>,
+    ) -> Self {
+        Self {
+            text: text.into(),
+            reporting_ua: None,
+            original_recipient: None,
+            final_recipient: final_recipient.into(),
+            original_message_id: None,
+            manual_action: false,
+            disposition,
+        }
+    }
+
+    /// Set the Reporting-UA field.
+    pub fn reporting_ua(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.reporting_ua = Some(value.into());
+        self
+    }
+
+    /// Set the Original-Recipient field.
+    pub fn original_recipient(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.original_recipient = Some(value.into());
+        self
+    }
+
+    /// Set the Original-Message-ID field.
+    pub fn original_message_id(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.original_message_id = Some(value.into());
+        self
+    }
+
+    /// Mark the disposition as the result of an explicit user action,
+    /// rather than an automatic one.
+    pub fn manual_action(mut self) -> Self {
+        self.manual_action = true;
+        self
+    }
+
+    /// Returns the contents of the message/disposition-notification part,
+    /// or an error if a field value contains a NUL, CR or LF character.
+    pub fn fields(&self) -> io::Result<String> {
+        for value in self
+            .reporting_ua
+            .iter()
+            .chain(self.original_recipient.iter())
+            .chain(self.original_message_id.iter())
+            .chain([&self.final_recipient])
+        {
+            check_header_line(value)?;
+        }
+        let mut fields = String::new();
+        if let Some(reporting_ua) = &self.reporting_ua {
+            let _ = write!(fields, "Reporting-UA: {}\r\n", reporting_ua);
+        }
+        if let Some(original_recipient) = &self.original_recipient {
+            let _ = write!(
+                fields,
+                "Original-Recipient: rfc822;{}\r\n",
+                original_recipient
+            );
+        }
+        let _ = write!(
+            fields,
+            "Final-Recipient: rfc822;{}\r\n",
+            self.final_recipient
+        );
+        if let Some(original_message_id) = &self.original_message_id {
+            let _ = write!(fields, "Original-Message-ID: <{}>\r\n", original_message_id);
+        }
+        let _ = write!(
+            fields,
+            "Disposition: {}; {}\r\n",
+            if self.manual_action {
+                "manual-action/MDN-sent-manually"
+            } else {
+                "automatic-action/MDN-sent-automatically"
+            },
+            self.disposition.as_str()
+        );
+        Ok(fields)
+    }
+
+    /// Build the multipart/report MIME part containing the notification.
+    pub fn into_part(self) -> io::Result<MimePart<'x>> {
+        let fields = self.fields()?;
+        Ok(MimePart::new(
+            ContentType::new("multipart/report")
+                .attribute("report-type", "disposition-notification"),
+            BodyPart::Multipart(vec![
+                MimePart::new_text(self.text),
+                MimePart::new(
+                    ContentType::new("message/disposition-notification"),
+                    BodyPart::Text(fields.into()),
+                ),
+            ]),
+        ))
+    }
+}
+
+impl<'x> TryFrom<MDN<'x>> for MimePart<'x> {
+    type Error = io::Error;
+
+    fn try_from(value: MDN<'x>) -> io::Result<Self> {
+        value.into_part()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mail_parser::{Message, MimeHeaders};
+
+    use crate::MessageBuilder;
+
+    use super::{DispositionType, MDN};
+
+    #[test]
+    fn build_mdn() {
+        let mut message = MessageBuilder::new();
+        message.from(("Jane Doe", "jane@example.com"));
+        message.to(("John Doe", "john@example.com"));
+        message.subject("Read: Hello, world!");
+        message.body(
+            MDN::new(
+                "jane@example.com",
+                DispositionType::Displayed,
+                "Your message was displayed.",
+            )
+            .reporting_ua("mail.example.com; mail-builder")
+            .original_recipient("jane@example.com")
+            .original_message_id("original-id@example.com")
+            .manual_action()
+            .into_part()
+            .unwrap(),
+        );
+
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        for field in [
+            "Reporting-UA: mail.example.com; mail-builder\r\n",
+            "Original-Recipient: rfc822;jane@example.com\r\n",
+            "Final-Recipient: rfc822;jane@example.com\r\n",
+            "Original-Message-ID: <original-id@example.com>\r\n",
+            "Disposition: manual-action/MDN-sent-manually; displayed\r\n",
+        ] {
+            assert!(output.contains(field), "{}", field);
+        }
+
+        let message = Message::parse(output.as_bytes()).unwrap();
+        let content_type = message.get_content_type().unwrap();
+        assert_eq!(content_type.get_subtype(), Some("report"));
+        assert_eq!(
+            content_type.get_attribute("report-type"),
+            Some("disposition-notification")
+        );
+        assert_eq!(
+            message.get_text_body(0).unwrap().trim_end(),
+            "Your message was displayed."
+        );
+
+        // Field values cannot add fields.
+        let err = MDN::new(
+            "jane@example.com",
+            DispositionType::Displayed,
+            "Your message was displayed.",
+        )
+        .original_message_id("id@example.com>\r\nDisposition: x; deleted")
+        .into_part()
+        .unwrap_err();
+        assert_eq!(
+            crate::Error::from_io(&err),
+            Some(&crate::Error::LineBreakInHeader)
+        );
+    }
+}
@@ -11,10 +11,17 @@
 
 use std::{
     borrow::Cow,
+    cell::Cell,
     collections::{btree_map::Entry, hash_map::DefaultHasher, BTreeMap},
+    fmt,
     hash::{Hash, Hasher},
     io::{self, Write},
     iter::FromIterator,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     thread,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
@@ -22,24 +29,81 @@ use std::{
 use crate::{
     encoders::{
         base64::base64_encode,
+        body::BodyEncoder,
         encode::{get_encoding_type, EncodingType},
-        quoted_printable::quoted_printable_encode,
+        quoted_printable::quoted_printable_encode_with,
+        utf7::utf7_encode,
     },
     headers::{
-        content_type::ContentType, message_id::MessageId, raw::Raw, text::Text, Header, HeaderType,
+        content_location::ContentLocation,
+        content_type::{is_token, ContentType},
+        message_id::MessageId,
+        text::Text,
+        write_header_limited, Header, HeaderOptions, HeaderType,
     },
+    Error,
 };
 
 /// MIME part of an e-mail.
+#[derive(Debug)]
 pub struct MimePart<'x> {
     pub headers: BTreeMap<Cow<'x, str>, HeaderType<'x>>,
     pub contents: BodyPart<'x>,
+    pub prefer_quoted_printable: bool,
+    pub content_length: bool,
+    pub encoder: Option<Arc<dyn BodyEncoder>>,
 }
 
 pub enum BodyPart<'x> {
     Text(Cow<'x, str>),
     Binary(Cow<'x, [u8]>),
+    #[cfg(feature = "bytes")]
+    Bytes(bytes::Bytes),
+    /// A file that is only read when the message is written.
+    File(PathBuf),
+    /// Headers and body written exactly as provided, see `MimePart::raw_part`.
+    Verbatim {
+        headers: Vec<(Cow<'x, str>, Cow<'x, str>)>,
+        body: Cow<'x, [u8]>,
+    },
     Multipart(Vec<MimePart<'x>>),
+    /// Contents read from an async reader, see `MimePart::new_async`.
+    #[cfg(feature = "tokio")]
+    Async(AsyncSource),
+}
+
+/// Reader of a part that is streamed by `MessageBuilder::write_to_async`.
+#[cfg(feature = "tokio")]
+pub struct AsyncSource {
+    reader: Option<Box<dyn tokio::io::AsyncRead + Send + Unpin>>,
+    marker: String,
+}
+
+#[cfg(feature = "tokio")]
+impl fmt::Debug for AsyncSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AsyncSource")
+    }
+}
+
+impl<'x> fmt::Debug for BodyPart<'x> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyPart::Text(text) => f.debug_tuple("Text").field(text).finish(),
+            BodyPart::Binary(binary) => write!(f, "Binary({} bytes)", binary.len()),
+            #[cfg(feature = "bytes")]
+            BodyPart::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            BodyPart::File(path) => f.debug_tuple("File").field(path).finish(),
+            BodyPart::Verbatim { headers, body } => f
+                .debug_struct("Verbatim")
+                .field("headers", headers)
+                .field("body", &format_args!("{} bytes", body.len()))
+                .finish(),
+            BodyPart::Multipart(parts) => f.debug_tuple("Multipart").field(parts).finish(),
+            #[cfg(feature = "tokio")]
+            BodyPart::Async(source) => source.fmt(f),
+        }
+    }
 }
 
 impl<'x> From<&'x str> for BodyPart<'x> {
@@ -66,9 +130,367 @@ impl<'x> From<Vec<u8>> for BodyPart<'x> {
     }
 }
 
+#[cfg(feature = "bytes")]
+impl<'x> From<bytes::Bytes> for BodyPart<'x> {
+    fn from(value: bytes::Bytes) -> Self {
+        BodyPart::Bytes(value)
+    }
+}
+
+impl<'x> BodyPart<'x> {
+    /// Convert the body part into an owned, `'static` version
+    pub fn into_owned(self) -> BodyPart<'static> {
+        match self {
+            BodyPart::Text(text) => BodyPart::Text(text.into_owned().into()),
+            BodyPart::Binary(binary) => BodyPart::Binary(binary.into_owned().into()),
+            #[cfg(feature = "bytes")]
+            BodyPart::Bytes(bytes) => BodyPart::Bytes(bytes),
+            BodyPart::File(path) => BodyPart::File(path),
+            BodyPart::Verbatim { headers, body } => BodyPart::Verbatim {
+                headers: headers
+                    .into_iter()
+                    .map(|(name, value)| (name.into_owned().into(), value.into_owned().into()))
+                    .collect(),
+                body: body.into_owned().into(),
+            },
+            BodyPart::Multipart(parts) => {
+                BodyPart::Multipart(parts.into_iter().map(|p| p.into_owned()).collect())
+            }
+            #[cfg(feature = "tokio")]
+            BodyPart::Async(source) => BodyPart::Async(source),
+        }
+    }
+
+    /// Returns a copy of the body part borrowing its contents. The readers of
+    /// async parts are not copied, and their copies are written empty.
+    pub(crate) fn borrowed(&self, uppercase_charset: bool) -> io::Result<BodyPart<'_>> {
+        Ok(match self {
+            BodyPart::Text(text) => BodyPart::Text(text.as_ref().into()),
+            BodyPart::Binary(binary) => BodyPart::Binary(binary.as_ref().into()),
+            #[cfg(feature = "bytes")]
+            BodyPart::Bytes(bytes) => BodyPart::Bytes(bytes.clone()),
+            BodyPart::File(path) => BodyPart::File(path.clone()),
+            BodyPart::Verbatim { headers, body } => BodyPart::Verbatim {
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| (name.as_ref().into(), value.as_ref().into()))
+                    .collect(),
+                body: body.as_ref().into(),
+            },
+            BodyPart::Multipart(parts) => BodyPart::Multipart(
+                parts
+                    .iter()
+                    .map(|part| part.borrowed(uppercase_charset))
+                    .collect::<io::Result<_>>()?,
+            ),
+            #[cfg(feature = "tokio")]
+            BodyPart::Async(_) => BodyPart::Async(AsyncSource {
+                reader: None,
+                marker: "\r\n".into(),
+            }),
+        })
+    }
+}
+
+/// Node of the MIME structure of a message, see `MessageBuilder::mime_tree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimeNode {
+    /// Content-Type of the part, without parameters.
+    pub content_type: String,
+    /// Content-Transfer-Encoding the part is written with, `None` for
+    /// multipart containers.
+    pub encoding: Option<String>,
+    /// Body parts of a multipart container.
+    pub children: Vec<MimeNode>,
+}
+
+/// Options that control how MIME parts are written.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct WriteOptions {
+    /// Write unencoded bodies verbatim, without converting bare LFs to CRLF.
+    pub assume_crlf: bool,
+    /// Protect text body lines starting with `From ` using quoted-printable.
+    pub escape_from: bool,
+    /// Write a Content-Length header for the part being written.
+    pub content_length: bool,
+    /// Fold long content lines of text/vcard and text/calendar parts.
+    pub fold_content_lines: bool,
+    /// Write the charset label of encoded-words in uppercase.
+    pub uppercase_charset_labels: bool,
+}
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Number of the next boundary while boundaries are fixed, see
+    /// `with_fixed_boundaries`.
+    static FIXED_BOUNDARY: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Runs `f` with boundaries generated on the current thread replaced by
+/// numbered ones, so that serializing a message is deterministic.
+pub(crate) fn with_fixed_boundaries<T>(f: impl FnOnce() -> T) -> T {
+    struct Reset(Option<u64>);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            FIXED_BOUNDARY.with(|next| next.set(self.0));
+        }
+    }
+
+    let _reset = Reset(FIXED_BOUNDARY.with(|next| next.replace(Some(0))));
+    f()
+}
+
+/// Generate a unique MIME boundary. A process-wide atomic counter is
+/// combined with the current time and thread id, so boundaries generated
+/// concurrently from multiple threads never collide.
 pub fn make_boundary() -> String {
-    // TODO
-    String::new()
+    if let Some(num) = FIXED_BOUNDARY.with(|next| {
+        let num = next.get();
+        next.set(num.map(|num| num + 1));
+        num
+    }) {
+        return format!("boundary_{}", num);
+    }
+    let mut s = DefaultHasher::new();
+    thread::current().id().hash(&mut s);
+    std::process::id().hash(&mut s);
+
+    format!(
+        "{:x}_{:x}_{:x}",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::new(0, 0))
+            .as_nanos(),
+        s.finish(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+    )
+}
+
+/// Generate a unique MIME boundary starting with `prefix`, which is
+/// shortened if needed to keep the boundary within 70 characters.
+pub fn make_prefixed_boundary(prefix: &str) -> String {
+    let boundary = make_boundary();
+    let prefix_len = prefix.len().min(70usize.saturating_sub(boundary.len() + 1));
+    format!("{}_{}", &prefix[..prefix_len], boundary)
+}
+
+/// Guess the content type of a file from its extension, defaulting to
+/// `application/octet-stream`.
+pub fn content_type_from_path(path: impl AsRef<Path>) -> &'static str {
+    let extension = path
+        .as_ref()
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "txt" | "text" | "log" => "text/plain",
+        "htm" | "html" => "text/html",
+        "css" => "text/css",
+        "csv" => "text/csv",
+        "ics" => "text/calendar",
+        "vcf" => "text/vcard",
+        "md" => "text/markdown",
+        "xml" => "application/xml",
+        "json" => "application/json",
+        "js" | "mjs" => "application/javascript",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+        "eml" => "message/rfc822",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "webp" => "image/webp",
+        "svg" | "svgz" => "image/svg+xml",
+        "avif" => "image/avif",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        "jxl" => "image/jxl",
+        "apng" => "image/apng",
+        "ico" => "image/vnd.microsoft.icon",
+        "tif" | "tiff" => "image/tiff",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "opus" => "audio/opus",
+        "m4a" => "audio/mp4",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Returns true for multipart/mixed, multipart/alternative and
+/// multipart/related parts without any headers other than Content-Type.
+fn is_redundant_multipart(headers: &BTreeMap<Cow<str>, HeaderType>) -> bool {
+    headers.len() == 1
+        && headers
+            .get("Content-Type")
+            .and_then(|v| v.as_content_type())
+            .is_some_and(|v| {
+                [
+                    "multipart/mixed",
+                    "multipart/alternative",
+                    "multipart/related",
+                ]
+                .iter()
+                .any(|c_type| v.c_type.eq_ignore_ascii_case(c_type))
+            })
+}
+
+/// Line width used when wrapping format=flowed text.
+const FLOWED_WIDTH: usize = 72;
+
+/// Signature separator line, which keeps its trailing space in flowed text.
+const SIGNATURE_SEPARATOR: &str = "-- ";
+
+/// Add a space to each soft line break of a text wrapped by `format_flowed`,
+/// which is removed by receivers when unwrapping delsp=yes text.
+fn delsp_soft_breaks(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 16);
+    let mut lines = text.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        result.push_str(line);
+        if lines.peek().is_some() {
+            if line.ends_with(' ') && line != SIGNATURE_SEPARATOR {
+                result.push(' ');
+            }
+            result.push('\n');
+        }
+    }
+    result
+}
+
+/// Wrap a text using the format=flowed encoding (RFC3676). Lines longer than
+/// `FLOWED_WIDTH` are soft-wrapped at spaces, leaving a trailing space at the end
+/// of each soft line break, trailing spaces are removed from hard line breaks
+/// and lines starting with a space, `>` or `From ` are space-stuffed. Lines
+/// starting with `>` are not wrapped, so that quotes stay intact in clients
+/// that do not support format=flowed, and the `-- ` signature separator is
+/// kept as is.
+fn format_flowed(text: Cow<'_, str>) -> Cow<'_, str> {
+    let needs_stuffing =
+        |line: &str| line.starts_with(' ') || line.starts_with('>') || line.starts_with("From ");
+    if !text.split('\n').any(|line| {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        needs_stuffing(line) || line.ends_with(' ') || line.chars().count() > FLOWED_WIDTH
+    }) {
+        return text;
+    }
+
+    let mut flowed = String::with_capacity(text.len() + 16);
+    let mut push_line = |line: &str| {
+        if needs_stuffing(line) {
+            flowed.push(' ');
+        }
+        flowed.push_str(line);
+    };
+
+    for (pos, line) in text.split('\n').enumerate() {
+        if pos > 0 {
+            push_line("\n");
+        }
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let line = if line != SIGNATURE_SEPARATOR {
+            line.trim_end_matches(' ')
+        } else {
+            line
+        };
+        if line.chars().count() <= FLOWED_WIDTH || line.starts_with('>') {
+            push_line(line);
+            continue;
+        }
+
+        let mut start = 0;
+        let mut end = 0;
+        let mut width = 0;
+        for word in line.split_inclusive(' ') {
+            let word_width = word.trim_end_matches(' ').chars().count();
+            if end > start && width + word_width > FLOWED_WIDTH {
+                push_line(&line[start..end]);
+                push_line("\n");
+                start = end;
+                width = 0;
+            }
+            end += word.len();
+            width += word.chars().count();
+        }
+        push_line(&line[start..]);
+    }
+    flowed.into()
+}
+
+/// Maximum length of a vCard or iCalendar content line in octets, excluding
+/// the CRLF (RFC6350 section 3.2, RFC5545 section 3.1).
+const CONTENT_LINE_LENGTH: usize = 75;
+
+/// Fold the lines of a vCard or iCalendar object longer than `CONTENT_LINE_LENGTH` octets, continuing
+/// them on the next line after a single space, and use CRLF line endings.
+/// Multi-byte UTF-8 characters are never split.
+fn fold_content_lines(vcard: &str) -> String {
+    let mut folded = String::with_capacity(vcard.len() + vcard.len() / CONTENT_LINE_LENGTH * 3 + 2);
+    for line in vcard.lines() {
+        let mut line_len = 0;
+        for ch in line.chars() {
+            if line_len + ch.len_utf8() > CONTENT_LINE_LENGTH {
+                folded.push_str("\r\n ");
+                line_len = 1;
+            }
+            folded.push(ch);
+            line_len += ch.len_utf8();
+        }
+        folded.push_str("\r\n");
+    }
+    folded
+}
+
+/// Fold the content lines of a text/vcard or text/calendar part, see `fold_content_lines`.
+/// Binary contents that are not valid UTF-8 are left unchanged.
+fn fold_part_contents(contents: BodyPart<'_>) -> io::Result<BodyPart<'_>> {
+    let binary = match contents {
+        BodyPart::Text(text) => return Ok(BodyPart::Text(fold_content_lines(&text).into())),
+        BodyPart::Binary(binary) => binary.into_owned(),
+        #[cfg(feature = "bytes")]
+        BodyPart::Bytes(bytes) => bytes.to_vec(),
+        BodyPart::File(path) => std::fs::read(path)?,
+        contents => return Ok(contents),
+    };
+    Ok(BodyPart::Binary(match String::from_utf8(binary) {
+        Ok(text) => fold_content_lines(&text).into_bytes().into(),
+        Err(err) => err.into_bytes().into(),
+    }))
+}
+
+/// Content type for binary parts, falling back to `application/octet-stream`
+/// when no content type is provided.
+fn binary_content_type<'x>(c_type: impl Into<Cow<'x, str>>) -> ContentType<'x> {
+    let c_type = c_type.into();
+    if !c_type.trim().is_empty() {
+        ContentType::new(c_type)
+    } else {
+        ContentType::new("application/octet-stream")
+    }
 }
 
 impl<'x> MimePart<'x> {
@@ -77,10 +499,15 @@ impl<'x> MimePart<'x> {
         Self {
             contents,
             headers: BTreeMap::from_iter(vec![("Content-Type".into(), content_type.into())]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
         }
     }
 
-    /// Create a new multipart/* MIME part.
+    /// Create a new multipart/* MIME part. Multipart parts must contain at
+    /// least one body part, otherwise an `InvalidInput` error is returned
+    /// when the part is written.
     pub fn new_multipart(
         content_type: impl Into<Cow<'x, str>>,
         contents: Vec<MimePart<'x>>,
@@ -91,6 +518,9 @@ impl<'x> MimePart<'x> {
                 "Content-Type".into(),
                 ContentType::new(content_type).into(),
             )]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
         }
     }
     pub fn new_text(contents: impl Into<Cow<'x, str>>) -> Self {
@@ -102,13 +532,18 @@ impl<'x> MimePart<'x> {
                     .attribute("charset", "utf-8")
                     .into(),
             )]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
         }
     }
 
-    /// Create a new text/plain MIME part with format=flowed
+    /// Create a new text/plain MIME part with format=flowed. Long lines are
+    /// soft-wrapped at 72 columns and lines starting with a space, `>` or
+    /// `From ` are space-stuffed as required by RFC3676.
     pub fn new_text_flowed(contents: impl Into<Cow<'x, str>>) -> Self {
         Self {
-            contents: BodyPart::Text(contents.into()),
+            contents: BodyPart::Text(format_flowed(contents.into())),
             headers: BTreeMap::from_iter(vec![(
                 "Content-Type".into(),
                 ContentType::new("text/plain")
@@ -116,9 +551,47 @@ impl<'x> MimePart<'x> {
                     .attribute("format", "flowed")
                     .into(),
             )]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
         }
     }
 
+    /// Add the `delsp=yes` parameter to a format=flowed MIME part, indicating
+    /// that the space before each soft line break should be deleted when the
+    /// text is unwrapped. An extra space is added to each soft line break of
+    /// the text, so that words are still separated once it is unwrapped.
+    pub fn delsp(mut self) -> Self {
+        if let Some(HeaderType::ContentType(content_type)) = self.headers.get_mut("Content-Type") {
+            let is_flowed = content_type
+                .attributes
+                .get("format")
+                .is_some_and(|format| format.eq_ignore_ascii_case("flowed"));
+            let has_delsp = content_type
+                .attributes
+                .insert("delsp".into(), "yes".into())
+                .is_some_and(|delsp| delsp.eq_ignore_ascii_case("yes"));
+            if let (BodyPart::Text(text), true, false) = (&mut self.contents, is_flowed, has_delsp)
+            {
+                *text = delsp_soft_breaks(text).into();
+            }
+        }
+        self
+    }
+
+    /// Add a parameter to the Content-Type header of the part, replacing any
+    /// existing parameter with the same name.
+    pub fn attribute(
+        mut self,
+        key: impl Into<Cow<'x, str>>,
+        value: impl Into<Cow<'x, str>>,
+    ) -> Self {
+        if let Some(HeaderType::ContentType(content_type)) = self.headers.get_mut("Content-Type") {
+            content_type.attributes.insert(key.into(), value.into());
+        }
+        self
+    }
+
     /// Create a new text/* MIME part.
     pub fn new_text_other(
         content_type: impl Into<Cow<'x, str>>,
@@ -132,6 +605,31 @@ impl<'x> MimePart<'x> {
                     .attribute("charset", "utf-8")
                     .into(),
             )]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
+        }
+    }
+
+    /// Create a new text MIME part with the given charset parameter, or
+    /// without one when `charset` is `None`. The contents are written as
+    /// they are, so they should only use characters that are encoded the
+    /// same way in UTF-8 and in the chosen charset.
+    pub fn new_text_with_charset(
+        content_type: impl Into<Cow<'x, str>>,
+        charset: Option<impl Into<Cow<'x, str>>>,
+        contents: impl Into<Cow<'x, str>>,
+    ) -> Self {
+        let mut content_type = ContentType::new(content_type);
+        if let Some(charset) = charset {
+            content_type = content_type.attribute("charset", charset);
+        }
+        Self {
+            contents: BodyPart::Text(contents.into()),
+            headers: BTreeMap::from_iter(vec![("Content-Type".into(), content_type.into())]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
         }
     }
 
@@ -145,6 +643,35 @@ impl<'x> MimePart<'x> {
                     .attribute("charset", "utf-8")
                     .into(),
             )]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
+        }
+    }
+
+    /// Create a new text/vcard MIME part containing one or more contact
+    /// cards. Lines longer than 75 octets are folded as required by RFC6350.
+    pub fn new_vcard(vcard: impl AsRef<str>) -> Self {
+        MimePart::new_text_other("text/vcard", fold_content_lines(vcard.as_ref()))
+    }
+
+    /// Create a new text/calendar MIME part from an iCalendar object. The
+    /// `METHOD` property, if present, is copied to the `method` parameter
+    /// of the Content-Type and long lines are folded as required by RFC5545.
+    pub fn new_ics(ics: impl AsRef<str>) -> Self {
+        let ics = ics.as_ref();
+        let method = ics.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.trim().eq_ignore_ascii_case("METHOD") {
+                Some(value.trim().to_ascii_uppercase())
+            } else {
+                None
+            }
+        });
+        let part = MimePart::new_text_other("text/calendar", fold_content_lines(ics));
+        match method {
+            Some(method) => part.attribute("method", method),
+            None => part,
         }
     }
 
@@ -154,11 +681,195 @@ impl<'x> MimePart<'x> {
             contents: BodyPart::Binary(contents.into()),
             headers: BTreeMap::from_iter(vec![(
                 "Content-Type".into(),
-                ContentType::new(c_type).into(),
+                binary_content_type(c_type).into(),
             )]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
         }
     }
 
+    /// Create a binary MIME part whose contents are read from an async
+    /// reader and base64 encoded as they are written by
+    /// `MessageBuilder::write_to_async`. Messages containing such parts
+    /// cannot be written synchronously.
+    #[cfg(feature = "tokio")]
+    pub fn new_async(
+        c_type: impl Into<Cow<'x, str>>,
+        reader: impl tokio::io::AsyncRead + Send + Unpin + 'static,
+    ) -> Self {
+        Self {
+            contents: BodyPart::Async(AsyncSource {
+                reader: Some(Box::new(reader)),
+                marker: String::new(),
+            }),
+            headers: BTreeMap::from_iter(vec![(
+                "Content-Type".into(),
+                binary_content_type(c_type).into(),
+            )]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
+        }
+    }
+
+    /// Take the readers of the async parts, which are then written as a
+    /// unique marker starting with `token`.
+    #[cfg(feature = "tokio")]
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn take_async_readers(
+        &mut self,
+        token: &str,
+        readers: &mut Vec<(String, Box<dyn tokio::io::AsyncRead + Send + Unpin>)>,
+    ) {
+        match &mut self.contents {
+            BodyPart::Async(source) => {
+                if let Some(reader) = source.reader.take() {
+                    source.marker = format!("{}-{}\r\n", token, readers.len());
+                    readers.push((source.marker.clone(), reader));
+                }
+            }
+            BodyPart::Multipart(parts) => {
+                for part in parts {
+                    part.take_async_readers(token, readers);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Create a MIME part from pre-generated headers and an already encoded
+    /// body, which are written exactly as provided. Header values are not
+    /// folded or encoded and no Content-Transfer-Encoding is added, making
+    /// this suitable for S/MIME signatures and other opaque content.
+    /// Headers set on the part afterwards are not written.
+    pub fn raw_part<N, V>(headers: Vec<(N, V)>, body: impl Into<Cow<'x, [u8]>>) -> Self
+    where
+        N: Into<Cow<'x, str>>,
+        V: Into<Cow<'x, str>>,
+    {
+        Self {
+            contents: BodyPart::Verbatim {
+                headers: headers
+                    .into_iter()
+                    .map(|(name, value)| (name.into(), value.into()))
+                    .collect(),
+                body: body.into(),
+            },
+            headers: BTreeMap::new(),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
+        }
+    }
+
+    /// Create a new binary MIME part whose contents are read from a file
+    /// when the message is written, rather than being held in memory.
+    /// An error is returned at write time if the file cannot be read.
+    pub fn new_file(c_type: impl Into<Cow<'x, str>>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            contents: BodyPart::File(path.into()),
+            headers: BTreeMap::from_iter(vec![(
+                "Content-Type".into(),
+                binary_content_type(c_type).into(),
+            )]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
+        }
+    }
+
+    /// Create a new binary MIME part from a reference-counted `bytes::Bytes`
+    /// buffer, avoiding a copy of its contents.
+    #[cfg(feature = "bytes")]
+    pub fn new_bytes(c_type: impl Into<Cow<'x, str>>, contents: impl Into<bytes::Bytes>) -> Self {
+        Self {
+            contents: BodyPart::Bytes(contents.into()),
+            headers: BTreeMap::from_iter(vec![(
+                "Content-Type".into(),
+                binary_content_type(c_type).into(),
+            )]),
+            prefer_quoted_printable: false,
+            content_length: false,
+            encoder: None,
+        }
+    }
+
+    /// Create a new MIME part from the contents of a file. The content type
+    /// is guessed from the file extension and the file name is used as the
+    /// attachment or inline filename. Non UTF-8 file names are converted
+    /// lossily.
+    pub fn from_path(path: impl AsRef<Path>, inline: bool) -> io::Result<Self> {
+        let path = path.as_ref();
+        let part = MimePart::new_binary(content_type_from_path(path), std::fs::read(path)?);
+
+        Ok(
+            match path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+            {
+                Some(filename) if inline => part.inline_with_filename(filename),
+                Some(filename) => part.attachment(filename),
+                None if inline => part.inline(),
+                None => part,
+            },
+        )
+    }
+
+    /// Encode a binary MIME part as quoted-printable rather than base64 when it
+    /// would be smaller, such as mostly ASCII files with a non-text content type.
+    pub fn prefer_quoted_printable(mut self) -> Self {
+        self.prefer_quoted_printable = true;
+        self
+    }
+
+    /// Add a `Content-Length` header with the size in bytes of the decoded
+    /// contents. It is ignored on multipart parts.
+    pub fn content_length(mut self) -> Self {
+        self.content_length = true;
+        self
+    }
+
+    /// Write the contents using a custom Content-Transfer-Encoding, replacing
+    /// any Content-Transfer-Encoding header of the part. It is ignored on
+    /// multipart parts.
+    pub fn encoder(mut self, encoder: impl BodyEncoder + 'static) -> Self {
+        self.encoder = Some(Arc::new(encoder));
+        self
+    }
+
+    /// Convert a binary part with a textual Content-Type, such as one created
+    /// using `new_binary("text/plain", ...)`, into a text part when its
+    /// contents are valid UTF-8. A `charset=utf-8` parameter is added if
+    /// missing, so that the part can be encoded as 7bit or quoted-printable
+    /// like other text parts. Other parts are returned unchanged.
+    pub fn text_if_utf8(mut self) -> Self {
+        let content_type = match self.headers.get_mut("Content-Type") {
+            Some(HeaderType::ContentType(content_type)) if content_type.is_text() => content_type,
+            _ => return self,
+        };
+        let text = match self.contents {
+            BodyPart::Binary(Cow::Borrowed(binary)) => match std::str::from_utf8(binary) {
+                Ok(text) => Cow::Borrowed(text),
+                Err(_) => return self,
+            },
+            BodyPart::Binary(Cow::Owned(binary)) => match String::from_utf8(binary) {
+                Ok(text) => Cow::Owned(text),
+                Err(err) => {
+                    self.contents = BodyPart::Binary(err.into_bytes().into());
+                    return self;
+                }
+            },
+            _ => return self,
+        };
+        content_type
+            .attributes
+            .entry("charset".into())
+            .or_insert_with(|| "utf-8".into());
+        self.contents = BodyPart::Text(text);
+        self
+    }
+
     /// Set the attachment filename of a MIME part.
     pub fn attachment(mut self, filename: impl Into<Cow<'x, str>>) -> Self {
         self.headers.insert(
@@ -170,6 +881,29 @@ impl<'x> MimePart<'x> {
         self
     }
 
+    /// Set the attachment filename of a MIME part and also add it as the
+    /// `name` parameter of the Content-Type header, which is what some
+    /// legacy clients look at.
+    pub fn attachment_with_name(mut self, filename: impl Into<Cow<'x, str>>) -> Self {
+        let filename = filename.into();
+        if let Some(HeaderType::ContentType(content_type)) = self.headers.get_mut("Content-Type") {
+            content_type
+                .attributes
+                .insert("name".into(), filename.clone());
+        }
+        self.attachment(filename)
+    }
+
+    /// Returns the filename of the MIME part, if any.
+    pub fn filename(&self) -> Option<&str> {
+        self.headers
+            .get("Content-Disposition")?
+            .as_content_type()?
+            .attributes
+            .get("filename")
+            .map(|filename| filename.as_ref())
+    }
+
     /// Set the MIME part as inline.
     pub fn inline(mut self) -> Self {
         self.headers.insert(
@@ -179,6 +913,17 @@ impl<'x> MimePart<'x> {
         self
     }
 
+    /// Set the MIME part as inline with a filename.
+    pub fn inline_with_filename(mut self, filename: impl Into<Cow<'x, str>>) -> Self {
+        self.headers.insert(
+            "Content-Disposition".into(),
+            ContentType::new("inline")
+                .attribute("filename", filename)
+                .into(),
+        );
+        self
+    }
+
     /// Set the Content-Language header of a MIME part.
     pub fn language(mut self, value: impl Into<Cow<'x, str>>) -> Self {
         self.headers
@@ -195,8 +940,10 @@ impl<'x> MimePart<'x> {
 
     /// Set the Content-Location header of a MIME part.
     pub fn location(mut self, value: impl Into<Cow<'x, str>>) -> Self {
-        self.headers
-            .insert("Content-Location".into(), Raw::new(value).into());
+        self.headers.insert(
+            "Content-Location".into(),
+            ContentLocation::new(value).into(),
+        );
         self
     }
 
@@ -217,8 +964,271 @@ impl<'x> MimePart<'x> {
         }
     }
 
+    /// Hoist the only child of multipart/mixed, multipart/alternative and
+    /// multipart/related parts up, removing the redundant wrapping.
+    /// Multipart parts with headers other than Content-Type are left as is.
+    pub fn simplify(self) -> Self {
+        let MimePart {
+            headers,
+            contents,
+            prefer_quoted_printable,
+            content_length,
+            encoder,
+        } = self;
+        match contents {
+            BodyPart::Multipart(parts) => {
+                let mut parts = parts
+                    .into_iter()
+                    .map(|part| part.simplify())
+                    .collect::<Vec<_>>();
+                if parts.len() == 1 && is_redundant_multipart(&headers) {
+                    parts.pop().unwrap()
+                } else {
+                    MimePart {
+                        headers,
+                        contents: BodyPart::Multipart(parts),
+                        prefer_quoted_printable,
+                        content_length,
+                        encoder,
+                    }
+                }
+            }
+            contents => MimePart {
+                headers,
+                contents,
+                prefer_quoted_printable,
+                content_length,
+                encoder,
+            },
+        }
+    }
+
+    /// Returns the part that `simplify` would hoist up in place of this one.
+    pub(crate) fn simplified(&self) -> &MimePart<'x> {
+        match &self.contents {
+            BodyPart::Multipart(parts)
+                if parts.len() == 1 && is_redundant_multipart(&self.headers) =>
+            {
+                parts[0].simplified()
+            }
+            _ => self,
+        }
+    }
+
+    /// Set the charset parameter of a text part, if it contains ASCII text
+    /// or the charset is UTF-8. Parts without a charset parameter or with
+    /// a charset other than UTF-8 are left unchanged.
+    pub(crate) fn set_charset(&mut self, charset: &str) {
+        let is_ascii = match &self.contents {
+            BodyPart::Text(text) => text.is_ascii(),
+            _ => return,
+        };
+        if charset.eq_ignore_ascii_case("utf-7") {
+            self.encode_utf7();
+            return;
+        }
+        if let Some(HeaderType::ContentType(content_type)) = self.headers.get_mut("Content-Type") {
+            if content_type.is_text()
+                && content_type
+                    .attributes
+                    .get("charset")
+                    .is_some_and(|current| current.eq_ignore_ascii_case("utf-8"))
+                && (is_ascii
+                    || charset.eq_ignore_ascii_case("utf-8")
+                    || charset.eq_ignore_ascii_case("utf8"))
+            {
+                content_type
+                    .attributes
+                    .insert("charset".into(), charset.to_string().into());
+            }
+        }
+    }
+
+    /// Returns the contents of a leaf part, reading them from disk if
+    /// needed, or `None` for multipart and verbatim parts.
+    fn leaf_contents(&self) -> io::Result<Option<Cow<'_, [u8]>>> {
+        Ok(Some(match &self.contents {
+            BodyPart::Text(text) => Cow::from(text.as_bytes()),
+            BodyPart::Binary(binary) => Cow::from(binary.as_ref()),
+            #[cfg(feature = "bytes")]
+            BodyPart::Bytes(bytes) => Cow::from(bytes.as_ref()),
+            BodyPart::File(path) => Cow::from(std::fs::read(path)?),
+            BodyPart::Multipart(_) | BodyPart::Verbatim { .. } => return Ok(None),
+            #[cfg(feature = "tokio")]
+            BodyPart::Async(_) => return Ok(None),
+        }))
+    }
+
+    /// Encode the text of a text/* MIME part as UTF-7 (RFC2152) and set its
+    /// charset to `utf-7`, for legacy systems that cannot handle 8-bit text.
+    /// Other parts are left unchanged.
+    pub fn utf7(mut self) -> Self {
+        self.encode_utf7();
+        self
+    }
+
+    fn encode_utf7(&mut self) {
+        if let (BodyPart::Text(text), Some(HeaderType::ContentType(content_type))) =
+            (&mut self.contents, self.headers.get_mut("Content-Type"))
+        {
+            if content_type.is_text() {
+                *text = utf7_encode(text, false).into();
+                content_type
+                    .attributes
+                    .insert("charset".into(), "utf-7".into());
+            }
+        }
+    }
+
+    /// Write the `charset` parameter of this part and its subparts as a
+    /// quoted-string, see `ContentType::quoted_charset`.
+    pub(crate) fn quote_charset(&mut self) {
+        if let Some(HeaderType::ContentType(content_type)) = self.headers.get_mut("Content-Type") {
+            content_type.quote_charset = true;
+        }
+        if let BodyPart::Multipart(parts) = &mut self.contents {
+            for part in parts {
+                part.quote_charset();
+            }
+        }
+    }
+
+    /// Assign boundaries starting with `prefix` to this part and its
+    /// multipart subparts, unless they already have one.
+    pub(crate) fn prefix_boundaries(&mut self, prefix: &str) {
+        if let BodyPart::Multipart(parts) = &mut self.contents {
+            if let HeaderType::ContentType(content_type) = self
+                .headers
+                .entry("Content-Type".into())
+                .or_insert_with(|| ContentType::new("multipart/mixed").into())
+            {
+                content_type
+                    .attributes
+                    .entry("boundary".into())
+                    .or_insert_with(|| make_prefixed_boundary(prefix).into());
+            }
+            for part in parts {
+                part.prefix_boundaries(prefix);
+            }
+        }
+    }
+
+    /// Returns true if the part is a multipart/mixed part.
+    pub(crate) fn is_mixed(&self) -> bool {
+        matches!(self.contents, BodyPart::Multipart(_))
+            && self
+                .content_type()
+                .is_some_and(|c_type| c_type.eq_ignore_ascii_case("multipart/mixed"))
+    }
+
+    /// Returns the media type of the part, without parameters.
+    pub fn content_type(&self) -> Option<&str> {
+        if let BodyPart::Verbatim { headers, .. } = &self.contents {
+            return headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+                .and_then(|(_, value)| value.split(';').next())
+                .map(|c_type| c_type.trim());
+        }
+        match self.headers.get("Content-Type")? {
+            HeaderType::ContentType(content_type) => Some(content_type.c_type.as_ref()),
+            HeaderType::Raw(raw) => raw.raw.split(';').next().map(|c_type| c_type.trim()),
+            HeaderType::Text(text) => text.text.split(';').next().map(|c_type| c_type.trim()),
+            _ => None,
+        }
+    }
+
+    /// Returns the MIME structure of the part, annotating each leaf with the
+    /// Content-Transfer-Encoding it is written with. Leaves are encoded to
+    /// determine it, so the result always matches the written output.
+    pub(crate) fn into_mime_node(self, options: WriteOptions) -> io::Result<MimeNode> {
+        let content_type = self
+            .content_type()
+            .unwrap_or(if let BodyPart::Multipart(_) = &self.contents {
+                "multipart/mixed"
+            } else {
+                "text/plain"
+            })
+            .to_string();
+
+        if let BodyPart::Multipart(parts) = self.contents {
+            return Ok(MimeNode {
+                content_type,
+                encoding: None,
+                children: parts
+                    .into_iter()
+                    .map(|part| part.into_mime_node(options))
+                    .collect::<io::Result<_>>()?,
+            });
+        }
+
+        let mut output = Vec::new();
+        self.write_part_with(&mut output, options)?;
+        let headers = output
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map_or(&output[..], |pos| &output[..pos]);
+        let encoding = String::from_utf8_lossy(headers).lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.eq_ignore_ascii_case("Content-Transfer-Encoding") {
+                Some(value.trim().to_string())
+            } else {
+                None
+            }
+        });
+        Ok(MimeNode {
+            content_type,
+            encoding,
+            children: Vec::new(),
+        })
+    }
+
+    /// Convert the MIME part into an owned, `'static` version
+    pub fn into_owned(self) -> MimePart<'static> {
+        MimePart {
+            headers: self
+                .headers
+                .into_iter()
+                .map(|(name, value)| (name.into_owned().into(), value.into_owned()))
+                .collect(),
+            contents: self.contents.into_owned(),
+            prefer_quoted_printable: self.prefer_quoted_printable,
+            content_length: self.content_length,
+            encoder: self.encoder,
+        }
+    }
+
+    /// Returns a copy of the MIME part borrowing its contents, see
+    /// `BodyPart::borrowed`.
+    pub(crate) fn borrowed(&self, uppercase_charset: bool) -> io::Result<MimePart<'_>> {
+        Ok(MimePart {
+            headers: self
+                .headers
+                .iter()
+                .map(|(name, value)| {
+                    Ok((
+                        name.as_ref().into(),
+                        value.try_clone(name.len() + 2, uppercase_charset)?,
+                    ))
+                })
+                .collect::<io::Result<_>>()?,
+            contents: self.contents.borrowed(uppercase_charset)?,
+            prefer_quoted_printable: self.prefer_quoted_printable,
+            content_length: self.content_length,
+            encoder: self.encoder.clone(),
+        })
+    }
+
     /// Write the MIME part to a writer.
-    pub fn write_part(self, mut output: impl Write) -> io::Result<usize> {
+    pub fn write_part(self, output: impl Write) -> io::Result<usize> {
+        self.write_part_with(output, WriteOptions::default())
+    }
+
+    pub(crate) fn write_part_with(
+        self,
+        mut output: impl Write,
+        options: WriteOptions,
+    ) -> io::Result<usize> {
         let mut stack = Vec::new();
         let mut it = vec![self].into_iter();
         let mut boundary: Option<Cow<str>> = None;
@@ -230,9 +1240,34 @@ impl<'x> MimePart<'x> {
                     output.write_all(boundary.as_bytes())?;
                     output.write_all(b"\r\n")?;
                 }
+                let options = WriteOptions {
+                    content_length: part.content_length,
+                    ..options
+                };
+                if options.fold_content_lines
+                    && matches!(part.content_type(), Some(c_type) if ["text/vcard", "text/calendar"]
+                        .iter()
+                        .any(|folded| c_type.eq_ignore_ascii_case(folded)))
+                {
+                    part.contents = fold_part_contents(part.contents)?;
+                }
+                if let Some(encoder) = part.encoder.take() {
+                    if let Some(contents) = part.leaf_contents()? {
+                        write_custom_encoded(
+                            &part.headers,
+                            &contents,
+                            &mut output,
+                            encoder.as_ref(),
+                            options,
+                        )?;
+                        continue;
+                    }
+                }
                 match part.contents {
                     BodyPart::Text(text) => {
+                        let mut is_html = false;
                         let mut is_attachment = false;
+                        let mut encoding = None;
                         for (header_name, header_value) in &part.headers {
                             output.write_all(header_name.as_bytes())?;
                             output.write_all(b": ")?;
@@ -241,38 +1276,108 @@ impl<'x> MimePart<'x> {
                                     .as_content_type()
                                     .map(|v| v.is_attachment())
                                     .unwrap_or(false);
+                            } else if header_name == "Content-Type" {
+                                is_html = header_value
+                                    .as_content_type()
+                                    .is_some_and(|v| v.c_type.eq_ignore_ascii_case("text/html"));
+                            } else if header_name.eq_ignore_ascii_case("Content-Transfer-Encoding")
+                            {
+                                encoding = Some(write_encoding_header(
+                                    header_value,
+                                    &mut output,
+                                    header_name.len() + 2,
+                                    options,
+                                )?);
+                                continue;
                             }
-                            header_value.write_header(&mut output, header_name.len() + 2)?;
+                            write_header_value(
+                                header_value,
+                                &mut output,
+                                header_name.len() + 2,
+                                options,
+                            )?;
+                        }
+                        if let Some(encoding) = encoding {
+                            write_encoded(
+                                text.as_bytes(),
+                                &mut output,
+                                &encoding,
+                                !is_attachment,
+                                options,
+                            )?;
+                        } else {
+                            detect_encoding(
+                                text.as_bytes(),
+                                &mut output,
+                                !is_attachment,
+                                is_html,
+                                options,
+                            )?;
                         }
-                        detect_encoding(text.as_bytes(), &mut output, !is_attachment)?;
                     }
                     BodyPart::Binary(binary) => {
-                        let mut is_text = false;
-                        let mut is_attachment = false;
-                        for (header_name, header_value) in &part.headers {
-                            output.write_all(header_name.as_bytes())?;
+                        write_binary(
+                            &part.headers,
+                            binary.as_ref(),
+                            &mut output,
+                            part.prefer_quoted_printable,
+                            options,
+                        )?;
+                    }
+                    #[cfg(feature = "bytes")]
+                    BodyPart::Bytes(bytes) => {
+                        write_binary(
+                            &part.headers,
+                            bytes.as_ref(),
+                            &mut output,
+                            part.prefer_quoted_printable,
+                            options,
+                        )?;
+                    }
+                    BodyPart::Verbatim { headers, body } => {
+                        for (name, value) in headers {
+                            output.write_all(name.as_bytes())?;
                             output.write_all(b": ")?;
-                            if !is_text && header_name == "Content-Type" {
-                                is_text = header_value
-                                    .as_content_type()
-                                    .map(|v| v.is_text())
-                                    .unwrap_or(false);
-                            } else if !is_attachment && header_name == "Content-Disposition" {
-                                is_attachment = header_value
-                                    .as_content_type()
-                                    .map(|v| v.is_attachment())
-                                    .unwrap_or(false);
-                            }
-                            header_value.write_header(&mut output, header_name.len() + 2)?;
+                            output.write_all(value.as_bytes())?;
+                            output.write_all(b"\r\n")?;
                         }
-                        if !is_text {
-                            output.write_all(b"Content-Transfer-Encoding: base64\r\n\r\n")?;
-                            base64_encode(binary.as_ref(), &mut output, false)?;
-                        } else {
-                            detect_encoding(binary.as_ref(), &mut output, !is_attachment)?;
+                        output.write_all(b"\r\n")?;
+                        output.write_all(&body)?;
+                    }
+                    #[cfg(feature = "tokio")]
+                    BodyPart::Async(source) => {
+                        if source.reader.is_some() || source.marker.is_empty() {
+                            return Err(Error::AsyncAttachment.into());
+                        }
+                        for (header_name, header_value) in &part.headers {
+                            if !header_name.eq_ignore_ascii_case("Content-Transfer-Encoding") {
+                                output.write_all(header_name.as_bytes())?;
+                                output.write_all(b": ")?;
+                                write_header_value(
+                                    header_value,
+                                    &mut output,
+                                    header_name.len() + 2,
+                                    options,
+                                )?;
+                            }
                         }
+                        output.write_all(b"Content-Transfer-Encoding: base64\r\n\r\n")?;
+                        output.write_all(source.marker.as_bytes())?;
+                    }
+                    BodyPart::File(path) => {
+                        let contents = std::fs::read(&path)?;
+                        write_binary(
+                            &part.headers,
+                            &contents,
+                            &mut output,
+                            part.prefer_quoted_printable,
+                            options,
+                        )?;
                     }
                     BodyPart::Multipart(parts) => {
+                        if parts.is_empty() {
+                            return Err(Error::EmptyMultipart.into());
+                        }
                         if boundary.is_some() {
                             stack.push((it, boundary));
                         }
@@ -286,26 +1391,13 @@ impl<'x> MimePart<'x> {
                                     {
                                         entry.insert(make_boundary().into());
                                     }
-                                    ct.write_header(&mut output, 14)?;
+                                    write_header_value(&ct, &mut output, 14, options)?;
                                     ct.attributes.remove("boundary")
                                 }
                                 HeaderType::Raw(raw) => {
-                                    if let Some(pos) = raw.raw.find("boundary=\"") {
-                                        if let Some(boundary) = raw.raw[pos..].split('"').nth(1) {
-                                            Some(boundary.to_string().into())
-                                        } else {
-                                            Some(make_boundary().into())
-                                        }
-                                    } else {
-                                        let boundary = make_boundary();
-                                        output.write_all(raw.raw.as_bytes())?;
-                                        output.write_all(b"; boundary=\"")?;
-                                        output.write_all(boundary.as_bytes())?;
-                                        output.write_all(b"\"\r\n")?;
-                                        Some(boundary.into())
-                                    }
+                                    Some(write_raw_multipart_type(&raw.raw, &mut output)?.into())
                                 }
-                                _ => panic!("Unsupported Content-Type header value."),
+                                _ => return Err(Error::UnsupportedContentType.into()),
                             }
                         } else {
                             let boundary = make_boundary();
@@ -318,7 +1410,12 @@ impl<'x> MimePart<'x> {
                         for (header_name, header_value) in part.headers {
                             output.write_all(header_name.as_bytes())?;
                             output.write_all(b": ")?;
-                            header_value.write_header(&mut output, header_name.len() + 2)?;
+                            write_header_value(
+                                &header_value,
+                                &mut output,
+                                header_name.len() + 2,
+                                options,
+                            )?;
                         }
                         output.write_all(b"\r\n")?;
                         it = parts.into_iter();
@@ -341,31 +1438,320 @@ impl<'x> MimePart<'x> {
     }
 }
 
-fn detect_encoding(input: &[u8], mut output: impl Write, is_body: bool) -> io::Result<()> {
-    match get_encoding_type(input, false, is_body) {
+/// Writes a header value, failing if any of its lines is too long, and
+/// uppercasing the charset label of encoded-words when requested.
+pub(crate) fn write_header_value(
+    header: &dyn Header,
+    output: &mut dyn Write,
+    bytes_written: usize,
+    options: WriteOptions,
+) -> io::Result<usize> {
+    write_header_limited(
+        header,
+        output,
+        bytes_written,
+        HeaderOptions {
+            uppercase_charset: options.uppercase_charset_labels,
+        },
+    )
+}
+
+/// Writes the value of a Content-Transfer-Encoding header set by the user,
+/// whatever its header type, and returns the encoding it names.
+fn write_encoding_header(
+    header: &dyn Header,
+    output: &mut dyn Write,
+    bytes_written: usize,
+    options: WriteOptions,
+) -> io::Result<String> {
+    let mut value = Vec::new();
+    write_header_value(header, &mut value, bytes_written, options)?;
+    output.write_all(&value)?;
+    Ok(String::from_utf8_lossy(&value)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// Returns the position of the value of a parameter in a raw Content-Type,
+/// comparing the `;` separated parameter names case-insensitively.
+fn find_parameter(raw: &str, name: &str) -> Option<usize> {
+    let mut in_quotes = false;
+    let mut is_escaped = false;
+    let mut param_starts = Vec::new();
+    for (pos, ch) in raw.char_indices() {
+        match ch {
+            _ if is_escaped => is_escaped = false,
+            '\\' if in_quotes => is_escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => param_starts.push(pos + 1),
+            _ => (),
+        }
+    }
+    param_starts.into_iter().find_map(|start| {
+        let (param_name, _) = raw[start..].split_once('=')?;
+        if param_name.trim().eq_ignore_ascii_case(name) {
+            Some(start + param_name.len() + 1)
+        } else {
+            None
+        }
+    })
+}
+
+/// Writes a raw multipart Content-Type, making sure that its boundary
+/// parameter is quoted or adding one when missing. Returns the boundary.
+fn write_raw_multipart_type(raw: &str, mut output: impl Write) -> io::Result<String> {
+    let raw = raw.trim_end();
+    if let Some(value_pos) = find_parameter(raw, "boundary") {
+        let value = &raw[value_pos..];
+        if let Some(value) = value.strip_prefix('"') {
+            if let Some(boundary) = value.split('"').next().filter(|_| value.contains('"')) {
+                output.write_all(raw.as_bytes())?;
+                output.write_all(b"\r\n")?;
+                return Ok(boundary.to_string());
+            }
+        } else {
+            let boundary_len = value
+                .find(|ch: char| ch == ';' || ch.is_ascii_whitespace())
+                .unwrap_or(value.len());
+            if boundary_len > 0 {
+                output.write_all(&raw.as_bytes()[..value_pos])?;
+                output.write_all(b"\"")?;
+                output.write_all(&value.as_bytes()[..boundary_len])?;
+                output.write_all(b"\"")?;
+                output.write_all(&value.as_bytes()[boundary_len..])?;
+                output.write_all(b"\r\n")?;
+                return Ok(value[..boundary_len].to_string());
+            }
+        }
+        return Err(Error::InvalidBoundary.into());
+    }
+
+    let boundary = make_boundary();
+    output.write_all(raw.as_bytes())?;
+    output.write_all(b"; boundary=\"")?;
+    output.write_all(boundary.as_bytes())?;
+    output.write_all(b"\"\r\n")?;
+    Ok(boundary)
+}
+
+fn write_binary(
+    headers: &BTreeMap<Cow<str>, HeaderType>,
+    contents: &[u8],
+    mut output: impl Write,
+    prefer_quoted_printable: bool,
+    options: WriteOptions,
+) -> io::Result<()> {
+    let mut is_text = false;
+    let mut is_html = false;
+    let mut is_attachment = false;
+    let mut encoding = None;
+    for (header_name, header_value) in headers {
+        output.write_all(header_name.as_bytes())?;
+        output.write_all(b": ")?;
+        if header_name.eq_ignore_ascii_case("Content-Transfer-Encoding") {
+            encoding = Some(write_encoding_header(
+                header_value,
+                &mut output,
+                header_name.len() + 2,
+                options,
+            )?);
+            continue;
+        } else if !is_text && header_name == "Content-Type" {
+            is_text = header_value
+                .as_content_type()
+                .map(|v| v.is_text())
+                .unwrap_or(false);
+            is_html = header_value
+                .as_content_type()
+                .is_some_and(|v| v.c_type.eq_ignore_ascii_case("text/html"));
+        } else if !is_attachment && header_name == "Content-Disposition" {
+            is_attachment = header_value
+                .as_content_type()
+                .map(|v| v.is_attachment())
+                .unwrap_or(false);
+        }
+        write_header_value(header_value, &mut output, header_name.len() + 2, options)?;
+    }
+    if let Some(encoding) = encoding {
+        write_encoded(
+            contents,
+            &mut output,
+            &encoding,
+            is_text && !is_attachment,
+            options,
+        )
+    } else if is_text {
+        detect_encoding(contents, &mut output, !is_attachment, is_html, options)
+    } else if prefer_quoted_printable {
+        // Line endings in binary parts are encoded, so that the
+        // contents are preserved exactly.
+        detect_encoding(contents, &mut output, false, false, options)
+    } else {
+        write_content_length(contents, &mut output, false, options)?;
+        output.write_all(b"Content-Transfer-Encoding: base64\r\n\r\n")?;
+        base64_encode(contents, &mut output, false)?;
+        Ok(())
+    }
+}
+
+/// Writes the contents with the smallest suitable encoding. Mostly ASCII
+/// HTML, that is without NUL bytes and with at most a quarter of the bytes
+/// outside of ASCII, is encoded as quoted-printable even if base64 would be
+/// smaller, so that tags and `cid:` references can be read in the raw message.
+fn detect_encoding(
+    input: &[u8],
+    mut output: impl Write,
+    is_body: bool,
+    is_html: bool,
+    options: WriteOptions,
+) -> io::Result<()> {
+    let encoding = match get_encoding_type(input, false, is_body) {
+        EncodingType::None if is_body && options.escape_from && has_from_line(input) => {
+            EncodingType::QuotedPrintable(true)
+        }
+        EncodingType::Base64
+            if is_html
+                && !input.contains(&0)
+                && input.iter().filter(|&&ch| ch >= 127).count() * 4 <= input.len() =>
+        {
+            EncodingType::QuotedPrintable(false)
+        }
+        encoding => encoding,
+    };
+    write_content_length(
+        input,
+        &mut output,
+        match encoding {
+            EncodingType::Base64 => false,
+            EncodingType::QuotedPrintable(_) => is_body,
+            EncodingType::None => is_body && !options.assume_crlf,
+        },
+        options,
+    )?;
+    match encoding {
         EncodingType::Base64 => {
             output.write_all(b"Content-Transfer-Encoding: base64\r\n\r\n")?;
             base64_encode(input, &mut output, false)?;
         }
         EncodingType::QuotedPrintable(_) => {
             output.write_all(b"Content-Transfer-Encoding: quoted-printable\r\n\r\n")?;
-            quoted_printable_encode(input, &mut output, false, is_body)?;
+            quoted_printable_encode_with(
+                input,
+                &mut output,
+                false,
+                is_body,
+                is_body && options.escape_from,
+            )?;
         }
         EncodingType::None => {
             output.write_all(b"Content-Transfer-Encoding: 7bit\r\n\r\n")?;
-            if is_body {
-                let mut prev_ch = 0;
-                for ch in input {
-                    if *ch == b'\n' && prev_ch != b'\r' {
-                        output.write_all(b"\r")?;
-                    }
-                    output.write_all(&[*ch])?;
-                    prev_ch = *ch;
-                }
-            } else {
-                output.write_all(input)?;
+            write_unencoded(input, &mut output, is_body && !options.assume_crlf)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the headers of a part, except for any Content-Transfer-Encoding,
+/// followed by the contents encoded with a custom encoder.
+fn write_custom_encoded(
+    headers: &BTreeMap<Cow<str>, HeaderType>,
+    input: &[u8],
+    mut output: impl Write,
+    encoder: &dyn BodyEncoder,
+    options: WriteOptions,
+) -> io::Result<()> {
+    if !is_token(encoder.name()) {
+        return Err(Error::InvalidEncoderName(encoder.name().to_string()).into());
+    }
+    for (header_name, header_value) in headers {
+        if !header_name.eq_ignore_ascii_case("Content-Transfer-Encoding") {
+            output.write_all(header_name.as_bytes())?;
+            output.write_all(b": ")?;
+            write_header_value(header_value, &mut output, header_name.len() + 2, options)?;
+        }
+    }
+    write_content_length(input, &mut output, false, options)?;
+    output.write_all(b"Content-Transfer-Encoding: ")?;
+    output.write_all(encoder.name().as_bytes())?;
+    output.write_all(b"\r\n\r\n")?;
+    encoder.encode(input, &mut output)
+}
+
+/// Writes the contents using the Content-Transfer-Encoding set by the user
+/// on the MIME part, rather than an automatically detected one.
+fn write_encoded(
+    input: &[u8],
+    mut output: impl Write,
+    encoding: &str,
+    is_body: bool,
+    options: WriteOptions,
+) -> io::Result<()> {
+    let encoding = encoding.trim();
+    let is_base64 = encoding.eq_ignore_ascii_case("base64");
+    let is_quoted_printable = encoding.eq_ignore_ascii_case("quoted-printable");
+    write_content_length(
+        input,
+        &mut output,
+        !is_base64 && is_body && (is_quoted_printable || !options.assume_crlf),
+        options,
+    )?;
+    output.write_all(b"\r\n")?;
+    if is_base64 {
+        base64_encode(input, &mut output, false)?;
+    } else if is_quoted_printable {
+        quoted_printable_encode_with(
+            input,
+            &mut output,
+            false,
+            is_body,
+            is_body && options.escape_from,
+        )?;
+    } else {
+        write_unencoded(input, &mut output, is_body && !options.assume_crlf)?;
+    }
+    Ok(())
+}
+
+/// Writes a Content-Length header with the decoded size of the contents when
+/// requested, accounting for bare LFs being converted to CRLF in text bodies.
+fn write_content_length(
+    input: &[u8],
+    mut output: impl Write,
+    normalize_crlf: bool,
+    options: WriteOptions,
+) -> io::Result<()> {
+    if options.content_length {
+        let mut len = input.len();
+        if normalize_crlf {
+            len += input
+                .iter()
+                .enumerate()
+                .filter(|&(pos, &ch)| ch == b'\n' && (pos == 0 || input[pos - 1] != b'\r'))
+                .count();
+        }
+        write!(output, "Content-Length: {}\r\n", len)?;
+    }
+    Ok(())
+}
+
+/// Returns true if any line of the input starts with `From `.
+fn has_from_line(input: &[u8]) -> bool {
+    input.starts_with(b"From ") || input.windows(6).any(|window| window == b"\nFrom ")
+}
+
+fn write_unencoded(input: &[u8], mut output: impl Write, is_body: bool) -> io::Result<()> {
+    if is_body {
+        let mut prev_ch = 0;
+        for ch in input {
+            if *ch == b'\n' && prev_ch != b'\r' {
+                output.write_all(b"\r")?;
             }
+            output.write_all(&[*ch])?;
+            prev_ch = *ch;
         }
+    } else {
+        output.write_all(input)?;
     }
     Ok(())
 }
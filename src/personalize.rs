@@ -0,0 +1,254 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{borrow::Cow, collections::HashMap, io};
+
+use crate::{
+    headers::{address::Address, HeaderType},
+    mime::{BodyPart, MimePart},
+    MessageBuilder,
+};
+
+/// Substitution variables, mapping a variable name to its value.
+pub type Vars<'x> = HashMap<Cow<'x, str>, Cow<'x, str>>;
+
+/// A recipient of a personalized message, along with its substitution variables.
+#[derive(Debug)]
+pub struct Recipient<'x> {
+    pub address: Address<'x>,
+    pub vars: Vars<'x>,
+}
+
+impl<'x> Recipient<'x> {
+    /// Create a new recipient without any variables.
+    pub fn new(address: impl Into<Address<'x>>) -> Self {
+        Self {
+            address: address.into(),
+            vars: HashMap::new(),
+        }
+    }
+
+    /// Set a substitution variable for this recipient.
+    pub fn var(mut self, name: impl Into<Cow<'x, str>>, value: impl Into<Cow<'x, str>>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// Build one message per recipient from a template. For each recipient the
+/// template function is called to obtain a fresh builder, its To header is set
+/// to the recipient's address and all `{{name}}` variables are replaced with
+/// the recipient's values before the message is written.
+pub fn personalize<'x>(
+    template: impl Fn() -> MessageBuilder<'x>,
+    recipients: impl IntoIterator<Item = Recipient<'x>>,
+) -> io::Result<Vec<Vec<u8>>> {
+    recipients
+        .into_iter()
+        .map(|recipient| {
+            let mut message = template().substitute(&recipient.vars);
+            message.set_header("To", recipient.address);
+            let mut output = Vec::new();
+            message.write_to(&mut output)?;
+            Ok(output)
+        })
+        .collect()
+}
+
+/// Replace all `{{name}}` variables found in a text. Unknown variables are
+/// left unchanged.
+pub fn substitute_vars<'y>(text: &'y str, vars: &Vars) -> Cow<'y, str> {
+    substitute(text, vars, false)
+}
+
+/// Replace the variables of a text, removing any CR and LF characters from
+/// the values when `is_header` is set so that they cannot start new headers.
+fn substitute<'y>(text: &'y str, vars: &Vars, is_header: bool) -> Cow<'y, str> {
+    if !text.contains("{{") {
+        return text.into();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after
+            .find("}}")
+            .and_then(|end| vars.get(after[..end].trim()).map(|value| (end, value)))
+        {
+            Some((end, value)) => {
+                if is_header {
+                    result.extend(value.chars().filter(|&ch| ch != '\r' && ch != '\n'));
+                } else {
+                    result.push_str(value);
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                result.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result.into()
+}
+
+/// Replace the variables found in a header value, if it contains text or
+/// addresses with display names.
+pub(crate) fn substitute_header(value: &mut HeaderType, vars: &Vars) {
+    match value {
+        HeaderType::Text(text) => {
+            if let Cow::Owned(result) = substitute(&text.text, vars, true) {
+                text.text = result.into();
+            }
+        }
+        HeaderType::Raw(raw) => {
+            if let Cow::Owned(result) = substitute(&raw.raw, vars, true) {
+                raw.raw = result.into();
+            }
+        }
+        HeaderType::Address(address) => substitute_address(address, vars),
+        _ => (),
+    }
+}
+
+/// Replace the variables found in the display names of an address.
+fn substitute_address(address: &mut Address, vars: &Vars) {
+    let name = match address {
+        Address::Address(address) => &mut address.name,
+        Address::Group(group) => {
+            for address in &mut group.addresses {
+                substitute_address(address, vars);
+            }
+            &mut group.name
+        }
+        Address::List(list) => {
+            for address in list {
+                substitute_address(address, vars);
+            }
+            return;
+        }
+    };
+    if let Some(name) = name {
+        if let Cow::Owned(result) = substitute(name, vars, true) {
+            *name = result.into();
+        }
+    }
+}
+
+/// Replace the variables found in the headers and text contents of a MIME part.
+pub(crate) fn substitute_part(part: &mut MimePart, vars: &Vars) {
+    for value in part.headers.values_mut() {
+        substitute_header(value, vars);
+    }
+    match &mut part.contents {
+        BodyPart::Text(text) => {
+            if let Cow::Owned(result) = substitute_vars(text, vars) {
+                *text = result.into();
+            }
+        }
+        BodyPart::Multipart(parts) => {
+            for part in parts {
+                substitute_part(part, vars);
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mail_parser::{HeaderValue, Message};
+
+    use crate::MessageBuilder;
+
+    use super::{personalize, Recipient};
+
+    #[test]
+    fn personalize_messages() {
+        let messages = personalize(
+            || {
+                let mut message = MessageBuilder::new();
+                message.from(("Acme for {{name}}", "news@acme.com"));
+                message.subject("Hello, {{name}}!");
+                message.header("X-Code", crate::headers::raw::Raw::new("{{ code }}"));
+                message.text_body("Dear {{name}}, your code is {{ code }}. {{unknown}}");
+                message.html_body("<p>Dear {{name}}</p>");
+                message
+            },
+            vec![
+                Recipient::new(("Jane Doe", "jane@example.com"))
+                    .var("name", "Jane")
+                    .var("code", "1234"),
+                Recipient::new("john@example.com")
+                    .var("name", "Jöhn")
+                    .var("code", "5678"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(messages.len(), 2);
+
+        for (output, name, email, code) in [
+            (&messages[0], "Jane", "jane@example.com", "1234"),
+            (&messages[1], "Jöhn", "john@example.com", "5678"),
+        ] {
+            let message = Message::parse(output).unwrap();
+            assert_eq!(message.get_subject().unwrap(), format!("Hello, {}!", name));
+            assert_eq!(
+                message.get_text_body(0).unwrap().trim_end(),
+                format!("Dear {}, your code is {}. {{{{unknown}}}}", name, code)
+            );
+            assert_eq!(
+                message.get_html_body(0).unwrap().trim_end(),
+                format!("<p>Dear {}</p>", name)
+            );
+            assert!(String::from_utf8_lossy(output).contains(&format!("<{}>", email)));
+            match message.get_from() {
+                HeaderValue::Address(address) => assert_eq!(
+                    address.name.as_deref(),
+                    Some(format!("Acme for {}", name).as_str())
+                ),
+                from => panic!("Unexpected From {:?}", from),
+            }
+        }
+
+        // Values cannot inject headers.
+        let messages = personalize(
+            || {
+                let mut message = MessageBuilder::new();
+                message.from("news@acme.com");
+                message.subject("Hello, {{name}}!");
+                message.header("X-Code", crate::headers::raw::Raw::new("{{code}}"));
+                message.text_body("Dear {{name}}");
+                message
+            },
+            vec![Recipient::new("jane@example.com")
+                .var("name", "Jane\r\nBcc: victim@example.com")
+                .var("code", "1\r\nBcc: victim@example.com")],
+        )
+        .unwrap();
+        let output = String::from_utf8_lossy(&messages[0]);
+        let headers = &output[..output.find("\r\n\r\n").unwrap()];
+        assert!(!headers.contains("\r\nBcc:"), "{}", output);
+        assert!(
+            output.contains("X-Code: 1Bcc: victim@example.com\r\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("Dear Jane\r\nBcc: victim@example.com"),
+            "{}",
+            output
+        );
+    }
+}
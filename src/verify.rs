@@ -0,0 +1,327 @@
+/*
+ * Copyright Stalwart Labs, Minter Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! Compliance checks over serialized messages.
+
+use std::fmt;
+
+/// Maximum length of a line, excluding the CRLF, as defined in RFC5322.
+const MAX_LINE_OCTETS: usize = 998;
+
+/// A compliance issue found in a serialized message. Line numbers start
+/// at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Violation {
+    /// A line is longer than 998 octets, excluding the CRLF.
+    LineTooLong { line: usize, length: usize },
+    /// A line contains a CR that is not followed by a LF.
+    BareCr { line: usize },
+    /// A line is terminated by a LF that is not preceded by a CR.
+    BareLf { line: usize },
+    /// A line of a part without Content-Transfer-Encoding, or encoded as
+    /// `7bit`, contains non-ASCII octets.
+    NonAsciiIn7bit { line: usize },
+    /// The message has no MIME-Version header.
+    MissingMimeVersion,
+    /// A boundary parameter contains characters that are not allowed in a
+    /// token and is not written as a quoted-string.
+    UnquotedBoundary { line: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::LineTooLong { line, length } => {
+                write!(f, "Line {} is {} octets long.", line, length)
+            }
+            Violation::BareCr { line } => write!(f, "Line {} contains a bare CR.", line),
+            Violation::BareLf { line } => write!(f, "Line {} ends with a bare LF.", line),
+            Violation::NonAsciiIn7bit { line } => {
+                write!(f, "Line {} contains non-ASCII octets in a 7bit part.", line)
+            }
+            Violation::MissingMimeVersion => f.write_str("Missing MIME-Version header."),
+            Violation::UnquotedBoundary { line } => {
+                write!(f, "Line {} has a boundary that must be quoted.", line)
+            }
+        }
+    }
+}
+
+/// Scans a serialized message for lines over 998 octets, bare CR and LF
+/// characters, non-ASCII octets in 7bit parts, a missing MIME-Version
+/// header and boundaries that must be quoted.
+pub fn verify_output(bytes: &[u8]) -> Result<(), Vec<Violation>> {
+    let mut violations = Vec::new();
+    // Open boundaries, with whether the multipart declaring them is 7bit.
+    let mut boundaries: Vec<(Vec<u8>, bool)> = Vec::new();
+    let mut part_boundary = None;
+    let mut in_headers = true;
+    let mut is_top_level = true;
+    let mut has_mime_version = false;
+    let mut is_7bit = true;
+    let mut part_is_7bit = true;
+    let mut field: Option<(usize, Vec<u8>)> = None;
+
+    let mut pos = 0;
+    let mut line_num = 0;
+    while pos < bytes.len() {
+        line_num += 1;
+        let (line, next_pos) = match bytes[pos..].iter().position(|&ch| ch == b'\n') {
+            Some(lf_pos) => {
+                let end = pos + lf_pos;
+                if end > pos && bytes[end - 1] == b'\r' {
+                    (&bytes[pos..end - 1], end + 1)
+                } else {
+                    violations.push(Violation::BareLf { line: line_num });
+                    (&bytes[pos..end], end + 1)
+                }
+            }
+            None => (&bytes[pos..], bytes.len()),
+        };
+        pos = next_pos;
+
+        if line.len() > MAX_LINE_OCTETS {
+            violations.push(Violation::LineTooLong {
+                line: line_num,
+                length: line.len(),
+            });
+        }
+        if line.contains(&b'\r') {
+            violations.push(Violation::BareCr { line: line_num });
+        }
+
+        if in_headers {
+            if line.first().is_some_and(|&ch| ch == b' ' || ch == b'\t') {
+                if let Some((_, field)) = &mut field {
+                    field.extend_from_slice(line);
+                }
+                continue;
+            }
+            if let Some((field_line, field)) = field.take() {
+                match field_name(&field) {
+                    name if name.eq_ignore_ascii_case(b"MIME-Version") => {
+                        has_mime_version |= is_top_level;
+                    }
+                    name if name.eq_ignore_ascii_case(b"Content-Transfer-Encoding") => {
+                        part_is_7bit = field_value(&field).eq_ignore_ascii_case(b"7bit");
+                    }
+                    name if name.eq_ignore_ascii_case(b"Content-Type") => {
+                        if let Some((boundary, is_quoted)) = boundary(field_value(&field)) {
+                            if !is_quoted && !boundary.iter().all(|&ch| is_token_char(ch)) {
+                                violations.push(Violation::UnquotedBoundary { line: field_line });
+                            }
+                            part_boundary = Some(boundary);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            if line.is_empty() {
+                in_headers = false;
+                is_top_level = false;
+                is_7bit = part_is_7bit;
+                if let Some(boundary) = part_boundary.take() {
+                    boundaries.push((boundary, part_is_7bit));
+                }
+            } else {
+                field = Some((line_num, line.to_vec()));
+            }
+        } else if let Some((index, is_closing)) = delimiter(line, &boundaries) {
+            if is_closing {
+                // The epilogue follows the encoding of the multipart it
+                // belongs to.
+                is_7bit = boundaries[index].1;
+                boundaries.truncate(index);
+            } else {
+                boundaries.truncate(index + 1);
+                in_headers = true;
+                part_is_7bit = true;
+            }
+        } else if is_7bit && !line.is_ascii() {
+            violations.push(Violation::NonAsciiIn7bit { line: line_num });
+        }
+    }
+
+    if !has_mime_version {
+        violations.push(Violation::MissingMimeVersion);
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+fn field_name(field: &[u8]) -> &[u8] {
+    let name = field
+        .iter()
+        .position(|&ch| ch == b':')
+        .map_or(field, |pos| &field[..pos]);
+    name.trim_ascii()
+}
+
+fn field_value(field: &[u8]) -> &[u8] {
+    field
+        .iter()
+        .position(|&ch| ch == b':')
+        .map_or(&[][..], |pos| field[pos + 1..].trim_ascii())
+}
+
+/// Returns the boundary parameter of a Content-Type value and whether it is
+/// a quoted-string.
+fn boundary(value: &[u8]) -> Option<(Vec<u8>, bool)> {
+    value.split(|&ch| ch == b';').skip(1).find_map(|param| {
+        let param = param.trim_ascii();
+        let eq_pos = param.iter().position(|&ch| ch == b'=')?;
+        if !param[..eq_pos]
+            .trim_ascii()
+            .eq_ignore_ascii_case(b"boundary")
+        {
+            return None;
+        }
+        let value = param[eq_pos + 1..].trim_ascii();
+        Some(match value.strip_prefix(b"\"") {
+            Some(quoted) => (
+                quoted
+                    .iter()
+                    .position(|&ch| ch == b'"')
+                    .map_or(quoted, |end| &quoted[..end])
+                    .to_vec(),
+                true,
+            ),
+            None => (value.to_vec(), false),
+        })
+    })
+}
+
+/// Returns the position of the boundary a delimiter line refers to, and
+/// whether it is a closing delimiter.
+fn delimiter(line: &[u8], boundaries: &[(Vec<u8>, bool)]) -> Option<(usize, bool)> {
+    let line = line.strip_prefix(b"--")?;
+    boundaries
+        .iter()
+        .rposition(|(boundary, _)| line.starts_with(boundary))
+        .and_then(|index| {
+            let rest = &line[boundaries[index].0.len()..];
+            if rest.trim_ascii().is_empty() {
+                Some((index, false))
+            } else if rest.starts_with(b"--") && rest[2..].trim_ascii().is_empty() {
+                Some((index, true))
+            } else {
+                None
+            }
+        })
+}
+
+/// RFC2045 token characters, anything but controls, space and tspecials.
+fn is_token_char(ch: u8) -> bool {
+    ch.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?=".contains(&ch)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::MessageBuilder;
+
+    use super::{verify_output, Violation};
+
+    #[test]
+    fn verify_builder_output() {
+        let mut message = MessageBuilder::new();
+        message.from("john@example.com");
+        message.subject("Résumé");
+        message.text_body("Voilà, ".repeat(300));
+        message.html_body("<p>Hello</p>");
+        message.binary_attachment("image/png", "image.png", &[0u8, 1, 2, 0xff][..]);
+        let mut output = Vec::new();
+        message.write_to(&mut output).unwrap();
+        assert_eq!(verify_output(&output), Ok(()));
+    }
+
+    #[test]
+    fn verify_malformed_output() {
+        let mut message = concat!(
+            "From: john@example.com\r\n",
+            "Content-Type: multipart/mixed; boundary=a:b\r\n",
+            "\r\n",
+            "--a:b\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "Caf\u{e9}\r\n",
+            "bare\rcr\r\n",
+            "bare lf\n",
+            "--a:b\r\n",
+            "Content-Type: text/plain\r\n",
+            "Content-Transfer-Encoding: 8bit\r\n",
+            "\r\n",
+            "Caf\u{e9}\r\n",
+            "--a:b--\r\n",
+        )
+        .as_bytes()
+        .to_vec();
+        message.extend(std::iter::repeat_n(b'x', 999));
+        message.extend_from_slice(b"\r\n");
+
+        assert_eq!(
+            verify_output(&message),
+            Err(vec![
+                Violation::UnquotedBoundary { line: 2 },
+                Violation::NonAsciiIn7bit { line: 7 },
+                Violation::BareCr { line: 8 },
+                Violation::BareLf { line: 9 },
+                Violation::LineTooLong {
+                    line: 16,
+                    length: 999
+                },
+                Violation::MissingMimeVersion,
+            ])
+        );
+
+        // Quoted boundaries and token boundaries are accepted.
+        for content_type in [
+            "multipart/mixed; boundary=\"a:b\"",
+            "multipart/mixed; boundary=a_b",
+        ]
+        .iter()
+        {
+            let message = format!(
+                "MIME-Version: 1.0\r\nContent-Type: {}\r\n\r\n",
+                content_type
+            );
+            assert_eq!(verify_output(message.as_bytes()), Ok(()));
+        }
+
+        // Epilogues follow the encoding of the multipart they belong to.
+        let message = concat!(
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/mixed; boundary=a\r\n",
+            "Content-Transfer-Encoding: 8bit\r\n",
+            "\r\n",
+            "--a\r\n",
+            "Content-Type: multipart/alternative; boundary=b\r\n",
+            "\r\n",
+            "--b\r\n",
+            "Content-Type: text/plain\r\n",
+            "Content-Transfer-Encoding: 8bit\r\n",
+            "\r\n",
+            "Caf\u{e9}\r\n",
+            "--b--\r\n",
+            "Caf\u{e9}\r\n",
+            "--a--\r\n",
+            "Caf\u{e9}\r\n",
+        );
+        assert_eq!(
+            verify_output(message.as_bytes()),
+            Err(vec![Violation::NonAsciiIn7bit { line: 14 }])
+        );
+    }
+}